@@ -0,0 +1,106 @@
+//! Request buffering: `Vec::drain` from the front against `ByteBuffer`.
+//!
+//! Replays a chunked upload the way `parse_chunked_body` sees it: socket
+//! reads of up to 64 KiB land in the buffer, which is then eaten one chunk
+//! size line, chunk and CRLF at a time. Run with `cargo bench`.
+
+use server_proxy::utils::ByteBuffer;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const TOTAL: usize = 256 * 1024 * 1024;
+const READ_SIZE: usize = 64 * 1024;
+
+/// Minimal view of a front-consumed buffer, so both sides run the same loop.
+trait Input {
+    fn append(&mut self, bytes: &[u8]);
+    fn bytes(&self) -> &[u8];
+    fn eat(&mut self, n: usize);
+}
+
+impl Input for Vec<u8> {
+    fn append(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+    fn eat(&mut self, n: usize) {
+        self.drain(..n);
+    }
+}
+
+impl Input for ByteBuffer {
+    fn append(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+    fn eat(&mut self, n: usize) {
+        self.consume(n);
+    }
+}
+
+fn chunked_body(chunk: usize) -> Vec<u8> {
+    let mut body = Vec::with_capacity(READ_SIZE * 4);
+    while body.len() < READ_SIZE * 4 {
+        body.extend_from_slice(format!("{:x}\r\n", chunk).as_bytes());
+        body.extend(std::iter::repeat_n(b'x', chunk));
+        body.extend_from_slice(b"\r\n");
+    }
+    body
+}
+
+/// Parses chunked framing out of `input` as data arrives, returning the
+/// payload bytes seen.
+fn replay<B: Input>(mut input: B, stream: &[u8]) -> usize {
+    let mut payload = 0;
+    let mut fed = 0;
+    let mut offset = 0;
+    while fed < TOTAL {
+        let end = (offset + READ_SIZE).min(stream.len());
+        input.append(&stream[offset..end]);
+        fed += end - offset;
+        offset = if end == stream.len() { 0 } else { end };
+
+        loop {
+            let bytes = input.bytes();
+            let Some(line_end) = bytes.windows(2).take(16).position(|w| w == b"\r\n") else {
+                break;
+            };
+            let size = usize::from_str_radix(std::str::from_utf8(&bytes[..line_end]).unwrap(), 16).unwrap();
+            let framed = line_end + 2 + size + 2;
+            if bytes.len() < framed {
+                break;
+            }
+            payload += black_box(&bytes[line_end + 2..line_end + 2 + size]).len();
+            input.eat(framed);
+        }
+    }
+    payload
+}
+
+fn time<F: FnMut() -> usize>(mut run: F) -> Duration {
+    let started = Instant::now();
+    black_box(run());
+    started.elapsed()
+}
+
+fn main() {
+    println!("{} MiB of chunked body in {} KiB reads", TOTAL >> 20, READ_SIZE >> 10);
+    for chunk in [256, 4096, 16384] {
+        // Whole chunks per stream cycle, so framing survives the wrap-around
+        let stream = chunked_body(chunk);
+        let vec = time(|| replay(Vec::with_capacity(READ_SIZE * 2), &stream));
+        let ring = time(|| replay(ByteBuffer::with_capacity(READ_SIZE * 2), &stream));
+        let mib_s = |d: Duration| (TOTAL >> 20) as f64 / d.as_secs_f64();
+        println!(
+            "  {:>6} B chunks: Vec::drain {:>8.1} MiB/s, ByteBuffer {:>8.1} MiB/s ({:.1}x)",
+            chunk,
+            mib_s(vec),
+            mib_s(ring),
+            vec.as_secs_f64() / ring.as_secs_f64()
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod from_yaml;
 pub use from_yaml::*;
+pub mod to_yaml;
+pub use to_yaml::*;
 use std::{collections::BTreeMap, fmt::Debug};
 
 use crate::lexer::{LexerError, Token, Tokenizer};
@@ -0,0 +1,162 @@
+use crate::YamlError;
+use std::fmt::Write;
+
+/// The inverse of `FromYaml`: writes a value so that it parses back equal.
+pub trait ToYaml {
+    fn to_yaml(&self) -> std::result::Result<String, YamlError> {
+        let mut out = String::new();
+        self.write_block(&mut out, 0)?;
+        Ok(out)
+    }
+
+    /// Writes the value as it appears after `key: ` or inside `[...]`.
+    fn write_inline(&self, out: &mut String) -> std::result::Result<(), YamlError>;
+
+    /// Writes the value as whole lines indented by `indent` spaces.
+    fn write_block(&self, out: &mut String, indent: usize) -> std::result::Result<(), YamlError> {
+        out.push_str(&" ".repeat(indent));
+        self.write_inline(out)?;
+        out.push('\n');
+        Ok(())
+    }
+
+    /// Block values start on the line after their key.
+    fn is_block(&self) -> bool {
+        false
+    }
+}
+
+/// Writes one `key: value` line, or the key and a nested block under it.
+pub fn write_field<T: ToYaml>(
+    out: &mut String,
+    indent: usize,
+    key: &str,
+    value: &T,
+) -> std::result::Result<(), YamlError> {
+    out.push_str(&" ".repeat(indent));
+    out.push_str(key);
+    out.push(':');
+    if value.is_block() {
+        out.push('\n');
+        value.write_block(out, indent + 2)
+    } else {
+        out.push(' ');
+        value.write_inline(out)?;
+        out.push('\n');
+        Ok(())
+    }
+}
+
+/// The lexer has no escapes, so a string is wrapped in whichever quote it
+/// doesn't contain. One with both can only go out bare, which works as long
+/// as it reads back as a single identifier.
+fn write_scalar(out: &mut String, value: &str) -> std::result::Result<(), YamlError> {
+    if !value.contains('"') {
+        let _ = write!(out, "\"{}\"", value);
+    } else if !value.contains('\'') {
+        let _ = write!(out, "'{}'", value);
+    } else if value.starts_with(|c: char| c.is_alphanumeric() || "/._".contains(c))
+        && !value.contains(|c: char| c.is_whitespace() || ":#[],{}".contains(c))
+    {
+        out.push_str(value);
+    } else {
+        return Err(YamlError::Generic(format!(
+            "cannot write {:?}: it has both quote kinds and is not a plain word",
+            value
+        )));
+    }
+    Ok(())
+}
+
+impl ToYaml for String {
+    fn write_inline(&self, out: &mut String) -> std::result::Result<(), YamlError> {
+        write_scalar(out, self)
+    }
+}
+
+impl ToYaml for bool {
+    fn write_inline(&self, out: &mut String) -> std::result::Result<(), YamlError> {
+        out.push_str(if *self { "true" } else { "false" });
+        Ok(())
+    }
+}
+
+/// Scalars go in a flow list; maps get a `- ` item each.
+impl<T: ToYaml> ToYaml for Vec<T> {
+    fn write_inline(&self, out: &mut String) -> std::result::Result<(), YamlError> {
+        out.push('[');
+        for (i, item) in self.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            item.write_inline(out)?;
+        }
+        out.push(']');
+        Ok(())
+    }
+
+    fn write_block(&self, out: &mut String, indent: usize) -> std::result::Result<(), YamlError> {
+        let pad = " ".repeat(indent);
+        for item in self {
+            if !item.is_block() {
+                out.push_str(&pad);
+                out.push_str("- ");
+                item.write_inline(out)?;
+                out.push('\n');
+                continue;
+            }
+            // The item's first line carries the dash in place of its indent
+            let mut lines = String::new();
+            item.write_block(&mut lines, indent + 2)?;
+            out.push_str(&pad);
+            out.push_str("- ");
+            match lines.get(indent + 2..) {
+                Some(rest) if !rest.is_empty() => out.push_str(rest),
+                _ => out.push_str("{}\n"),
+            }
+        }
+        Ok(())
+    }
+
+    fn is_block(&self) -> bool {
+        self.first().is_some_and(|item| item.is_block())
+    }
+}
+
+impl<K, V> ToYaml for std::collections::HashMap<K, V>
+where
+    K: ToYaml + Ord,
+    V: ToYaml,
+{
+    /// A flow map with sorted keys, so the output is stable.
+    fn write_inline(&self, out: &mut String) -> std::result::Result<(), YamlError> {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        out.push('{');
+        for (i, (key, value)) in entries.into_iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            key.write_inline(out)?;
+            out.push_str(": ");
+            value.write_inline(out)?;
+        }
+        out.push('}');
+        Ok(())
+    }
+}
+
+macro_rules! impl_to_yaml_numeric {
+    ($($t:ty),*) => {
+        $(
+            impl ToYaml for $t {
+                fn write_inline(&self, out: &mut String) -> std::result::Result<(), YamlError> {
+                    let _ = write!(out, "{}", self);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_to_yaml_numeric!(u16, u32, u64, usize, i32, i64, f64);
@@ -142,8 +142,8 @@ pub fn derive_yaml_struct(input: TokenStream) -> TokenStream {
 );
 
     // 3. Generate the "Fill" logic
-    for (field, is_option, yaml_key, _) in fields {
-        if is_option {
+    for (field, is_option, yaml_key, _) in &fields {
+        if *is_option {
             generated.push_str(&format!(
                 "if let Some(v) = m.get(\"{yaml_key}\") {{
                 obj.{field} = parser::FromYaml::from_yaml_opt(Some(v), \"{field}\")?;
@@ -165,5 +165,36 @@ pub fn derive_yaml_struct(input: TokenStream) -> TokenStream {
     generated.push_str("std::result::Result::Ok(obj)");
     generated.push_str("} else { std::result::Result::Err(parser::YamlError::Generic(\"Expected a Map\".into())) } } }");
 
+    // 4. Generate ToYaml from the same fields, so the dump can't miss one
+    generated.push_str(&format!(
+        "impl parser::ToYaml for {name} {{
+        fn write_inline(&self, _out: &mut String) -> std::result::Result<(), parser::YamlError> {{
+            std::result::Result::Err(parser::YamlError::Generic(\"{name} can only be written as a block\".into()))
+        }}
+
+        fn is_block(&self) -> bool {{
+            true
+        }}
+
+        fn write_block(&self, out: &mut String, indent: usize) -> std::result::Result<(), parser::YamlError> {{
+        ",
+        name = struct_name
+    ));
+    for (field, is_option, yaml_key, _) in &fields {
+        // An unset option is left out and parses back as None
+        if *is_option {
+            generated.push_str(&format!(
+                "if let Some(v) = &self.{field} {{
+                parser::write_field(out, indent, \"{yaml_key}\", v)?;
+            }}"
+            ));
+        } else {
+            generated.push_str(&format!(
+                "parser::write_field(out, indent, \"{yaml_key}\", &self.{field})?;"
+            ));
+        }
+    }
+    generated.push_str("std::result::Result::Ok(()) } }");
+
     generated.parse().expect("Generated code was invalid")
 }
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn format_time(now: SystemTime) -> String {
@@ -14,28 +16,85 @@ pub fn format_time(now: SystemTime) -> String {
     format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second)
 }
 
+/// Severity of a log line, most severe first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!(
+                "unknown log level '{}', expected error, warn, info, debug or trace",
+                s
+            )),
+        }
+    }
+}
+
+// Everything is printed until `set_level` says otherwise
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Trace as u8);
+
+/// Drops every line less severe than `level`, process-wide.
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Sends log lines to stderr, keeping stdout for the program's own output.
+pub fn set_stderr(on: bool) {
+    TO_STDERR.store(on, Ordering::Relaxed);
+}
+
+pub fn emit(line: String) {
+    if TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
 #[macro_export]
 macro_rules! log {
-    ($level:expr, $color:expr, $($arg:tt)*) => {
-        let ts = $crate::format_time(std::time::SystemTime::now());
-        println!(
-            "[{}] \x1b[30m #|| web-server ||# \x1b[0 \x1b[{}m{}\x1b[0m: {}",
-            ts,
-            $color,
-            $level,
-            format!($($arg)*)
-        );
+    ($severity:expr, $level:expr, $color:expr, $($arg:tt)*) => {
+        if $crate::enabled($severity) {
+            let ts = $crate::format_time(std::time::SystemTime::now());
+            $crate::emit(format!(
+                "[{}] \x1b[30m #|| web-server ||# \x1b[0 \x1b[{}m{}\x1b[0m: {}",
+                ts,
+                $color,
+                $level,
+                format!($($arg)*)
+            ));
+        }
     };
 }
 
 
 #[macro_export]
-macro_rules! info { ($($arg:tt)*) => { $crate::log!("INFO ", "32", $($arg)*); }; } // Green
+macro_rules! info { ($($arg:tt)*) => { $crate::log!($crate::Level::Info, "INFO ", "32", $($arg)*); }; } // Green
 #[macro_export]
-macro_rules! warn { ($($arg:tt)*) => { $crate::log!("WARN ", "33", $($arg)*); }; } // Yellow
+macro_rules! warn { ($($arg:tt)*) => { $crate::log!($crate::Level::Warn, "WARN ", "33", $($arg)*); }; } // Yellow
 #[macro_export]
-macro_rules! errors { ($($arg:tt)*) => { $crate::log!("ERROR", "31", $($arg)*); }; } // Red
+macro_rules! errors { ($($arg:tt)*) => { $crate::log!($crate::Level::Error, "ERROR", "31", $($arg)*); }; } // Red
 #[macro_export]
-macro_rules! debug { ($($arg:tt)*) => { $crate::log!("DEBUG", "36", $($arg)*); }; } // Cyan
+macro_rules! debug { ($($arg:tt)*) => { $crate::log!($crate::Level::Debug, "DEBUG", "36", $($arg)*); }; } // Cyan
 #[macro_export]
-macro_rules! trace { ($($arg:tt)*) => { $crate::log!("TRACE", "34", $($arg)*); }; } // Blue
\ No newline at end of file
+macro_rules! trace { ($($arg:tt)*) => { $crate::log!($crate::Level::Trace, "TRACE", "34", $($arg)*); }; } // Blue
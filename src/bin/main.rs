@@ -1,14 +1,82 @@
-use mio::Poll;
-use parser::FromYaml;
-use server_proxy::{config::AppConfig, error::Result, server::Server};
+use server_proxy::{
+    cli::{self, Cli, USAGE},
+    error::Result,
+    reload::Reloader,
+    server::Server,
+};
+use parser::ToYaml;
+use std::process::ExitCode;
+use std::sync::Arc;
 
-fn main() -> Result<()> {
-    let content = std::fs::read_to_string("config.yaml")?;
-    let mut config = AppConfig::from_str(&content)?;
+fn main() -> ExitCode {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if cli.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    if let Some(level) = cli.log_level {
+        proxy_log::set_level(level);
+    }
+    // Keep stdout for the verdict or the dump
+    proxy_log::set_stderr(cli.test || cli.dump_config);
+
+    let result = if cli.test {
+        test_config(&cli)
+    } else if cli.dump_config {
+        dump_config(&cli)
+    } else {
+        run(cli)
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// `--test`: unlike startup, a skipped server block is a failure.
+fn test_config(cli: &Cli) -> Result<()> {
+    let mut config = cli::read_config(&cli.config)?;
+    let blocks = config.servers.len();
     config.validate()?;
-    config.display_config();
+    let skipped = blocks - config.servers.len();
+    if skipped > 0 {
+        return Err(format!(
+            "{}: {} of {} server blocks are invalid",
+            cli.config.display(),
+            skipped,
+            blocks
+        )
+        .into());
+    }
+    println!("{}: configuration is valid", cli.config.display());
+    Ok(())
+}
+
+/// `--dump-config`: what the server would run, as YAML that parses back to it.
+fn dump_config(cli: &Cli) -> Result<()> {
+    let config = cli::load_config(&cli.config)?;
+    print!("{}", config.to_yaml()?);
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let config = cli::load_config(&cli.config)?;
+    if !cli.quiet {
+        config.display_config();
+    }
 
-    let poll = Poll::new()?;
-    let mut server = Server::new(config, &poll)?;
-    server.run(poll)
+    let path = cli.config;
+    let reloader = Reloader::new(config.workers, move || {
+        cli::load_config(&path).map_err(|e| e.to_string())
+    });
+    Server::serve(config, Some(Arc::new(reloader)))
 }
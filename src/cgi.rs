@@ -5,11 +5,15 @@ pub enum CgiParsingState {
     ReadHeaders,
     StreamBody,
     StreamBodyChuncked,
+    /// Headers were sent for a HEAD request, anything after them is dropped.
+    DiscardBody,
 }
 
-pub fn parse_cgi_headers(bytes: &[u8], session: &mut Session) -> (u16, Vec<(String, String)>) {
-    let mut status = 200;
-    let mut headers = Vec::new();
+/// Splits the CGI header block into the status and the response fields.
+/// Repeated fields such as `Set-Cookie` are kept as separate lines.
+pub fn parse_cgi_headers(bytes: &[u8], session: &mut Session) -> (StatusCode, HeaderMap) {
+    let mut status = StatusCode::OK;
+    let mut headers = HeaderMap::new();
     let content = String::from_utf8_lossy(bytes);
 
     for line in content.lines() {
@@ -22,7 +26,8 @@ pub fn parse_cgi_headers(bytes: &[u8], session: &mut Session) -> (u16, Vec<(Stri
                     .split_whitespace()
                     .next()
                     .and_then(|s| s.parse().ok())
-                    .unwrap_or(200);
+                    .and_then(StatusCode::new)
+                    .unwrap_or(StatusCode::BAD_GATEWAY);
             } else if key == "x-session-update" {
                 if let Some((s_key, s_val)) = val.split_once('=') {
                     session
@@ -30,14 +35,14 @@ pub fn parse_cgi_headers(bytes: &[u8], session: &mut Session) -> (u16, Vec<(Stri
                         .insert(s_key.trim().to_string(), s_val.trim().to_string());
                 }
             } else {
-                headers.push((key, val));
+                headers.append(&key, val);
             }
         }
     }
     (status, headers)
 }
 
-pub fn parse_cgi_output(raw_output: &[u8]) -> (u16, Vec<(String, String)>, Vec<u8>) {
+pub fn parse_cgi_output(raw_output: &[u8]) -> (StatusCode, Vec<(String, String)>, Vec<u8>) {
     let mut header_end = 0;
     if let Some(pos) = find_subsequence(raw_output, b"\r\n\r\n", 0) {
         header_end = pos;
@@ -46,7 +51,7 @@ pub fn parse_cgi_output(raw_output: &[u8]) -> (u16, Vec<(String, String)>, Vec<u
     let header_section = String::from_utf8_lossy(&raw_output[..header_end]);
     let body = raw_output[header_end + 4..].to_vec();
 
-    let mut status_code = 200;
+    let mut status_code = StatusCode::OK;
     let mut headers = Vec::new();
 
     for line in header_section.lines() {
@@ -60,7 +65,8 @@ pub fn parse_cgi_output(raw_output: &[u8]) -> (u16, Vec<(String, String)>, Vec<u
                     .split_whitespace()
                     .next()
                     .and_then(|s| s.parse().ok())
-                    .unwrap_or(200);
+                    .and_then(StatusCode::new)
+                    .unwrap_or(StatusCode::BAD_GATEWAY);
             } else {
                 headers.push((key, value));
             }
@@ -77,82 +83,129 @@ pub fn handle_cgi_event(
     cgi_token: Token,
     client_token: Token,
     conn: &mut HttpConnection,
-    cgi_to_client: &mut HashMap<Token, Token>,
+    tokens: &mut TokenSlab,
 ) -> Result<()> {
     // 1. Handle reading from the Script (Stdout)
-    if event.is_readable() && Some(cgi_token) == conn.cgi_out_token {
-        read_cgi_output(conn, session_store, poll, client_token)?;
+    if event.is_readable() && Some(cgi_token) == conn.cgi_out_token && !conn.backend_paused {
+        read_cgi_output(conn, session_store, poll, client_token, tokens)?;
     }
 
     // 2. Handle writing to the Script (Stdin)
     if event.is_writable() && Some(cgi_token) == conn.cgi_in_token {
-        write_to_cgi_stdin(conn, poll, client_token)?;
+        write_to_cgi_stdin(conn, poll, client_token, tokens)?;
     }
 
     // 3. Monitor the Process lifecycle
-    monitor_cgi_process(conn, poll, client_token, cgi_to_client)?;
+    monitor_cgi_process(conn, session_store, poll, client_token, tokens)?;
 
     Ok(())
 }
 
+/// Streams script output into the write buffer until the pipe is empty or
+/// the client falls `write_buffer_high` bytes behind, which pauses the pipe.
 fn read_cgi_output(
     conn: &mut HttpConnection,
     session_store: &mut SessionStore,
     poll: &Poll,
     client_token: Token,
+    tokens: &mut TokenSlab,
 ) -> Result<()> {
+    let (high, _) = conn.write_buffer_marks();
+    let mut pause = false;
+    let mut eof = false;
+
     if let ActiveAction::Cgi {
         out_stream,
+        in_stream,
         parse_state,
         header_buf,
+        head_only,
         ..
     } = &mut conn.action
     {
         let mut buf = [0u8; 4096];
-        match out_stream.read(&mut buf) {
-            Ok(0) => {
-                if *parse_state == CgiParsingState::StreamBodyChuncked {
-                    conn.write_buffer.extend_from_slice(b"0\r\n\r\n");
-                    poll.registry().reregister(
-                        &mut conn.stream,
-                        client_token,
-                        Interest::READABLE | Interest::WRITABLE,
-                    )?;
+        loop {
+            match out_stream.read(&mut buf) {
+                Ok(0) => {
+                    if *parse_state == CgiParsingState::StreamBodyChuncked {
+                        conn.write_buffer.extend_from_slice(b"0\r\n\r\n");
+                    }
+                    if let Some(pipe) = in_stream {
+                        let _ = poll.registry().deregister(pipe);
+                    }
+                    eof = true;
+                    break;
                 }
-                conn.cgi_out_token = None;
-                conn.cgi_in_token = None;
-            }
-            Ok(n) => {
-                if let Some(session_id) = &conn.session_id
-                    && let Some(session) = session_store.sessions.get_mut(session_id)
-                {
-                    process_cgi_stdout(
-                        parse_state,
-                        header_buf,
-                        &mut conn.write_buffer,
-                        &buf[..n],
-                        session,
-                    )?;
+                Ok(n) => {
+                    let mut table = session_store.lock();
+                    if let Some(session_id) = &conn.session_id
+                        && let Some(session) = table.sessions.get_mut(session_id)
+                    {
+                        process_cgi_stdout(
+                            parse_state,
+                            header_buf,
+                            &mut conn.write_buffer,
+                            &buf[..n],
+                            session,
+                            ClientEnd {
+                                extra_headers: &conn.extra_headers,
+                                head_only: *head_only,
+                                chunked_ok: conn.request.accepts_chunked(),
+                                keep_alive: &mut conn.keep_alive,
+                            },
+                        )?;
+                    }
+                    drop(table);
+
+                    if conn.write_buffer.len() >= high {
+                        pause = true;
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    conn.closed = true;
+                    break;
                 }
-
-                poll.registry().reregister(
-                    &mut conn.stream,
-                    client_token,
-                    Interest::READABLE | Interest::WRITABLE,
-                )?;
             }
-            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
-            Err(_) => conn.closed = true,
         }
+
+        poll.registry().reregister(
+            &mut conn.stream,
+            client_token,
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+    }
+
+    if eof {
+        // Both pipes are done with; `next_deadline` polls for the exit
+        if let ActiveAction::Cgi {
+            ref mut output_done,
+            ..
+        } = conn.action
+        {
+            *output_done = Some(Instant::now());
+        }
+        cleanup_cgi(tokens, conn);
+    } else if pause {
+        conn.pause_backend(poll);
     }
     Ok(())
 }
 
-fn write_to_cgi_stdin(conn: &mut HttpConnection, poll: &Poll, client_token: Token) -> Result<()> {
+fn write_to_cgi_stdin(
+    conn: &mut HttpConnection,
+    poll: &Poll,
+    client_token: Token,
+    tokens: &mut TokenSlab,
+) -> Result<()> {
     if conn.cgi_buffer.is_empty() {
         return Ok(());
     }
 
+    let mut sent_all = false;
+
     if let ActiveAction::Cgi {
         ref mut in_stream, ..
     } = conn.action
@@ -160,7 +213,7 @@ fn write_to_cgi_stdin(conn: &mut HttpConnection, poll: &Poll, client_token: Toke
     {
         match pipe.write(&conn.cgi_buffer) {
             Ok(n) => {
-                conn.cgi_buffer.drain(..n);
+                conn.cgi_buffer.consume(n);
 
                 // Update client interest if buffer is clearing
                 if conn.cgi_buffer.len() < 65536 {
@@ -172,66 +225,77 @@ fn write_to_cgi_stdin(conn: &mut HttpConnection, poll: &Poll, client_token: Toke
                 }
 
                 // DROP THE PIPE: If everything is sent, we must close stdin
-                if conn.body_remaining == 0 && conn.cgi_buffer.is_empty() {
-                    in_stream.take();
-                    conn.cgi_in_token = None;
-                    trace!("CGI stdin pipe closed (EOF sent)");
-                }
+                sent_all = conn.body_remaining == 0 && conn.cgi_buffer.is_empty();
             }
             Err(e) if e.kind() == ErrorKind::WouldBlock => {}
             Err(_) => conn.closed = true,
         }
     }
+    if sent_all {
+        close_cgi_stdin(tokens, conn);
+        trace!("CGI stdin pipe closed (EOF sent)");
+    }
     Ok(())
 }
 
-fn monitor_cgi_process(
+/// Finishes the exchange once the script has exited, after collecting the
+/// output it left in the pipe.
+pub fn monitor_cgi_process(
     conn: &mut HttpConnection,
+    session_store: &mut SessionStore,
     poll: &Poll,
     client_token: Token,
-    cgi_to_client: &mut HashMap<Token, Token>,
+    tokens: &mut TokenSlab,
 ) -> Result<()> {
-    if let ActiveAction::Cgi {
-        ref mut child,
-        ref parse_state,
-        ref mut in_stream,
-        ..
-    } = conn.action
-    {
-        match child.try_wait() {
-            Ok(Some(status)) => {
-                // Handle premature exit
-                if !status.success() && parse_state == &CgiParsingState::ReadHeaders {
-                    handle_error(&mut conn.response, 505, conn.s_cfg.as_ref());
-                    conn.write_buffer.clear();
-                    conn.write_buffer
-                        .extend_from_slice(&conn.response.to_bytes());
-                    conn.closed = true;
-                }
+    let ActiveAction::Cgi { ref mut child, .. } = conn.action else {
+        return Ok(());
+    };
+    match child.try_wait() {
+        Ok(Some(status)) => {
+            // Collect what the script wrote before exiting. A client that is
+            // too far behind keeps the pipe open until it has caught up.
+            if conn.cgi_out_token.is_some() && !conn.backend_paused {
+                read_cgi_output(conn, session_store, poll, client_token, tokens)?;
+            }
+            if conn.backend_paused {
+                return Ok(());
+            }
+            let ActiveAction::Cgi {
+                ref parse_state,
+                ref in_stream,
+                ..
+            } = conn.action
+            else {
+                return Ok(());
+            };
+
+            // Handle premature exit
+            if !status.success() && parse_state == &CgiParsingState::ReadHeaders {
+                handle_error(&mut conn.response, StatusCode::BAD_GATEWAY, conn.s_cfg.as_ref());
+                conn.write_buffer.clear();
+                conn.write_buffer
+                    .extend_from_slice(&conn.response.to_bytes());
+                conn.closed = true;
+            }
 
-                // Cleanup pipes if the child died before we finished sending
-                if conn.body_remaining == 0
-                    && conn.cgi_buffer.is_empty()
-                    && let Some(pipe) = in_stream.take()
-                {
-                    info!("SUCCESS: All bytes sent. Closing Pipe.");
-                    drop(pipe);
-                    conn.cgi_in_token = None;
-                }
+            // Cleanup pipes if the child died before we finished sending
+            if conn.body_remaining == 0 && conn.cgi_buffer.is_empty() && in_stream.is_some() {
+                info!("SUCCESS: All bytes sent. Closing Pipe.");
+                close_cgi_stdin(tokens, conn);
+            }
 
-                cleanup_cgi(cgi_to_client, conn);
-                conn.action = ActiveAction::None;
+            cleanup_cgi(tokens, conn);
+            conn.action = ActiveAction::None;
 
-                // Final register to flush the error or remaining data
-                poll.registry().reregister(
-                    &mut conn.stream,
-                    client_token,
-                    Interest::READABLE | Interest::WRITABLE,
-                )?;
-            }
-            Ok(None) => {}
-            Err(_) => conn.closed = true,
+            // Final register to flush the error or remaining data
+            poll.registry().reregister(
+                &mut conn.stream,
+                client_token,
+                Interest::READABLE | Interest::WRITABLE,
+            )?;
         }
+        Ok(None) => {}
+        Err(_) => conn.closed = true,
     }
     Ok(())
 }
@@ -246,9 +310,10 @@ pub fn build_cgi_env(
     envs.insert("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string());
     envs.insert("SERVER_PROTOCOL".to_string(), "HTTP/1.1".to_string());
     envs.insert("REQUEST_METHOD".to_string(), req.method.to_string());
-    // envs.insert("QUERY_STRING".to_string(), req.query_string.clone());
-    envs.insert("PATH_INFO".to_string(), req.url.clone());
-    envs.insert("SCRIPT_NAME".to_string(), req.url.clone());
+    envs.insert("QUERY_STRING".to_string(), req.query.clone());
+    envs.insert("REQUEST_URI".to_string(), req.url.clone());
+    envs.insert("PATH_INFO".to_string(), req.path.clone());
+    envs.insert("SCRIPT_NAME".to_string(), req.path.clone());
 
     envs.insert("SERVER_NAME".to_string(), "01-SERVER".to_string());
     if let Ok(addr) = conn.stream.peer_addr() {
@@ -269,7 +334,7 @@ pub fn build_cgi_env(
     }
 
     if let Some(session_id) = &conn.session_id
-        && let Some(session) = session_store.sessions.get(session_id)
+        && let Some(session) = session_store.lock().sessions.get(session_id)
     {
         for (key, value) in &session.data {
             let env_key = format!("SESSION_{}", key.to_uppercase());
@@ -280,12 +345,32 @@ pub fn build_cgi_env(
     envs
 }
 
+/// What a streamed CGI or upstream response needs to know about the client.
+pub struct ClientEnd<'a> {
+    pub extra_headers: &'a [(String, String)],
+    pub head_only: bool,
+    /// HTTP/1.0 clients can't decode chunks
+    pub chunked_ok: bool,
+    pub keep_alive: &'a mut bool,
+}
+
+impl ClientEnd<'_> {
+    /// Marks a body without a length that the client can't take chunked:
+    /// closing the connection is the only way left to end it.
+    pub fn close_delimit(&mut self, res: &mut HttpResponse) {
+        res.set_header("connection", "close");
+        res.headers.remove("keep-alive");
+        *self.keep_alive = false;
+    }
+}
+
 pub fn process_cgi_stdout(
     parse_state: &mut CgiParsingState,
     header_buf: &mut Vec<u8>,
     write_buffer: &mut Vec<u8>,
     new_data: &[u8],
     session: &mut Session,
+    mut client: ClientEnd,
 ) -> Result<()> {
     match parse_state {
         CgiParsingState::ReadHeaders => {
@@ -301,17 +386,24 @@ pub fn process_cgi_stdout(
                 let body_start = header_buf[pos + delimiter_len..].to_vec();
 
                 let (status, cgi_headers) = parse_cgi_headers(&header_bytes, session);
-                let mut res = HttpResponse::new(status, &HttpResponse::status_text(status));
-
-                res.headers.remove("Content-Length");
-
-                for (k, v) in cgi_headers {
-                    res.set_header(&k, &v);
+                let mut res = HttpResponse::new(status);
+                res.headers = cgi_headers;
+                for (k, v) in client.extra_headers {
+                    res.set_header(k, v);
                 }
 
-                let is_chunked = !res.headers.contains_key("content-length");
+                let has_length = res.headers.contains_key("content-length");
+                if !has_length && !client.chunked_ok {
+                    client.close_delimit(&mut res);
+                }
+                let is_chunked = !has_length && client.chunked_ok;
+                // HEAD gets the framing headers GET would, just no body
                 if is_chunked {
                     res.set_header("transfer-encoding", "chunked");
+                }
+                if client.head_only {
+                    *parse_state = CgiParsingState::DiscardBody;
+                } else if is_chunked {
                     *parse_state = CgiParsingState::StreamBodyChuncked;
                 } else {
                     *parse_state = CgiParsingState::StreamBody;
@@ -319,7 +411,7 @@ pub fn process_cgi_stdout(
 
                 write_buffer.extend_from_slice(&res.to_bytes_headers_only());
 
-                if !body_start.is_empty() {
+                if !body_start.is_empty() && !client.head_only {
                     push_cgi_data(write_buffer, &body_start, is_chunked);
                 }
             }
@@ -330,11 +422,12 @@ pub fn process_cgi_stdout(
         CgiParsingState::StreamBodyChuncked => {
             push_cgi_data(write_buffer, new_data, true);
         }
+        CgiParsingState::DiscardBody => {}
     }
     Ok(())
 }
 
-fn push_cgi_data(write_buffer: &mut Vec<u8>, data: &[u8], chunked: bool) {
+pub fn push_cgi_data(write_buffer: &mut Vec<u8>, data: &[u8], chunked: bool) {
     if chunked {
         let header = format!("{:X}\r\n", data.len());
         write_buffer.extend_from_slice(header.as_bytes());
@@ -345,18 +438,32 @@ fn push_cgi_data(write_buffer: &mut Vec<u8>, data: &[u8], chunked: bool) {
     }
 }
 
-pub fn cleanup_cgi(cgi_to_client: &mut HashMap<Token, Token>, conn: &mut HttpConnection) {
+/// Sends the script EOF on stdin and gives the pipe's token back to the slab.
+fn close_cgi_stdin(tokens: &mut TokenSlab, conn: &mut HttpConnection) {
+    if let ActiveAction::Cgi {
+        ref mut in_stream, ..
+    } = conn.action
+    {
+        in_stream.take();
+    }
+    if let Some(t) = conn.cgi_in_token.take() {
+        tokens.remove(t);
+    }
+}
+
+pub fn cleanup_cgi(tokens: &mut TokenSlab, conn: &mut HttpConnection) {
     if let Some(t) = conn.cgi_out_token.take() {
-        cgi_to_client.remove(&t);
+        tokens.remove(t);
     }
     if let Some(t) = conn.cgi_in_token.take() {
-        cgi_to_client.remove(&t);
+        tokens.remove(t);
     }
+    conn.backend_paused = false;
 }
 
 pub fn force_cgi_timeout(
     conn: &mut HttpConnection,
-    cgi_to_client: &mut HashMap<Token, Token>,
+    tokens: &mut TokenSlab,
     zombie_purgatory: &mut Vec<Child>,
 ) {
     let old_action = std::mem::replace(&mut conn.action, ActiveAction::None);
@@ -378,8 +485,10 @@ pub fn force_cgi_timeout(
         if parse_state == CgiParsingState::StreamBodyChuncked {
             let end_marker = "0\r\n\r\n";
             conn.write_buffer.extend_from_slice(end_marker.as_bytes());
-        } else if let Some(s_cfg) = &conn.s_cfg {
-            handle_error(&mut conn.response, 504, Some(s_cfg));
+        } else if parse_state == CgiParsingState::ReadHeaders
+            && let Some(s_cfg) = &conn.s_cfg
+        {
+            handle_error(&mut conn.response, StatusCode::GATEWAY_TIMEOUT, Some(s_cfg));
             conn.response.set_header("Connection", "close");
             conn.write_buffer.clear();
             conn.write_buffer
@@ -387,10 +496,8 @@ pub fn force_cgi_timeout(
         }
     }
 
-    conn.cgi_in_token = None;
-    conn.cgi_out_token = None;
     conn.cgi_buffer.clear();
 
-    // 4. Clean up the global CGI map
-    cleanup_cgi(cgi_to_client, conn);
+    // Give both pipe tokens back to the slab
+    cleanup_cgi(tokens, conn);
 }
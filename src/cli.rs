@@ -0,0 +1,93 @@
+use crate::config::AppConfig;
+use crate::error::{CleanError, Result};
+use parser::FromYaml;
+use proxy_log::Level;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: server_proxy [OPTIONS]
+
+Options:
+  -c, --config <path>     Configuration file [default: config.yaml]
+  -t, --test              Check the configuration and exit; fails if any
+                          server block would be skipped
+      --dump-config       Print the configuration the server would run and exit
+      --quiet             Don't print the configuration dashboard on startup
+      --log-level <level> error, warn, info, debug or trace [default: trace]
+  -h, --help              Print this help and exit";
+
+/// Options of the server binary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub config: PathBuf,
+    pub test: bool,
+    pub dump_config: bool,
+    pub quiet: bool,
+    pub log_level: Option<Level>,
+    pub help: bool,
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        Self {
+            config: PathBuf::from("config.yaml"),
+            test: false,
+            dump_config: false,
+            quiet: false,
+            log_level: None,
+            help: false,
+        }
+    }
+}
+
+impl Cli {
+    /// Parses the arguments after the program name. Values go in the next
+    /// argument or after `=` on long options: `--log-level=warn`.
+    pub fn parse<I>(args: I) -> std::result::Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            if inline.is_some() && matches!(flag.as_str(), "--test" | "--dump-config" | "--quiet" | "--help") {
+                return Err(format!("{} takes no value", flag));
+            }
+            let mut value = |name: &str| {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs a value", name))
+            };
+
+            match flag.as_str() {
+                "-c" | "--config" => cli.config = PathBuf::from(value("--config")?),
+                "-t" | "--test" => cli.test = true,
+                "--dump-config" => cli.dump_config = true,
+                "--quiet" => cli.quiet = true,
+                "--log-level" => cli.log_level = Some(value("--log-level")?.parse()?),
+                "-h" | "--help" => cli.help = true,
+                _ => return Err(format!("unknown option '{}'", flag)),
+            }
+        }
+        Ok(cli)
+    }
+}
+
+/// Parses a configuration file without validating it.
+pub fn read_config(path: &Path) -> Result<AppConfig> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| CleanError::from(format!("{}: {}", path.display(), e)))?;
+    Ok(AppConfig::from_str(&content)?)
+}
+
+/// Parses and validates a configuration file, as the server runs it.
+pub fn load_config(path: &Path) -> Result<AppConfig> {
+    let mut config = read_config(path)?;
+    config.validate()?;
+    Ok(config)
+}
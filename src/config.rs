@@ -1,14 +1,21 @@
 use std::{
     collections::{HashMap, HashSet},
     fs::File,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::Path,
+    sync::Arc,
 };
 
 use parser_derive::YamlStruct;
 use proxy_log::{errors, warn};
 
-use crate::{error::CleanError, http::Method, router::RoutingError};
+use crate::{
+    error::CleanError,
+    http::{Method, is_token},
+    proxy::{UPSTREAM_SCHEME, UpstreamTarget},
+    router::RoutingError,
+    upstream::UpstreamPool,
+};
 
 pub const DEFAULT_CLIENT_MAX_BODY_SIZE: usize = 1024 * 1024; // 1MB
 pub const ALLOWED_REDIRECTION_CODE: [u16; 5] = [301, 302, 303, 307, 308];
@@ -19,6 +26,12 @@ pub struct RouteConfig {
     pub methods: Vec<String>,
     pub redirection: Option<String>,
     pub redirect_code: Option<u16>,
+    pub proxy_pass: Option<String>,
+    /// Address of a plain `proxy_pass` host, resolved once by `validate`.
+    #[parcast(skip)]
+    pub proxy_addr: Option<SocketAddr>,
+    #[parcast(skip)]
+    pub upstream: Option<Arc<UpstreamPool>>,
     pub root: String,
     pub default_file: String,
     pub cgi_ext: Option<String>,
@@ -26,6 +39,7 @@ pub struct RouteConfig {
     pub autoindex: bool,
     pub upload_dir: String,
     pub allowe_upload: bool,
+    pub cors: Option<CorsConfig>,
 }
 
 impl Default for RouteConfig {
@@ -39,9 +53,55 @@ impl Default for RouteConfig {
             autoindex: false,
             redirection: None,
             redirect_code: None,
+            proxy_pass: None,
+            proxy_addr: None,
+            upstream: None,
             cgi_ext: None,
             cgi_path: None,
             allowe_upload: false,
+            cors: None,
+        }
+    }
+}
+
+impl RouteConfig {
+    /// Value of the `Allow` header: the configured methods plus the ones the
+    /// server always answers on a matched route (HEAD with GET, OPTIONS).
+    pub fn allow_header(&self) -> String {
+        let mut allowed: Vec<String> = Vec::new();
+        for method in &self.methods {
+            let method = method.to_uppercase();
+            if !allowed.contains(&method) {
+                allowed.push(method);
+            }
+        }
+        if allowed.iter().any(|m| m == "GET") && !allowed.iter().any(|m| m == "HEAD") {
+            allowed.push("HEAD".to_string());
+        }
+        if !allowed.iter().any(|m| m == "OPTIONS") {
+            allowed.push("OPTIONS".to_string());
+        }
+        allowed.join(", ")
+    }
+}
+
+#[derive(Debug, Clone, YamlStruct)]
+pub struct CorsConfig {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: vec!["*".into()],
+            methods: Vec::new(),
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
         }
     }
 }
@@ -56,8 +116,44 @@ pub struct ServerConfig {
     pub server_name: String,
     pub error_pages: HashMap<u16, String>,
     pub client_max_body_size: usize,
+    /// Longest request line, answered with 414 when exceeded.
+    pub max_request_line: usize,
+    /// Longest single header line, 431 when exceeded.
+    pub max_header_size: usize,
+    /// Most header fields in one request, 431 when exceeded.
+    pub max_headers: usize,
+    /// Whole header section including the request line, 431 when exceeded.
+    pub max_header_bytes: usize,
+    /// Seconds a client gets to send a complete request head (408).
+    pub header_timeout: u64,
+    /// Slowest accepted body upload in bytes per second, 0 to disable (408).
+    pub body_min_rate: usize,
+    /// Seconds an idle keep-alive connection is held open.
+    pub keepalive_timeout: u64,
+    /// Requests served on one connection before it is closed.
+    pub keepalive_requests: usize,
+    /// Seconds a response may sit unsent because the client stopped reading.
+    pub send_timeout: u64,
+    /// Bytes of response queued for a client before reading from its CGI
+    /// script or upstream is paused.
+    pub write_buffer_high: usize,
+    /// Queued bytes the client must drain down to before reading resumes,
+    /// a quarter of `write_buffer_high` unless set.
+    pub write_buffer_low: Option<usize>,
+    /// Product name sent in the `Server` header, empty to omit the header.
+    pub server_software: String,
+    /// Whether the `Server` header also carries the version.
+    pub server_tokens: bool,
     pub routes: Vec<RouteConfig>,
     pub default_server: bool,
+    pub forward_proxy: bool,
+    /// Hosts the forward proxy may reach, empty for any. A leading `.`
+    /// (`.example.com`) also admits every subdomain.
+    pub proxy_allow_hosts: Vec<String>,
+    /// Ports absolute-form requests may be forwarded to.
+    pub proxy_allow_ports: Vec<u16>,
+    /// Ports a CONNECT tunnel may be opened to.
+    pub connect_allow_ports: Vec<u16>,
     pub root: String,
 }
 
@@ -70,8 +166,25 @@ impl Default for ServerConfig {
             server_name: "_".to_string(),
             error_pages: HashMap::new(),
             client_max_body_size: 1048576,
+            max_request_line: 8192,
+            max_header_size: 8192,
+            max_headers: 100,
+            max_header_bytes: 32768,
+            header_timeout: 10,
+            body_min_rate: 1024,
+            keepalive_timeout: 75,
+            keepalive_requests: 100,
+            send_timeout: 60,
+            write_buffer_high: 262144,
+            write_buffer_low: None,
+            server_software: env!("CARGO_PKG_NAME").to_string(),
+            server_tokens: true,
             routes: Vec::new(),
             default_server: false,
+            forward_proxy: false,
+            proxy_allow_hosts: Vec::new(),
+            proxy_allow_ports: vec![80],
+            connect_allow_ports: vec![443],
             root: "./www".to_string(),
         }
     }
@@ -85,6 +198,38 @@ impl ServerConfig {
         }
     }
 
+    /// Value of the `Server` header, `None` when it is turned off.
+    pub fn server_header(&self) -> Option<String> {
+        if self.server_software.is_empty() {
+            None
+        } else if self.server_tokens {
+            Some(format!("{}/{}", self.server_software, env!("CARGO_PKG_VERSION")))
+        } else {
+            Some(self.server_software.clone())
+        }
+    }
+
+    /// Whether the forward proxy may open a connection to `host:port`.
+    pub fn forward_allowed(&self, host: &str, port: u16, tunnel: bool) -> bool {
+        let ports = if tunnel {
+            &self.connect_allow_ports
+        } else {
+            &self.proxy_allow_ports
+        };
+        if !ports.contains(&port) {
+            return false;
+        }
+        let host = host.to_ascii_lowercase();
+        self.proxy_allow_hosts.is_empty()
+            || self.proxy_allow_hosts.iter().any(|allowed| {
+                let allowed = allowed.to_ascii_lowercase();
+                match allowed.strip_prefix('.') {
+                    Some(domain) => host == domain || host.ends_with(&allowed),
+                    None => host == allowed,
+                }
+            })
+    }
+
     pub fn find_route(&self, path: &str, method: &Method) -> Result<&RouteConfig, RoutingError> {
         let mut best_match: Option<(&String, &RouteConfig)> = None;
         for route in &self.routes {
@@ -121,13 +266,86 @@ impl ServerConfig {
     }
 }
 
-#[derive(Debug, Default, YamlStruct)]
+#[derive(Debug, Clone, PartialEq, YamlStruct)]
+pub struct UpstreamConfig {
+    pub name: String,
+    pub servers: Vec<String>,
+    pub balance: String,
+    pub health_check: String,
+    pub health_interval: u64,
+    pub health_timeout: u64,
+    pub max_fails: u32,
+    pub rise: u32,
+    /// Seconds a peer marked down by proxy errors stays out of rotation
+    /// when no `health_check` can bring it back.
+    pub fail_timeout: u64,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            servers: Vec::new(),
+            balance: "round_robin".into(),
+            health_check: String::new(),
+            health_interval: 5,
+            health_timeout: 2,
+            max_fails: 2,
+            rise: 1,
+            fail_timeout: 10,
+        }
+    }
+}
+
+#[derive(Debug, YamlStruct)]
 pub struct AppConfig {
+    /// Event loop threads sharing the listeners through `SO_REUSEPORT`.
+    pub workers: usize,
+    /// Seconds in-flight requests get to finish after SIGTERM or SIGINT.
+    pub shutdown_timeout: u64,
     pub servers: Vec<ServerConfig>,
+    pub upstreams: Vec<UpstreamConfig>,
+    #[parcast(skip)]
+    pub pools: Vec<Arc<UpstreamPool>>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            shutdown_timeout: 30,
+            servers: Vec::new(),
+            upstreams: Vec::new(),
+            pools: Vec::new(),
+        }
+    }
 }
 
 impl AppConfig {
     pub fn validate(&mut self) -> Result<(), CleanError> {
+        if self.workers == 0 {
+            return Err("workers must be at least 1.".into());
+        }
+        // Only Linux balances accepts across SO_REUSEPORT sockets
+        if cfg!(not(target_os = "linux")) && self.workers > 1 {
+            return Err("workers above 1 need SO_REUSEPORT, which is Linux-only.".into());
+        }
+
+        let mut pools: HashMap<String, Arc<UpstreamPool>> = HashMap::new();
+        for u_cfg in &self.upstreams {
+            if u_cfg.name.is_empty() {
+                return Err("Upstream block without a name.".into());
+            }
+            let pool = UpstreamPool::from_config(u_cfg)
+                .map_err(|e| CleanError::from(format!("Upstream '{}': {}", u_cfg.name, e)))?;
+            if pools.insert(u_cfg.name.clone(), Arc::new(pool)).is_some() {
+                return Err(CleanError::from(format!(
+                    "Duplicate upstream name: {}",
+                    u_cfg.name
+                )));
+            }
+        }
+
         let mut virtual_hosts = HashSet::new();
         let mut default_servers_per_port = HashMap::new();
         let mut valid_servers = Vec::new();
@@ -174,6 +392,56 @@ impl AppConfig {
                 }
             }
 
+            // header limits
+            if s_cfg.max_request_line == 0
+                || s_cfg.max_header_size == 0
+                || s_cfg.max_headers == 0
+                || s_cfg.max_header_bytes == 0
+            {
+                errors!(
+                    "Server '{}': header limits must be greater than 0.",
+                    s_cfg.server_name
+                );
+                is_valid = false;
+            }
+
+            // client timeouts
+            if s_cfg.header_timeout == 0 || s_cfg.keepalive_timeout == 0 || s_cfg.send_timeout == 0 {
+                errors!(
+                    "Server '{}': header, keep-alive and send timeouts must be greater than 0.",
+                    s_cfg.server_name
+                );
+                is_valid = false;
+            }
+            if s_cfg.keepalive_requests == 0 {
+                errors!(
+                    "Server '{}': keepalive_requests must be at least 1.",
+                    s_cfg.server_name
+                );
+                is_valid = false;
+            }
+
+            // backend output buffering
+            let high = s_cfg.write_buffer_high;
+            let low = *s_cfg.write_buffer_low.get_or_insert(high / 4);
+            if high == 0 || low >= high {
+                errors!(
+                    "Server '{}': write_buffer_low must be below a non-zero write_buffer_high.",
+                    s_cfg.server_name
+                );
+                is_valid = false;
+            }
+
+            // the product name goes on the wire as an RFC 9110 token
+            if !s_cfg.server_software.is_empty() && !is_token(&s_cfg.server_software) {
+                errors!(
+                    "Server '{}': server_software '{}' is not a valid token.",
+                    s_cfg.server_name,
+                    s_cfg.server_software
+                );
+                is_valid = false;
+            }
+
             // 1. IP Validation
             if sync_host_fields(&mut s_cfg).is_err() {
                 errors!("Invalid IP address format: {}", s_cfg.host_str);
@@ -249,7 +517,51 @@ impl AppConfig {
                     }
                 }
 
-                if !route.default_file.is_empty() {
+                if let Some(ref url) = route.proxy_pass {
+                    match UpstreamTarget::parse(url) {
+                        Ok(target) if url.starts_with(UPSTREAM_SCHEME) => {
+                            match pools.get(&target.host) {
+                                Some(pool) => route.upstream = Some(Arc::clone(pool)),
+                                None => {
+                                    errors!(
+                                        "Route '{}': no upstream block named '{}'",
+                                        route.path,
+                                        target.host
+                                    );
+                                    is_valid = false;
+                                    break;
+                                }
+                            }
+                        }
+                        Ok(target) => match target.resolve() {
+                            Some(addr) => route.proxy_addr = Some(addr),
+                            None if pools.contains_key(&target.host) => {
+                                errors!(
+                                    "Route '{}': upstream blocks are named with {}{}",
+                                    route.path,
+                                    UPSTREAM_SCHEME,
+                                    target.host
+                                );
+                                is_valid = false;
+                                break;
+                            }
+                            None => {
+                                errors!(
+                                    "Route '{}': cannot resolve proxy_pass host '{}'",
+                                    route.path,
+                                    target.host
+                                );
+                                is_valid = false;
+                                break;
+                            }
+                        },
+                        Err(e) => {
+                            errors!("Route '{}': {}", route.path, e);
+                            is_valid = false;
+                            break;
+                        }
+                    }
+                } else if !route.default_file.is_empty() {
                     let d_file = r_root.join(&route.default_file);
                     if !can_read(&d_file) || !d_file.is_file() {
                         errors!(
@@ -282,6 +594,26 @@ impl AppConfig {
                     }
                 }
 
+                if let Some(ref cors) = route.cors
+                    && let Some(bad) = cors.methods.iter().find(|m| m.parse::<Method>().is_err())
+                {
+                    errors!("Route '{}': Invalid CORS method '{}'", route.path, bad);
+                    is_valid = false;
+                    break;
+                }
+
+                if let Some(ref cors) = route.cors
+                    && cors.credentials
+                    && cors.origins.iter().any(|o| o == "*")
+                {
+                    errors!(
+                        "Route '{}': CORS credentials need explicit origins, not '*'",
+                        route.path
+                    );
+                    is_valid = false;
+                    break;
+                }
+
                 // Check methods
                 for method in &route.methods {
                     match method.parse::<Method>() {
@@ -310,14 +642,40 @@ impl AppConfig {
             return Err("Zero valid server blocks found in configuration.".into());
         }
         self.servers = valid_servers;
+        self.pools = pools.into_values().collect();
         Ok(())
     }
 
+    /// Takes over the running pool of every upstream block that didn't
+    /// change, in the routes as well, so a reload keeps its peer state.
+    pub fn keep_pools(&mut self, running: &[Arc<UpstreamPool>]) {
+        for pool in &mut self.pools {
+            if let Some(kept) = running.iter().find(|r| r.config == pool.config) {
+                *pool = Arc::clone(kept);
+            }
+        }
+        for route in self.servers.iter_mut().flat_map(|s| s.routes.iter_mut()) {
+            if let Some(upstream) = &mut route.upstream
+                && let Some(kept) = self.pools.iter().find(|p| p.name == upstream.name)
+            {
+                *upstream = Arc::clone(kept);
+            }
+        }
+    }
+
     pub fn display_config(&self) {
         println!("\n\x1b[1;35m 🌐 SERVER CONFIGURATION DASHBOARD\x1b[0m");
         println!(
             "\x1b[38;5;240m ════════════════════════════════════════════════════════════════\x1b[0m"
         );
+        println!(
+            "\n  \x1b[1;34m⦿\x1b[0m \x1b[1;37mWorkers:\x1b[0m     \x1b[33m{}\x1b[0m \x1b[38;5;244mevent loop thread(s)\x1b[0m",
+            self.workers
+        );
+        println!(
+            "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mShutdown:\x1b[0m    \x1b[33m{}s\x1b[0m \x1b[38;5;244mto drain on SIGTERM/SIGINT\x1b[0m",
+            self.shutdown_timeout
+        );
 
         for (i, server) in self.servers.iter().enumerate() {
             let server_label = format!("SERVER BLOCK {:02}", i + 1);
@@ -338,6 +696,13 @@ impl AppConfig {
                 if server.default_server { "32" } else { "31" },
                 if server.default_server { "YES" } else { "NO" }
             );
+            if server.forward_proxy {
+                println!(
+                    "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mMode:\x1b[0m        \x1b[35mFORWARD PROXY\x1b[0m \x1b[38;5;244mports {:?}, CONNECT {:?}\x1b[0m",
+                    server.proxy_allow_ports,
+                    server.connect_allow_ports
+                );
+            }
 
             if server.client_max_body_size >= 1024 * 1024 {
                 println!(
@@ -351,6 +716,35 @@ impl AppConfig {
                 );
             }
 
+            println!(
+                "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mHeaders:\x1b[0m     \x1b[33m{} fields, {} B total\x1b[0m \x1b[38;5;244m(line {} B, request line {} B)\x1b[0m",
+                server.max_headers,
+                server.max_header_bytes,
+                server.max_header_size,
+                server.max_request_line
+            );
+
+            println!(
+                "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mTimeouts:\x1b[0m    \x1b[33mhead {}s, idle {}s, send {}s\x1b[0m \x1b[38;5;244m(body ≥ {} B/s)\x1b[0m",
+                server.header_timeout,
+                server.keepalive_timeout,
+                server.send_timeout,
+                server.body_min_rate
+            );
+            println!(
+                "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mKeep-Alive:\x1b[0m  \x1b[33m{} requests\x1b[0m \x1b[38;5;244mper connection\x1b[0m",
+                server.keepalive_requests
+            );
+            println!(
+                "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mBuffers:\x1b[0m     \x1b[33m{} KB\x1b[0m \x1b[38;5;244m(backend resumes below {} KB)\x1b[0m",
+                server.write_buffer_high / 1024,
+                server.write_buffer_low.unwrap_or(server.write_buffer_high / 4) / 1024
+            );
+            println!(
+                "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mServer:\x1b[0m      \x1b[33m{}\x1b[0m",
+                server.server_header().unwrap_or_else(|| "(hidden)".to_string())
+            );
+
             println!("  \x1b[1;34m⦿\x1b[0m \x1b[1;37mError Pages:\x1b[0m");
             for (code, path) in &server.error_pages {
                 println!(
@@ -421,6 +815,21 @@ impl AppConfig {
                     );
                 }
 
+                if let Some(upstream) = &route.proxy_pass {
+                    println!(
+                        "  \x1b[38;5;244m{} ├─ Proxy:\x1b[0m     \x1b[35m{}\x1b[0m",
+                        vertical_line, upstream
+                    );
+                }
+
+                if let Some(cors) = &route.cors {
+                    println!(
+                        "  \x1b[38;5;244m{} ├─ CORS:\x1b[0m      \x1b[36m{}\x1b[0m",
+                        vertical_line,
+                        cors.origins.join(" | ")
+                    );
+                }
+
                 // 6. CGI Check (Closing branch of the route)
                 if let Some(cgi) = &route.cgi_ext {
                     println!(
@@ -441,6 +850,30 @@ impl AppConfig {
             }
         }
 
+        for pool in &self.pools {
+            println!("\n  \x1b[1;37mUPSTREAM {}\x1b[0m", pool.name);
+            println!("  \x1b[38;5;244m───────────────────────────────────────────────\x1b[0m");
+            println!(
+                "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mBalance:\x1b[0m     \x1b[36m{:?}\x1b[0m",
+                pool.balance
+            );
+            for peer in &pool.peers {
+                println!("    \x1b[38;5;244m→\x1b[0m \x1b[32m{}\x1b[0m", peer.addr);
+            }
+            if pool.health_check.is_empty() {
+                println!(
+                    "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mHealth:\x1b[0m      \x1b[33mpassive, retry after {}s\x1b[0m",
+                    pool.fail_timeout.as_secs()
+                );
+            } else {
+                println!(
+                    "  \x1b[1;34m⦿\x1b[0m \x1b[1;37mHealth:\x1b[0m      \x1b[33m{} every {}s\x1b[0m",
+                    pool.health_check,
+                    pool.interval.as_secs()
+                );
+            }
+        }
+
         println!(
             "\n\x1b[38;5;240m ════════════════════════════════════════════════════════════════\x1b[0m"
         );
@@ -0,0 +1,106 @@
+use crate::prelude::*;
+
+impl CorsConfig {
+    /// The `Access-Control-Allow-Origin` value for `origin`, if it may call
+    /// this route. A wildcard policy only ever answers a literal `*`, which
+    /// browsers refuse for credentialed requests.
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.iter().any(|o| o == "*") {
+            Some("*".to_string())
+        } else if self.origins.iter().any(|o| o.eq_ignore_ascii_case(origin)) {
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Methods a preflight may ask for; defaults to the route's own methods.
+    pub fn allow_methods(&self, r_cfg: &RouteConfig) -> String {
+        if self.methods.is_empty() {
+            r_cfg.allow_header()
+        } else {
+            self.methods
+                .iter()
+                .map(|m| m.to_uppercase())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    }
+}
+
+/// A CORS preflight is an OPTIONS request announcing the real method.
+pub fn is_preflight(request: &HttpRequest) -> bool {
+    request.method == Method::OPTIONS
+        && request.headers.contains_key("origin")
+        && request
+            .headers
+            .contains_key("access-control-request-method")
+}
+
+/// Headers that decorate every response of a cross-origin request. Empty when
+/// the route has no `cors` block or the origin is not allowed.
+pub fn cors_headers(request: &HttpRequest, cors: &CorsConfig) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    let Some(origin) = request.headers.get("origin") else {
+        return headers;
+    };
+    let Some(allow_origin) = cors.allow_origin(origin) else {
+        return headers;
+    };
+
+    let wildcard = allow_origin == "*";
+    if !wildcard {
+        headers.push(("vary".to_string(), "Origin".to_string()));
+    }
+    headers.push(("access-control-allow-origin".to_string(), allow_origin));
+    if cors.credentials && !wildcard {
+        headers.push((
+            "access-control-allow-credentials".to_string(),
+            "true".to_string(),
+        ));
+    }
+    headers
+}
+
+/// Answers a preflight with 204. The `Access-Control-*` grant is only added
+/// when both the origin and the requested method are allowed, which is how
+/// the browser learns the call is refused.
+pub fn handle_preflight(
+    response: &mut HttpResponse,
+    request: &HttpRequest,
+    r_cfg: &RouteConfig,
+    cors: &CorsConfig,
+) {
+    handle_options(response, r_cfg);
+
+    let allow_methods = cors.allow_methods(r_cfg);
+    let requested = request
+        .headers
+        .get("access-control-request-method")
+        .map(|m| m.trim().to_uppercase())
+        .unwrap_or_default();
+    if !allow_methods.split(", ").any(|m| m == requested) {
+        return;
+    }
+
+    let grant = cors_headers(request, cors);
+    if grant.is_empty() {
+        return;
+    }
+    for (k, v) in &grant {
+        response.set_header(k, v);
+    }
+    response.set_header("access-control-allow-methods", &allow_methods);
+
+    if cors.headers.iter().any(|h| h == "*") {
+        if let Some(asked) = request.headers.get("access-control-request-headers") {
+            response.set_header("access-control-allow-headers", asked);
+        }
+    } else if !cors.headers.is_empty() {
+        response.set_header("access-control-allow-headers", &cors.headers.join(", "));
+    }
+
+    if let Some(max_age) = cors.max_age {
+        response.set_header("access-control-max-age", &max_age.to_string());
+    }
+}
@@ -8,14 +8,9 @@ pub fn handle_get(
 ) -> ActiveAction {
     let root = &r_cfg.root;
     let relative_path = request
-        .url
+        .path
         .strip_prefix(&r_cfg.path)
-        .unwrap_or(&request.url);
-
-    dbg!(&request.url);
-    dbg!(&r_cfg.path);
-    dbg!(&relative_path);
-
+        .unwrap_or(&request.path);
 
     let mut path = PathBuf::from(root);
     path.push(relative_path.trim_start_matches('/'));
@@ -24,10 +19,10 @@ pub fn handle_get(
         if !r_cfg.default_file.is_empty() {
             path.push(&r_cfg.default_file);
         } else if r_cfg.autoindex {
-            generate_autoindex(response, &path, &request.url);
+            generate_autoindex(response, &path, &request.path);
             return ActiveAction::None;
         } else {
-            response.set_status_code(403);
+            response.set_status_code(StatusCode::FORBIDDEN);
             response.set_body(
                 b"403 Forbidden: Directory listing denied".to_vec(),
                 "text/plain",
@@ -36,35 +31,107 @@ pub fn handle_get(
         }
     }
 
+    // Validators come from the metadata alone, so a 304 or 412 never opens the file
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            handle_io_error(response, &e, s_cfg);
+            return ActiveAction::None;
+        }
+    };
+    let validators = FileValidators::from_metadata(&metadata);
+
+    match validators.evaluate_preconditions(&request.headers) {
+        Some(StatusCode::NOT_MODIFIED) => {
+            response.set_status_code(StatusCode::NOT_MODIFIED);
+            response.headers.remove("Content-Length");
+            response.set_header("etag", &validators.etag);
+            response.set_header("last-modified", &validators.last_modified_header());
+            return ActiveAction::None;
+        }
+        Some(code) => {
+            handle_error(response, code, Some(s_cfg));
+            return ActiveAction::None;
+        }
+        None => {}
+    }
+
     match File::open(&path) {
         Ok(file) => {
-            let Ok(metadata) = file.metadata() else {
-                handle_error(response, HTTP_INTERNAL_SERVER_ERROR, Some(s_cfg));
-                return ActiveAction::None;
-            };
-            let file_size = metadata.size() as usize;
+            let file_size = metadata.size();
             let mime_type = get_mime_type(path.extension().and_then(|s| s.to_str()));
-            // conn.action = Some();
 
-            response.set_status_code(HTTP_OK);
-            response
-                .headers
-                .insert("Content-Length".to_string(), file_size.to_string());
-            response
-                .headers
-                .insert("Content-Type".to_string(), mime_type.to_string());
+            response.set_status_code(StatusCode::OK);
+            response.set_header("content-type", mime_type);
+            response.set_header("accept-ranges", "bytes");
+            response.set_header("etag", &validators.etag);
+            response.set_header("last-modified", &validators.last_modified_header());
 
-            ActiveAction::FileDownload(file, file_size)
-        }
-        Err(e) => {
-            match e.kind() {
-                std::io::ErrorKind::NotFound => handle_error(response, HTTP_NOT_FOUND, Some(s_cfg)),
-                std::io::ErrorKind::PermissionDenied => {
-                    handle_error(response, HTTP_FORBIDDEN, Some(s_cfg))
+            // Range handling is only defined for GET, and a stale If-Range
+            // asks for the whole new representation
+            let ranges = match request.headers.get("range") {
+                Some(range)
+                    if request.method == Method::GET
+                        && validators.if_range_matches(request.headers.get("if-range")) =>
+                {
+                    parse_range(range, file_size)
                 }
-                _ => handle_error(response, HTTP_INTERNAL_SERVER_ERROR, Some(s_cfg)),
+                _ => RangeRequest::Full,
             };
+
+            let (segments, content_length) = match ranges {
+                RangeRequest::Full => (full_segments(file_size), file_size as usize),
+                RangeRequest::Unsatisfiable => {
+                    handle_error(response, StatusCode::RANGE_NOT_SATISFIABLE, Some(s_cfg));
+                    response.set_header("content-range", &format!("bytes */{}", file_size));
+                    return ActiveAction::None;
+                }
+                RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+                    let (first, last) = ranges[0];
+                    let len = (last - first + 1) as usize;
+                    response.set_status_code(StatusCode::PARTIAL_CONTENT);
+                    response.set_header(
+                        "content-range",
+                        &format!("bytes {}-{}/{}", first, last, file_size),
+                    );
+                    let mut segments = VecDeque::new();
+                    segments.push_back(FileSegment::Range { offset: first, len });
+                    (segments, len)
+                }
+                RangeRequest::Partial(ranges) => {
+                    let boundary = format!(
+                        "{:016x}",
+                        SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .map(|d| d.as_nanos() as u64)
+                            .unwrap_or(0)
+                    );
+                    let (segments, len) =
+                        multipart_segments(&ranges, file_size, mime_type, &boundary);
+                    response.set_status_code(StatusCode::PARTIAL_CONTENT);
+                    response.set_header(
+                        "content-type",
+                        &format!("multipart/byteranges; boundary={}", boundary),
+                    );
+                    (segments, len)
+                }
+            };
+
+            response.set_header("content-length", &content_length.to_string());
+
+            ActiveAction::FileDownload(file, segments)
+        }
+        Err(e) => {
+            handle_io_error(response, &e, s_cfg);
             ActiveAction::None
         }
     }
 }
+
+fn handle_io_error(response: &mut HttpResponse, e: &std::io::Error, s_cfg: &Arc<ServerConfig>) {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => handle_error(response, StatusCode::NOT_FOUND, Some(s_cfg)),
+        std::io::ErrorKind::PermissionDenied => handle_error(response, StatusCode::FORBIDDEN, Some(s_cfg)),
+        _ => handle_error(response, StatusCode::INTERNAL_SERVER_ERROR, Some(s_cfg)),
+    }
+}
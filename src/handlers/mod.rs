@@ -1,7 +1,11 @@
 pub mod get_handler;
 pub mod delete_handler;
+pub mod put_handler;
+pub mod options_handler;
 pub mod upload_handler;
 
 pub use get_handler::*;
 pub use delete_handler::*;
+pub use put_handler::*;
+pub use options_handler::*;
 pub use upload_handler::*;
\ No newline at end of file
@@ -0,0 +1,8 @@
+pub use crate::prelude::*;
+
+/// `OPTIONS` on a route: no body, just the methods it accepts.
+pub fn handle_options(response: &mut HttpResponse, r_cfg: &RouteConfig) {
+    response.set_status_code(StatusCode::NO_CONTENT);
+    response.headers.remove("Content-Length");
+    response.set_header("allow", &r_cfg.allow_header());
+}
@@ -0,0 +1,85 @@
+pub use crate::prelude::*;
+
+use std::path::Component;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Prepares `PUT` of the request body to exactly the URL path under the
+/// route's upload_dir. On success the returned `Upload` streams the body
+/// into a temporary file in the upload dir, which replaces the target only
+/// once the body is complete; otherwise `response` already holds the error.
+/// Missing directories on the way are created at that point too, so a
+/// rejected or aborted body leaves nothing behind.
+pub fn handle_put(
+    response: &mut HttpResponse,
+    request: &HttpRequest,
+    r_cfg: &RouteConfig,
+    s_cfg: &Arc<ServerConfig>,
+) -> Option<Upload> {
+    let upload_base = PathBuf::from(&r_cfg.root).join(&r_cfg.upload_dir);
+
+    // e.g., /upload/reports/q3.csv -> reports/q3.csv
+    let relative_path = request.path.strip_prefix(&r_cfg.path).unwrap_or("");
+    let relative = Path::new(relative_path.trim_start_matches('/'));
+
+    // Only plain names: no "..", no absolute paths and no directory target
+    if relative_path.ends_with('/')
+        || relative.file_name().is_none()
+        || !relative.components().all(|c| matches!(c, Component::Normal(_)))
+    {
+        handle_error(response, StatusCode::FORBIDDEN, Some(s_cfg));
+        return None;
+    }
+
+    let Ok(absolute_upload_base) = upload_base.canonicalize() else {
+        handle_error(response, StatusCode::NOT_FOUND, Some(s_cfg));
+        return None;
+    };
+
+    let target_path = absolute_upload_base.join(relative);
+    let parent = target_path.parent().unwrap_or(&absolute_upload_base);
+
+    // Security: the deepest directory that already exists must resolve inside
+    // the upload dir, so a symlink can't carry the new file elsewhere
+    let mut existing = parent;
+    while !existing.exists() {
+        existing = existing.parent().unwrap_or(&absolute_upload_base);
+    }
+    match existing.canonicalize() {
+        Ok(path) if path.starts_with(&absolute_upload_base) => {}
+        _ => {
+            handle_error(response, StatusCode::FORBIDDEN, Some(s_cfg));
+            return None;
+        }
+    }
+
+    let created = !target_path.exists();
+    if !created {
+        match target_path.canonicalize() {
+            Ok(path) if path.starts_with(&absolute_upload_base) && !path.is_dir() => {}
+            _ => {
+                handle_error(response, StatusCode::FORBIDDEN, Some(s_cfg));
+                return None;
+            }
+        }
+    }
+
+    let temp_path = temp_path_for(&absolute_upload_base, &target_path);
+    if let Err(e) = File::create_new(&temp_path) {
+        match e.kind() {
+            ErrorKind::PermissionDenied => handle_error(response, StatusCode::FORBIDDEN, Some(s_cfg)),
+            _ => handle_error(response, StatusCode::INTERNAL_SERVER_ERROR, Some(s_cfg)),
+        }
+        return None;
+    }
+
+    Some(Upload::for_target(temp_path, target_path, created))
+}
+
+/// Hidden file in `base` named after `target`, unique per process and
+/// request so that concurrent `PUT`s of one file never share it.
+fn temp_path_for(base: &Path, target: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    base.join(format!(".{}.{}-{}.part", name, std::process::id(), n))
+}
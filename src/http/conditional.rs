@@ -0,0 +1,84 @@
+use crate::prelude::*;
+
+/// Validators of a static file: a strong ETag built from inode, size and
+/// mtime, and the mtime itself for `Last-Modified`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileValidators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl FileValidators {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let mtime_nanos = metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128;
+        Self {
+            etag: format!(
+                "\"{:x}-{:x}-{:x}\"",
+                metadata.ino(),
+                metadata.size(),
+                mtime_nanos
+            ),
+            last_modified: truncate_to_secs(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)),
+        }
+    }
+
+    pub fn last_modified_header(&self) -> String {
+        format_http_date(self.last_modified)
+    }
+
+    /// Evaluates the request preconditions in RFC 9110 section 13.2.2 order
+    /// for a GET or HEAD. Returns 304 or 412 when the request should stop
+    /// here, `None` to serve the file.
+    pub fn evaluate_preconditions(&self, headers: &HashMap<String, String>) -> Option<StatusCode> {
+        if let Some(if_match) = headers.get("if-match") {
+            if !etag_list_matches(if_match, &self.etag, false) {
+                return Some(StatusCode::PRECONDITION_FAILED);
+            }
+        } else if let Some(since) = headers.get("if-unmodified-since").and_then(|v| parse_http_date(v.trim()))
+            && self.last_modified > since
+        {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+
+        if let Some(if_none_match) = headers.get("if-none-match") {
+            if etag_list_matches(if_none_match, &self.etag, true) {
+                return Some(StatusCode::NOT_MODIFIED);
+            }
+        } else if let Some(since) = headers.get("if-modified-since").and_then(|v| parse_http_date(v.trim()))
+            && self.last_modified <= since
+        {
+            return Some(StatusCode::NOT_MODIFIED);
+        }
+
+        None
+    }
+
+    /// `If-Range` holds either an entity-tag, compared strongly, or a date
+    /// that must match `Last-Modified` exactly. A missing header passes.
+    pub fn if_range_matches(&self, if_range: Option<&String>) -> bool {
+        let Some(value) = if_range.map(|v| v.trim()) else {
+            return true;
+        };
+        if value.starts_with('"') || value.starts_with("W/") {
+            value == self.etag
+        } else {
+            parse_http_date(value) == Some(self.last_modified)
+        }
+    }
+}
+
+/// Matches `etag` against a header holding `*` or a comma-separated list of
+/// entity-tags. Weak comparison ignores the `W/` prefix on both sides; strong
+/// comparison never matches a weak tag.
+fn etag_list_matches(header: &str, etag: &str, weak: bool) -> bool {
+    if header.trim() == "*" {
+        return true;
+    }
+    let etag = etag.trim_start_matches("W/");
+    header.split(',').map(str::trim).any(|candidate| {
+        match candidate.strip_prefix("W/") {
+            Some(tag) => weak && tag == etag,
+            None => candidate == etag,
+        }
+    })
+}
@@ -0,0 +1,119 @@
+use crate::prelude::*;
+
+/// Longest chunk-size line we accept, extensions included.
+pub const MAX_CHUNK_LINE: usize = 1024;
+
+/// `tchar` from RFC 9110 section 5.6.2.
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
+
+/// A line handed to us without its CRLF must not hide another line ending:
+/// a bare CR or LF is read differently by other parsers on the path.
+pub fn check_line(line: &[u8]) -> core::result::Result<(), ParseError> {
+    if line.iter().any(|&b| b == b'\r' || b == b'\n') {
+        return Err(ParseError::MalformedRequestLine);
+    }
+    Ok(())
+}
+
+/// Parses one `field-name: field-value` line, rejecting the lenient forms
+/// that let two parsers disagree on where a header starts or ends.
+pub fn parse_header_line(line: &[u8]) -> core::result::Result<(String, String), ParseError> {
+    check_line(line)?;
+    // obs-fold: a continuation of the previous header
+    if line.first().is_some_and(|&b| b == b' ' || b == b'\t') {
+        return Err(ParseError::InvalidHeaderName);
+    }
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::MalformedRequestLine)?;
+    let Some((name, value)) = line.split_once(':') else {
+        return Err(ParseError::MalformedRequestLine);
+    };
+    // Also catches whitespace between the name and the colon
+    if !is_token(name) {
+        return Err(ParseError::InvalidHeaderName);
+    }
+    let value = value.trim_matches([' ', '\t']);
+    if value.bytes().any(|b| b != b'\t' && (b < 0x20 || b == 0x7f)) {
+        return Err(ParseError::InvalidHeaderValue);
+    }
+    Ok((name.to_ascii_lowercase(), value.to_string()))
+}
+
+/// Adds a header field, combining repeats the way RFC 9110 section 5.3
+/// allows. Fields that frame the message or pick the server may not repeat.
+pub fn merge_header(
+    headers: &mut HashMap<String, String>,
+    name: String,
+    value: String,
+) -> core::result::Result<(), ParseError> {
+    match headers.get_mut(&name) {
+        None => {
+            headers.insert(name, value);
+        }
+        Some(_) if name == "content-length" || name == "host" => {
+            return Err(ParseError::InvalidHeaderValue);
+        }
+        Some(existing) => {
+            let sep = if name == "cookie" { "; " } else { ", " };
+            existing.push_str(sep);
+            existing.push_str(&value);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the body length can be read only one way (RFC 9112 section 6).
+pub fn validate_framing(
+    version: &str,
+    headers: &HashMap<String, String>,
+) -> core::result::Result<(), ParseError> {
+    if let Some(cl) = headers.get("content-length")
+        && (cl.is_empty() || !cl.bytes().all(|b| b.is_ascii_digit()) || cl.parse::<usize>().is_err())
+    {
+        return Err(ParseError::InvalidHeaderValue);
+    }
+
+    let Some(te) = headers.get("transfer-encoding") else {
+        return Ok(());
+    };
+    if version != "HTTP/1.1" || headers.contains_key("content-length") {
+        return Err(ParseError::InvalidHeaderValue);
+    }
+    let codings: Vec<String> = te
+        .split(',')
+        .map(|c| c.trim_matches([' ', '\t']).to_ascii_lowercase())
+        .collect();
+    match codings.as_slice() {
+        [only] if only == "chunked" => Ok(()),
+        // chunked must be applied exactly once and last, or the length is unknowable
+        [.., last] if last == "chunked" && !codings[..codings.len() - 1].contains(last) => {
+            Err(ParseError::Error(StatusCode::NOT_IMPLEMENTED))
+        }
+        _ => Err(ParseError::InvalidHeaderValue),
+    }
+}
+
+/// Parses a `chunk-size [ chunk-ext ]` line. The size is bare hex digits,
+/// with no sign, whitespace or `0x`; extensions are checked and ignored.
+pub fn parse_chunk_size(line: &[u8]) -> core::result::Result<usize, ParseError> {
+    check_line(line)?;
+    let (size, ext) = match line.iter().position(|&b| b == b';') {
+        Some(idx) => (&line[..idx], Some(&line[idx + 1..])),
+        None => (line, None),
+    };
+    if size.is_empty() || size.len() > 16 || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(ParseError::InvalidChunkSize);
+    }
+    if let Some(ext) = ext
+        && ext.iter().any(|&b| b != b'\t' && (b < 0x20 || b == 0x7f))
+    {
+        return Err(ParseError::InvalidChunkSize);
+    }
+    let size = std::str::from_utf8(size).map_err(|_| ParseError::InvalidChunkSize)?;
+    usize::from_str_radix(size, 16).map_err(|_| ParseError::InvalidChunkSize)
+}
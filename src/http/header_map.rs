@@ -0,0 +1,79 @@
+/// Response header fields in insertion order. Names compare
+/// case-insensitively and are stored lowercase; `set` replaces every value
+/// of a field while `append` adds another line, as `Set-Cookie` needs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces all values of `name`, keeping the position of the first one.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.position(name) {
+            Some(idx) => {
+                self.entries[idx].1 = value;
+                let mut seen = 0;
+                self.entries.retain(|(k, _)| {
+                    if k.eq_ignore_ascii_case(name) {
+                        seen += 1;
+                        seen == 1
+                    } else {
+                        true
+                    }
+                });
+            }
+            None => self.entries.push((name.to_ascii_lowercase(), value)),
+        }
+    }
+
+    /// Adds a value without touching existing ones.
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.entries.push((name.to_ascii_lowercase(), value.into()));
+    }
+
+    /// First value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name).map(|idx| self.entries[idx].1.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Removes every value of `name` and returns the first.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self.position(name).map(|idx| self.entries[idx].1.clone());
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        first
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(name))
+    }
+}
@@ -17,15 +17,69 @@ pub struct HttpConnection {
     pub linger_until: Option<Instant>,
     pub cgi_in_token: Option<Token>,
     pub cgi_out_token: Option<Token>,
-    pub cgi_buffer: Vec<u8>,
+    /// Request body waiting to be written to the script's stdin.
+    pub cgi_buffer: ByteBuffer,
+    pub proxy_token: Option<Token>,
+    pub proxy_buffer: Vec<u8>,
+    /// Headers added to every response of the current request (e.g. CORS).
+    pub extra_headers: Vec<(String, String)>,
     pub session_id: Option<String>,
     pub last_activity: Instant,
+    /// When the first byte of the current request head arrived, or when a
+    /// fresh connection was accepted.
+    pub head_started: Option<Instant>,
+    /// Start of the body rate window and the bytes received within it.
+    pub body_started: Option<Instant>,
+    pub body_received: usize,
+    /// Whether the connection survives the current response.
+    pub keep_alive: bool,
+    /// Set once the worker shuts down: the request in flight is the last.
+    pub draining: bool,
+    pub requests_served: usize,
+    /// Set while output is pending (buffered or a file still streaming),
+    /// restarted by every write that makes progress.
+    pub write_blocked_since: Option<Instant>,
+    /// Cleared once sendfile(2) fails as unsupported; files then go
+    /// through `write_buffer`.
+    pub zero_copy: bool,
+    /// Set while the CGI output or upstream response is left unread because
+    /// the client is more than `write_buffer_high` bytes behind.
+    pub backend_paused: bool,
+}
+
+/// Bytes one connection holds in its buffers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferUsage {
+    pub request: usize,
+    pub write: usize,
+    pub cgi: usize,
+    pub proxy: usize,
+    /// Capacity behind all of the above plus the backend header buffer,
+    /// which is what the connection actually costs.
+    pub allocated: usize,
+}
+
+impl BufferUsage {
+    /// Bytes waiting to go out to the client or a backend, or to be parsed.
+    pub fn queued(&self) -> usize {
+        self.request + self.write + self.cgi + self.proxy
+    }
+}
+
+impl fmt::Display for BufferUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "request {} B, write {} B, cgi {} B, proxy {} B ({} B allocated)",
+            self.request, self.write, self.cgi, self.proxy, self.allocated
+        )
+    }
 }
 
 #[derive(Debug)]
 pub enum ActiveAction {
     Upload(PathBuf),
-    FileDownload(File, usize),
+    FileDownload(File, VecDeque<FileSegment>),
     Cgi {
         out_stream: mio::net::UnixStream,
         in_stream: Option<mio::net::UnixStream>,
@@ -33,6 +87,33 @@ pub enum ActiveAction {
         parse_state: CgiParsingState,
         header_buf: Vec<u8>,
         start_time: Instant,
+        head_only: bool,
+        /// When stdout hit EOF; from then on the exit is polled for.
+        output_done: Option<Instant>,
+    },
+    Proxy {
+        upstream: TcpStream,
+        parse_state: CgiParsingState,
+        header_buf: Vec<u8>,
+        connected: bool,
+        start_time: Instant,
+        peer: Option<PeerGuard>,
+        head_only: bool,
+    },
+    /// A proxy target whose address a resolver thread is looking up.
+    Resolve {
+        lookup: mio::net::UnixStream,
+        answer: Vec<u8>,
+        tunnel: bool,
+        head_only: bool,
+        start_time: Instant,
+    },
+    Tunnel {
+        upstream: TcpStream,
+        connected: bool,
+        /// The client finished sending; the upstream gets the EOF once
+        /// everything before it is flushed.
+        client_eof: bool,
     },
     Discard,
     None,
@@ -44,7 +125,7 @@ impl HttpConnection {
             stream,
             write_buffer: Vec::new(),
             request: HttpRequest::new(),
-            response: HttpResponse::new(200, "OK"),
+            response: HttpResponse::new(StatusCode::OK),
             upload_manager: None,
             config_list,
             s_cfg: None,
@@ -56,9 +137,21 @@ impl HttpConnection {
             linger_until: None,
             cgi_in_token: None,
             cgi_out_token: None,
-            cgi_buffer: Vec::new(),
+            cgi_buffer: ByteBuffer::new(),
+            proxy_token: None,
+            proxy_buffer: Vec::new(),
+            extra_headers: Vec::new(),
             session_id: None,
             last_activity: Instant::now(),
+            head_started: Some(Instant::now()),
+            body_started: None,
+            body_received: 0,
+            write_blocked_since: None,
+            keep_alive: true,
+            draining: false,
+            requests_served: 0,
+            zero_copy: true,
+            backend_paused: false,
         }
     }
 
@@ -66,6 +159,23 @@ impl HttpConnection {
         self.closed && self.write_buffer.is_empty() && self.cgi_buffer.is_empty()
     }
 
+    /// A CONNECT tunnel, established or still resolving its target. The
+    /// client's bytes after the head belong to the tunnel, not the parser.
+    pub fn is_tunnel(&self) -> bool {
+        matches!(
+            self.action,
+            ActiveAction::Tunnel { .. } | ActiveAction::Resolve { tunnel: true, .. }
+        )
+    }
+
+    /// Between requests: nothing buffered either way and no action running.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.action, ActiveAction::None)
+            && self.request.state == ParsingState::RequestLine
+            && self.request.buffer.is_empty()
+            && self.write_buffer.is_empty()
+    }
+
     pub fn resolve_config(&self) -> Arc<ServerConfig> {
         if let Some(host_header) = self.request.headers.get("host") {
             let hostname = host_header.split(':').next().unwrap_or("");
@@ -77,7 +187,12 @@ impl HttpConnection {
             }
         }
 
-        //  default_server
+        self.default_config()
+    }
+
+    /// The listener's default server. Its limits apply while the request
+    /// head is parsed, before the Host header can pick a virtual host.
+    pub fn default_config(&self) -> Arc<ServerConfig> {
         for config in &self.config_list {
             if config.default_server {
                 return Arc::clone(config);
@@ -106,20 +221,240 @@ impl HttpConnection {
         Ok(false)
     }
 
+    /// Hands file ranges to the kernel with sendfile(2) until the socket is
+    /// full or a literal segment is next. Returns true when the socket is
+    /// blocked (or the connection failed) and nothing else should be written.
+    pub fn send_file_ranges(&mut self) -> bool {
+        let ActiveAction::FileDownload(ref file, ref mut segments) = self.action else {
+            return false;
+        };
+        while let Some(FileSegment::Range { offset, len }) = segments.front_mut() {
+            match send_file(&self.stream, file, *offset, *len) {
+                // The file shrank under us, the promised length can't be met
+                Ok(0) => {
+                    self.closed = true;
+                    return true;
+                }
+                Ok(n) => {
+                    *offset += n as u64;
+                    *len -= n;
+                    if *len == 0 {
+                        segments.pop_front();
+                    }
+                    self.write_blocked_since = Some(Instant::now());
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.write_blocked_since.get_or_insert_with(Instant::now);
+                    return true;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if sendfile_unsupported(&e) => {
+                    trace!("sendfile unavailable ({}), falling back to buffered copy", e);
+                    self.zero_copy = false;
+                    return false;
+                }
+                Err(_) => {
+                    self.closed = true;
+                    return true;
+                }
+            }
+        }
+        if segments.is_empty() {
+            self.action = ActiveAction::None;
+            self.write_blocked_since = None;
+        }
+        false
+    }
+
     pub fn write_data(&mut self) -> bool {
         match self.stream.write(&self.write_buffer) {
             Ok(n) => {
                 self.write_buffer.drain(..n);
+                // Any progress restarts the stall clock
+                let pending = !self.write_buffer.is_empty()
+                    || matches!(self.action, ActiveAction::FileDownload(_, _));
+                self.write_blocked_since = pending.then(Instant::now);
+                false
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                self.write_blocked_since.get_or_insert_with(Instant::now);
                 false
             }
-            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
             Err(_) => true,
         }
     }
 
+    pub fn buffer_usage(&self) -> BufferUsage {
+        let header_buf = match &self.action {
+            ActiveAction::Cgi { header_buf, .. } | ActiveAction::Proxy { header_buf, .. } => {
+                header_buf.capacity()
+            }
+            _ => 0,
+        };
+        BufferUsage {
+            request: self.request.buffer.len(),
+            write: self.write_buffer.len(),
+            cgi: self.cgi_buffer.len(),
+            proxy: self.proxy_buffer.len(),
+            allocated: self.request.buffer.capacity()
+                + self.write_buffer.capacity()
+                + self.cgi_buffer.capacity()
+                + self.proxy_buffer.capacity()
+                + header_buf,
+        }
+    }
+
+    /// High and low water marks of the write buffer, from the virtual host
+    /// once it is known.
+    pub fn write_buffer_marks(&self) -> (usize, usize) {
+        let s_cfg = self.s_cfg.clone().unwrap_or_else(|| self.default_config());
+        let high = s_cfg.write_buffer_high;
+        (high, s_cfg.write_buffer_low.unwrap_or(high / 4))
+    }
+
+    /// Stops reading the CGI output or upstream response until the client
+    /// drains its write buffer, see `resume_backend`.
+    pub fn pause_backend(&mut self, poll: &Poll) {
+        if !self.backend_paused {
+            self.backend_paused = true;
+            trace!("Client is behind, pausing its backend: {}", self.buffer_usage());
+            self.sync_backend_interest(poll);
+        }
+    }
+
+    /// Reads from the backend again once the write buffer is down to the
+    /// low water mark. Registering the source anew reports any data that
+    /// piled up meanwhile, so nothing waits for a fresh edge.
+    pub fn resume_backend(&mut self, poll: &Poll) {
+        if self.backend_paused && self.write_buffer.len() <= self.write_buffer_marks().1 {
+            self.backend_paused = false;
+            self.sync_backend_interest(poll);
+        }
+    }
+
+    /// Registers the CGI stdout pipe or the upstream socket for what it is
+    /// needed for right now. A paused backend is not read from, so a pipe is
+    /// deregistered and an upstream only stays writable while the request is
+    /// still being sent.
+    pub fn sync_backend_interest(&mut self, poll: &Poll) {
+        let registry = poll.registry();
+        let paused = self.backend_paused;
+        match self.action {
+            ActiveAction::Cgi {
+                ref mut out_stream, ..
+            } => {
+                if let Some(token) = self.cgi_out_token {
+                    let _ = if paused {
+                        registry.deregister(out_stream)
+                    } else {
+                        register_source(registry, out_stream, token, Interest::READABLE)
+                    };
+                }
+            }
+            ActiveAction::Proxy {
+                ref mut upstream,
+                connected,
+                ..
+            }
+            | ActiveAction::Tunnel {
+                ref mut upstream,
+                connected,
+                ..
+            } => {
+                if let Some(token) = self.proxy_token {
+                    let sending = !connected || !self.proxy_buffer.is_empty();
+                    let interest = match (paused, sending) {
+                        (false, true) => Some(Interest::READABLE | Interest::WRITABLE),
+                        (false, false) => Some(Interest::READABLE),
+                        (true, true) => Some(Interest::WRITABLE),
+                        (true, false) => None,
+                    };
+                    let _ = match interest {
+                        Some(interest) => register_source(registry, upstream, token, interest),
+                        None => registry.deregister(upstream),
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
     pub fn touch(&mut self) {
         self.last_activity = Instant::now();
     }
+
+    /// Decides whether the connection outlives this request and adds the
+    /// matching `Connection` / `Keep-Alive` headers to every response path.
+    pub fn negotiate_keep_alive(&mut self, s_cfg: &ServerConfig) {
+        self.requests_served += 1;
+        self.keep_alive = !self.draining
+            && self.request.wants_keep_alive()
+            && self.requests_served < s_cfg.keepalive_requests;
+        if self.keep_alive {
+            self.extra_headers
+                .push(("connection".to_string(), "keep-alive".to_string()));
+            self.extra_headers.push((
+                "keep-alive".to_string(),
+                format!(
+                    "timeout={}, max={}",
+                    s_cfg.keepalive_timeout,
+                    s_cfg.keepalive_requests - self.requests_served
+                ),
+            ));
+        } else {
+            self.extra_headers
+                .push(("connection".to_string(), "close".to_string()));
+        }
+    }
+
+    /// Queues the current response. A HEAD request gets the exact same
+    /// status line and headers (Content-Length included) but no body.
+    pub fn queue_response(&mut self) {
+        // A response that already asked to close (errors, redirects) wins
+        if self
+            .response
+            .headers
+            .get("connection")
+            .is_some_and(|v| v.eq_ignore_ascii_case("close"))
+        {
+            self.keep_alive = false;
+        }
+        for (k, v) in &self.extra_headers {
+            self.response.set_header(k, v);
+        }
+        // Requests rejected before a virtual host was picked
+        if !self.response.headers.contains_key("server") {
+            let s_cfg = self.s_cfg.clone().unwrap_or_else(|| self.default_config());
+            if let Some(server) = s_cfg.server_header() {
+                self.response.set_header("server", &server);
+            }
+        }
+        if !self.keep_alive {
+            self.response.set_header("connection", "close");
+            self.response.headers.remove("keep-alive");
+        }
+        let bytes = if self.request.method == Method::HEAD {
+            self.response.to_bytes_headers_only()
+        } else {
+            self.response.to_bytes()
+        };
+        self.write_buffer.extend_from_slice(&bytes);
+    }
+}
+
+/// Registers `source`, or updates it when it is still registered.
+fn register_source<S: mio::event::Source + ?Sized>(
+    registry: &mio::Registry,
+    source: &mut S,
+    token: Token,
+    interest: Interest,
+) -> std::io::Result<()> {
+    match registry.register(source, token, interest) {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            registry.reregister(source, token, interest)
+        }
+        result => result,
+    }
 }
 
 impl HttpConnection {
@@ -129,24 +464,38 @@ impl HttpConnection {
     /// 1. Drains the OS socket buffer into the `HttpConnection` request buffer.
     /// 2. Checks for EOF or read errors to update the `closed` state.
     /// 3. Implements CGI backpressure by switching interest to `WRITABLE` if the buffer is full.
-    /// 4. Triggers `proces_request` if there is pending data to be parsed.
+    /// 4. Triggers `proces_request` if there is pending data to be parsed, or hands
+    ///    the bytes to the upstream when the connection is a CONNECT tunnel.
     pub fn handle_read_phase(
         conn: &mut HttpConnection,
         poll: &Poll,
         token: Token,
-        next_token: &mut usize,
-        cgi_to_client: &mut HashMap<Token, Token>,
+        tokens: &mut TokenSlab,
         session_store: &mut SessionStore,
     ) -> Result<()> {
+        if conn.is_tunnel() {
+            read_tunnel_client(conn, poll);
+            return Ok(());
+        }
+
+        let buffered = conn.request.buffer.len();
         match conn.read_data() {
             Ok(is_eof) => conn.closed = is_eof,
             Err(_) => conn.closed = true,
         }
+        if matches!(
+            conn.request.state,
+            ParsingState::Body | ParsingState::ChunkedBody
+        ) {
+            conn.body_received += conn.request.buffer.len().saturating_sub(buffered);
+        }
 
         // Manage Backpressure for CGI
         let mut interest = Interest::READABLE;
-        if matches!(conn.action, ActiveAction::Cgi { .. })
-            && conn.request.buffer.len() > MAX_READ_DATA
+        if matches!(
+            conn.action,
+            ActiveAction::Cgi { .. } | ActiveAction::Proxy { .. } | ActiveAction::Resolve { .. }
+        ) && conn.request.buffer.len() > MAX_READ_DATA
         {
             interest = Interest::WRITABLE;
         }
@@ -158,8 +507,7 @@ impl HttpConnection {
             conn.closed = HttpRequest::proces_request(
                 poll,
                 token,
-                next_token,
-                cgi_to_client,
+                tokens,
                 conn,
                 session_store,
             )?;
@@ -170,7 +518,8 @@ impl HttpConnection {
     /// Manages data egress by flushing buffers and handling file streaming.
     ///
     /// # Logic Steps
-    /// 1. Refills the internal write buffer from an active file stream if currently empty.
+    /// 1. Streams file ranges with sendfile(2) when possible; otherwise refills the
+    ///    write buffer from the file (always for literal multipart segments).
     /// 2. Flushes the write buffer to the client socket and updates the connection's closed state.
     /// 3. If the buffer is fully drained and the connection is open, triggers post-write updates.
     /// 4. Supports HTTP Keep-Alive and Pipelining by checking for subsequent requests via `handle_post_write_update`.
@@ -178,29 +527,45 @@ impl HttpConnection {
         conn: &mut HttpConnection,
         poll: &Poll,
         token: Token,
-        next_token: &mut usize,
-        cgi_to_client: &mut HashMap<Token, Token>,
+        tokens: &mut TokenSlab,
         session_store: &mut SessionStore,
     ) -> Result<()> {
-        // 1. Fill buffer from file if needed
-        if conn.write_buffer.is_empty()
-            && let ActiveAction::FileDownload(ref mut file, ref mut remaining) = conn.action
+        // 1. Send file ranges zero-copy, or fill the buffer from the file
+        let blocked = conn.write_buffer.is_empty() && conn.zero_copy && conn.send_file_ranges();
+        if !blocked
+            && conn.write_buffer.is_empty()
+            && let ActiveAction::FileDownload(ref file, ref mut segments) = conn.action
         {
-            let mut chunk = vec![0u8; 8192];
-            match file.read(&mut chunk) {
-                Ok(0) => conn.action = ActiveAction::None,
-                Ok(n) => {
-                    conn.write_buffer.extend_from_slice(&chunk[..n]);
-                    *remaining -= n;
+            match segments.pop_front() {
+                Some(FileSegment::Raw(bytes)) => conn.write_buffer.extend_from_slice(&bytes),
+                Some(FileSegment::Range { offset, len }) => {
+                    let mut chunk = vec![0u8; len.min(8192)];
+                    match file.read_at(&mut chunk, offset) {
+                        // The file shrank under us, the promised length can't be met
+                        Ok(0) | Err(_) => conn.closed = true,
+                        Ok(n) => {
+                            conn.write_buffer.extend_from_slice(&chunk[..n]);
+                            if n < len {
+                                segments.push_front(FileSegment::Range {
+                                    offset: offset + n as u64,
+                                    len: len - n,
+                                });
+                            }
+                        }
+                    }
                 }
-                Err(_) => conn.closed = true,
+                None => {}
+            }
+            if segments.is_empty() {
+                conn.action = ActiveAction::None;
             }
         }
 
-        // 2. Flush buffer to socket
+        // 2. Flush buffer to socket, letting a paused backend refill it
         if !conn.write_buffer.is_empty() {
             conn.closed = conn.write_data() || conn.closed;
         }
+        conn.resume_backend(poll);
 
         // 3. Post-write logic: Check for pipelined requests or Keep-Alive
         if !conn.closed && conn.write_buffer.is_empty() {
@@ -208,8 +573,7 @@ impl HttpConnection {
                 conn,
                 poll,
                 token,
-                next_token,
-                cgi_to_client,
+                tokens,
                 session_store,
             )?;
         }
@@ -230,28 +594,38 @@ impl HttpConnection {
         conn: &mut HttpConnection,
         poll: &Poll,
         token: Token,
-        next_token: &mut usize,
-        cgi_to_client: &mut HashMap<Token, Token>,
+        tokens: &mut TokenSlab,
         session_store: &mut SessionStore,
     ) -> Result<()> {
+        // The last response is flushed and the connection wasn't kept alive
+        if !conn.keep_alive
+            && matches!(conn.action, ActiveAction::None)
+            && conn.request.state == ParsingState::RequestLine
+        {
+            conn.closed = true;
+            return Ok(());
+        }
+
         let mut interest = Interest::READABLE;
         if matches!(conn.action, ActiveAction::FileDownload(_, _)) {
             interest |= Interest::WRITABLE;
         }
 
-        conn.response = HttpResponse::new(HTTP_OK, &HttpResponse::status_text(HTTP_OK));
+        conn.response = HttpResponse::new(StatusCode::OK);
         poll.registry()
             .reregister(&mut conn.stream, token, interest)?;
 
         // PIPELINING
-        if !conn.request.buffer.is_empty() && conn.request.state == ParsingState::RequestLine {
+        if !conn.request.buffer.is_empty()
+            && conn.request.state == ParsingState::RequestLine
+            && !conn.is_tunnel()
+        {
             info!("Write finished. Pipelined data detected, processing next request...");
 
             conn.closed = HttpRequest::proces_request(
                 poll,
                 token,
-                next_token,
-                cgi_to_client,
+                tokens,
                 conn,
                 session_store,
             )?;
@@ -263,22 +637,28 @@ impl HttpConnection {
     /// Cleans up a connection and its resources, specifically handling CGI process reaping.
     ///
     /// # Logic Steps
-    /// 1. Removes the connection from the server's map.
+    /// 1. Frees the connection's token; the caller has already lent it out.
     /// 2. Kills active CGI child processes and attempts to reap them.
     /// 3. Moves un-reaped processes to purgatory to prevent zombies.
-    /// 4. Cleans up CGI-to-client internal mappings.
-    pub fn terminate_connection(server: &mut Server, token: Token) {
-        if let Some(mut conn) = server.connections.remove(&token) {
-            println!("Removing connection: {:?}", token);
-            let action = std::mem::replace(&mut conn.action, ActiveAction::None);
+    /// 4. Frees the tokens of its CGI pipes or proxy upstream.
+    pub fn terminate_connection(server: &mut Server, token: Token, mut conn: Box<HttpConnection>) {
+        server.tokens.remove(token);
+        server.timers.cancel(token);
+        println!("Removing connection: {:?} ({})", token, conn.buffer_usage());
+        let action = std::mem::replace(&mut conn.action, ActiveAction::None);
 
-            if let ActiveAction::Cgi { mut child, .. } = action {
+        match action {
+            ActiveAction::Cgi { mut child, .. } => {
                 let _ = child.kill();
                 if let Ok(None) = child.try_wait() {
                     server.zombie_purgatory.push(child)
                 }
-                cleanup_cgi(&mut server.cgi_to_client, &mut conn);
+                cleanup_cgi(&mut server.tokens, &mut conn);
+            }
+            ActiveAction::Proxy { .. } | ActiveAction::Resolve { .. } | ActiveAction::Tunnel { .. } => {
+                cleanup_proxy(&mut server.tokens, &mut conn)
             }
+            _ => {}
         }
     }
 }
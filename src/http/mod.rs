@@ -1,6 +1,18 @@
+pub mod conditional;
+pub mod framing;
+pub mod header_map;
+pub mod range;
 pub mod request;
 pub mod response;
+pub mod status;
 pub mod http_connection;
+pub mod query;
+pub use conditional::*;
+pub use framing::*;
+pub use header_map::*;
+pub use range::*;
 pub use request::*;
 pub use response::*;
-pub use http_connection::*;
\ No newline at end of file
+pub use status::*;
+pub use http_connection::*;
+pub use query::*;
\ No newline at end of file
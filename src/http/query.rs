@@ -0,0 +1,131 @@
+/// Decodes `%XX` escapes. With `plus_as_space` a `+` becomes a space, as in
+/// `application/x-www-form-urlencoded` query strings. Returns `None` on a
+/// truncated or non-hex escape.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Option<Vec<u8>> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = input.get(i + 1..i + 3)?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    Some(out)
+}
+
+/// Escapes a decoded path for use in a request target again. Unreserved
+/// characters, sub-delims, `:`, `@` and `/` pass through; every other byte,
+/// `%` included, becomes `%XX`.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Turns the raw path of a request target into the path used for routing
+/// and file lookups: percent-decoded, `.`/`..` segments resolved and repeated
+/// slashes collapsed. A trailing slash is kept.
+///
+/// Returns `None` for bad escapes, NUL bytes, invalid UTF-8 or a `..` that
+/// would climb above the root.
+pub fn normalize_path(raw: &str) -> Option<String> {
+    let decoded = String::from_utf8(percent_decode(raw, false)?).ok()?;
+    if decoded.contains('\0') || !decoded.starts_with('/') {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            s => segments.push(s),
+        }
+    }
+
+    let mut path = format!("/{}", segments.join("/"));
+    let ends_in_dir = decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    if ends_in_dir && !segments.is_empty() {
+        path.push('/');
+    }
+    Some(path)
+}
+
+/// Decoded `key=value` pairs of a query string, in order and with repeated
+/// keys kept (`?tag=a&tag=b`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn parse(raw: &str) -> Self {
+        let decode = |s: &str| match percent_decode(s, true) {
+            Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            None => s.to_string(),
+        };
+
+        let pairs = raw
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| match p.split_once('=') {
+                Some((k, v)) => (decode(k), decode(v)),
+                None => (decode(p), String::new()),
+            })
+            .collect();
+        Self { pairs }
+    }
+
+    /// First value for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value for `key`, in request order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+
+/// Beyond this many ranges the header is ignored and the full file is sent,
+/// so a client can't make us interleave thousands of tiny parts.
+pub const MAX_RANGES: usize = 16;
+
+/// One piece of a file response, consumed in order by the write phase.
+#[derive(Debug, PartialEq)]
+pub enum FileSegment {
+    /// Literal bytes, e.g. the part headers of a `multipart/byteranges` body.
+    Raw(Vec<u8>),
+    /// `len` bytes of the file starting at `offset`.
+    Range { offset: u64, len: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// No usable `Range` header: send the whole file.
+    Full,
+    /// Well-formed, but no range overlaps the file (416).
+    Unsatisfiable,
+    /// Satisfiable ranges as inclusive `(first, last)` byte positions,
+    /// sorted and with overlapping or adjacent ones merged.
+    Partial(Vec<(u64, u64)>),
+}
+
+/// Parses a `Range: bytes=...` header against a file of `size` bytes.
+/// Syntax errors make the whole header ignored, as RFC 9110 allows.
+pub fn parse_range(header: &str, size: u64) -> RangeRequest {
+    let Some((unit, set)) = header.split_once('=') else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    let specs: Vec<&str> = set.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = if first.is_empty() {
+            // Suffix range: the last N bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            (suffix > 0 && size > 0).then(|| (size.saturating_sub(suffix), size - 1))
+        } else {
+            let Ok(first) = first.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let last = if last.is_empty() {
+                u64::MAX
+            } else {
+                match last.parse::<u64>() {
+                    Ok(l) if l >= first => l,
+                    _ => return RangeRequest::Full,
+                }
+            };
+            (first < size).then(|| (first, last.min(size - 1)))
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(coalesce(ranges))
+    }
+}
+
+/// Merges overlapping and adjacent ranges so no byte is sent twice.
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+/// The whole file as a single segment.
+pub fn full_segments(size: u64) -> VecDeque<FileSegment> {
+    let mut segments = VecDeque::new();
+    if size > 0 {
+        segments.push_back(FileSegment::Range {
+            offset: 0,
+            len: size as usize,
+        });
+    }
+    segments
+}
+
+/// Lays out a `multipart/byteranges` body and returns it with its length.
+pub fn multipart_segments(
+    ranges: &[(u64, u64)],
+    size: u64,
+    content_type: &str,
+    boundary: &str,
+) -> (VecDeque<FileSegment>, usize) {
+    let mut segments = VecDeque::new();
+    let mut total = 0;
+    for &(first, last) in ranges {
+        let head = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, content_type, first, last, size
+        )
+        .into_bytes();
+        let len = (last - first + 1) as usize;
+        total += head.len() + len;
+        segments.push_back(FileSegment::Raw(head));
+        segments.push_back(FileSegment::Range { offset: first, len });
+    }
+    let tail = format!("\r\n--{}--\r\n", boundary).into_bytes();
+    total += tail.len();
+    segments.push_back(FileSegment::Raw(tail));
+    (segments, total)
+}
@@ -3,20 +3,32 @@ use crate::prelude::*;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Method {
     GET,
+    HEAD,
     POST,
+    PUT,
     DELETE,
+    OPTIONS,
+    CONNECT,
 }
 
 impl Method {
+    /// HEAD is implied wherever GET is allowed (RFC 9110 section 9.3.2) and
+    /// OPTIONS is always answered, it only describes the route.
     pub fn is_allowed(&self, allowed_methods: &[String]) -> bool {
         allowed_methods.contains(&self.to_string())
+            || (*self == Method::HEAD && allowed_methods.iter().any(|m| m == "GET"))
+            || *self == Method::OPTIONS
     }
 
     pub fn as_str(&self) -> &str {
         match self {
             Method::GET => "GET",
+            Method::HEAD => "HEAD",
             Method::POST => "POST",
+            Method::PUT => "PUT",
             Method::DELETE => "DELETE",
+            Method::OPTIONS => "OPTIONS",
+            Method::CONNECT => "CONNECT",
         }
     }
 }
@@ -26,8 +38,12 @@ impl FromStr for Method {
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s.to_uppercase().as_str() {
             "GET" => Ok(Method::GET),
+            "HEAD" => Ok(Method::HEAD),
             "POST" => Ok(Method::POST),
+            "PUT" => Ok(Method::PUT),
             "DELETE" => Ok(Method::DELETE),
+            "OPTIONS" => Ok(Method::OPTIONS),
+            "CONNECT" => Ok(Method::CONNECT),
             _ => Err(ParseError::InvalidMethod),
         }
     }
@@ -37,8 +53,12 @@ impl std::fmt::Display for Method {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
             Method::GET => "GET",
+            Method::HEAD => "HEAD",
             Method::POST => "POST",
+            Method::PUT => "PUT",
             Method::DELETE => "DELETE",
+            Method::OPTIONS => "OPTIONS",
+            Method::CONNECT => "CONNECT",
         };
         write!(f, "{}", s)
     }
@@ -64,6 +84,7 @@ pub enum ParseError {
     InvalidMethod,
     InvalidUtf8(std::string::FromUtf8Error),
     UnexpectedEof,
+    UriTooLong,
     HeaderTooLong,
     TooManyHeaders,
     InvalidHeaderName,
@@ -71,7 +92,7 @@ pub enum ParseError {
     InvalidChunkSize,
     PayloadTooLarge,
     ParseHexError,
-    Error(u16),
+    Error(StatusCode),
 }
 
 impl fmt::Display for ParseError {
@@ -82,6 +103,7 @@ impl fmt::Display for ParseError {
             ParseError::InvalidMethod => write!(f, "Invalid HTTP method"),
             ParseError::InvalidUtf8(_) => write!(f, "Invalid UTF-8 in request"),
             ParseError::UnexpectedEof => write!(f, "Unexpected end of input"),
+            ParseError::UriTooLong => write!(f, "Request line too long"),
             ParseError::HeaderTooLong => write!(f, "Header line too long"),
             ParseError::TooManyHeaders => write!(f, "Too many headers"),
             ParseError::InvalidHeaderName => write!(f, "Invalid header name"),
@@ -113,17 +135,27 @@ pub enum ChunkState {
 #[derive(Debug)]
 pub struct HttpRequest {
     pub method: Method,
+    /// Raw origin-form target, query included.
     pub url: String,
+    /// Decoded and normalized path used for routing and file lookups.
+    pub path: String,
+    /// Raw query string, without the `?`.
+    pub query: String,
+    pub query_params: QueryParams,
+    pub authority: Option<String>,
     pub version: String,
     pub headers: HashMap<String, String>,
     pub trailers: HashMap<String, String>,
     pub body: Vec<u8>,
     pub body_file: Option<File>,
     pub is_large_body: bool,
-    pub buffer: Vec<u8>,
+    /// Unparsed input; the parsers consume it from the front.
+    pub buffer: ByteBuffer,
     pub cursor: usize,
     pub state: ParsingState,
     pub chunk_state: ChunkState,
+    /// Header lines seen so far, repeats included.
+    pub header_count: usize,
 }
 
 impl Default for HttpRequest {
@@ -137,28 +169,84 @@ impl HttpRequest {
         HttpRequest {
             method: Method::GET,
             url: String::new(),
+            path: String::new(),
+            query: String::new(),
+            query_params: QueryParams::default(),
+            authority: None,
             version: String::new(),
             headers: HashMap::new(),
             trailers: HashMap::new(),
             body: Vec::new(),
-            buffer: Vec::with_capacity(4096),
+            buffer: ByteBuffer::with_capacity(4096),
             cursor: 0,
             state: ParsingState::RequestLine,
             is_large_body: false,
             body_file: None,
             chunk_state: ChunkState::ReadSize,
+            header_count: 0,
         }
     }
 
     pub fn clear(&mut self) {
         self.state = ParsingState::RequestLine;
+        self.method = Method::GET;
+        self.authority = None;
         self.headers.clear();
+        self.header_count = 0;
         self.trailers.clear();
         self.body.clear();
+        self.chunk_state = ChunkState::ReadSize;
+    }
+
+    /// True for an HTTP/1.1 request that holds its body back until it sees
+    /// `100 Continue` (RFC 9110 section 10.1.1).
+    pub fn expects_continue(&self) -> bool {
+        self.version == "HTTP/1.1"
+            && self
+                .headers
+                .get("expect")
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case("100-continue"))
+            && self.has_body()
+    }
+
+    /// Chunked transfer coding only exists from HTTP/1.1 on.
+    pub fn accepts_chunked(&self) -> bool {
+        self.version != "HTTP/1.0"
+    }
+
+    /// Persistence the client asked for: HTTP/1.1 unless it sent
+    /// `Connection: close`, HTTP/1.0 only with `Connection: keep-alive`.
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.headers.get("connection").is_some_and(|v| {
+                v.split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        if self.version == "HTTP/1.1" {
+            !has_option("close")
+        } else {
+            has_option("keep-alive")
+        }
+    }
+
+    /// Framing was validated with the headers, so any Transfer-Encoding
+    /// left here ends in chunked.
+    pub fn is_chunked(&self) -> bool {
+        self.headers.contains_key("transfer-encoding")
+    }
+
+    pub fn has_body(&self) -> bool {
+        self.is_chunked()
+            || self
+                .headers
+                .get("content-length")
+                .and_then(|s| s.parse::<usize>().ok())
+                .is_some_and(|len| len > 0)
     }
 
     pub fn finish_request(&mut self) {
-        self.buffer.drain(..self.cursor);
+        self.buffer.consume(self.cursor);
         self.cursor = 0;
         self.clear();
     }
@@ -166,30 +254,22 @@ impl HttpRequest {
     pub fn proces_request(
         poll: &Poll,
         token: Token,
-        next_token: &mut usize,
-        cgi_to_client: &mut HashMap<Token, Token>,
+        tokens: &mut TokenSlab,
         conn: &mut HttpConnection,
         session_store: &mut SessionStore,
     ) -> Result<bool> {
         let mut closed = false;
         trace!("### start processing a request ###");
-        match HttpRequest::parse_request(
-            conn,
-            poll,
-            next_token,
-            cgi_to_client,
-            token,
-            session_store,
-        ) {
+        match HttpRequest::parse_request(conn, poll, tokens, token, session_store) {
             Ok(()) => {
                 // println!("{}",conn.request);
                 trace!("### request state is complete ###");
                 let s_cfg = conn.s_cfg.as_ref().unwrap();
 
-                if let Some(upload_manager) = &mut conn.upload_manager {
-                    Upload::handel_upload_manager(&mut conn.response, upload_manager, s_cfg);
-                    conn.write_buffer
-                        .extend_from_slice(&conn.response.to_bytes());
+                if let Some(mut upload_manager) = conn.upload_manager.take() {
+                    Upload::handel_upload_manager(&mut conn.response, &mut upload_manager, s_cfg);
+                    conn.queue_response();
+                    conn.action = ActiveAction::None;
                 }
 
                 conn.request.finish_request();
@@ -197,15 +277,20 @@ impl HttpRequest {
             Err(ParseError::IncompleteRequestLine) => {}
             Err(e) => {
                 let code = match e {
-                    ParseError::PayloadTooLarge => HTTP_PAYLOAD_TOO_LARGE,
-                    ParseError::InvalidMethod => HTTP_METHOD_NOT_ALLOWED,
-                    ParseError::HeaderTooLong => HTTP_URI_TOO_LONG,
-                    _ => HTTP_BAD_REQUEST,
+                    ParseError::PayloadTooLarge => StatusCode::CONTENT_TOO_LARGE,
+                    ParseError::InvalidMethod => StatusCode::METHOD_NOT_ALLOWED,
+                    ParseError::UriTooLong => StatusCode::URI_TOO_LONG,
+                    ParseError::HeaderTooLong | ParseError::TooManyHeaders => {
+                        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+                    }
+                    ParseError::Error(code) => code,
+                    _ => StatusCode::BAD_REQUEST,
                 };
                 handle_error(&mut conn.response, code, conn.s_cfg.as_ref());
+                // Drops the temporary file of an unfinished PUT
+                conn.upload_manager = None;
                 closed = true;
-                conn.write_buffer
-                    .extend_from_slice(&conn.response.to_bytes());
+                conn.queue_response();
                 conn.request.finish_request();
             }
         }
@@ -218,38 +303,47 @@ impl HttpRequest {
                 Interest::READABLE | Interest::WRITABLE,
             )?;
         }
-        Ok(closed)
+        Ok(closed || conn.closed)
     }
 
     pub fn parse_request(
         conn: &mut HttpConnection,
         poll: &Poll,
-        next_token: &mut usize,
-        cgi_to_client: &mut HashMap<Token, Token>,
+        tokens: &mut TokenSlab,
         client_token: Token,
         session_store: &mut SessionStore,
     ) -> core::result::Result<(), ParseError> {
         loop {
             let res = match conn.request.state {
-                ParsingState::RequestLine => conn.request.parse_request_line(),
+                ParsingState::RequestLine => {
+                    // Anything pipelined after a non-persistent request is ignored
+                    if !conn.keep_alive {
+                        return Err(ParseError::IncompleteRequestLine);
+                    }
+                    conn.extra_headers.clear();
+                    // Each request gets its own body budget
+                    conn.total_body_read = 0;
+                    if !conn.request.buffer.is_empty() {
+                        conn.head_started.get_or_insert_with(Instant::now);
+                    }
+                    let limits = conn.default_config();
+                    conn.request.parse_request_line(limits.max_request_line)
+                }
                 ParsingState::Headers => HttpRequest::parse_headers(conn),
                 ParsingState::HeadersDone => {
-                    if HttpRequest::setup_action(
-                        conn,
-                        poll,
-                        next_token,
-                        cgi_to_client,
-                        client_token,
-                        session_store,
-                    )? {
-                        conn.write_buffer
-                            .extend_from_slice(&conn.response.to_bytes());
+                    if HttpRequest::setup_action(conn, poll, tokens, client_token, session_store)? {
+                        // A final answer to a client still waiting for 100 Continue:
+                        // the body never comes, so the connection can't be reused
+                        if conn.request.expects_continue() {
+                            conn.closed = true;
+                        }
+                        conn.queue_response();
                         conn.request.state = ParsingState::Complete;
                     }
                     Ok(())
                 }
                 ParsingState::Body => HttpRequest::parse_unchunked_body(poll, conn),
-                ParsingState::ChunkedBody => match HttpRequest::parse_chunked_body(conn) {
+                ParsingState::ChunkedBody => match HttpRequest::parse_chunked_body(conn, poll) {
                     Ok(true) => {
                         conn.request.state = ParsingState::Complete;
                         Ok(())
@@ -280,13 +374,16 @@ impl HttpRequest {
     pub fn setup_action(
         conn: &mut HttpConnection,
         poll: &Poll,
-        next_token: &mut usize,
-        cgi_to_client: &mut HashMap<Token, Token>,
+        tokens: &mut TokenSlab,
         client_token: Token,
         session_store: &mut SessionStore,
     ) -> core::result::Result<bool, ParseError> {
         let s_cfg = conn.resolve_config();
         conn.s_cfg = Some(Arc::clone(&s_cfg));
+        conn.negotiate_keep_alive(&s_cfg);
+        if let Some(server) = s_cfg.server_header() {
+            conn.extra_headers.push(("server".to_string(), server));
+        }
 
         session_store.mange_session_store(conn);
 
@@ -297,12 +394,7 @@ impl HttpRequest {
             .and_then(|s| s.parse::<usize>().ok())
             .unwrap_or(0);
 
-        let is_chunked = conn
-            .request
-            .headers
-            .get("transfer-encoding")
-            .map(|v| v.contains("chunked"))
-            .unwrap_or(false);
+        let is_chunked = conn.request.is_chunked();
 
         let content_type = conn
             .request
@@ -328,23 +420,68 @@ impl HttpRequest {
 
         // 2. Resolve Route and Set Intent
         let request = &conn.request;
+        let is_forward = s_cfg.forward_proxy && request.authority.is_some();
 
-        let res = match s_cfg.find_route(&request.url, &request.method) {
+        let res = match s_cfg.find_route(&request.path, &request.method) {
+            // Absolute-form targets and tunnels bypass the routes
+            _ if is_forward => {
+                let Some(plan) = ProxyPlan::for_forward(request) else {
+                    return Err(ParseError::MalformedRequestLine);
+                };
+                if !s_cfg.forward_allowed(&plan.target.host, plan.target.port, plan.tunnel) {
+                    handle_error(&mut conn.response, StatusCode::FORBIDDEN, Some(&s_cfg));
+                    return Ok(true);
+                }
+                if start_proxy(conn, plan, &s_cfg, poll, tokens, client_token) {
+                    return Ok(true);
+                }
+                false
+            }
+            _ if request.method == Method::CONNECT => {
+                handle_error(
+                    &mut conn.response,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    Some(&s_cfg),
+                );
+                true
+            }
             Ok(r_cfg) => {
-                if let Some(ref redirect_url) = r_cfg.redirection {
+                let preflight = is_preflight(request);
+                if let Some(cors) = &r_cfg.cors
+                    && !preflight
+                {
+                    conn.extra_headers.extend(cors_headers(request, cors));
+                }
+
+                if let Some(cors) = &r_cfg.cors
+                    && preflight
+                {
+                    handle_preflight(&mut conn.response, request, r_cfg, cors);
+                    true
+                } else if let Some(ref redirect_url) = r_cfg.redirection {
                     HttpResponse::redirect(
                         &mut conn.response,
-                        r_cfg.redirect_code.unwrap_or(HTTP_FOUND),
+                        r_cfg
+                            .redirect_code
+                            .and_then(StatusCode::new)
+                            .unwrap_or(StatusCode::FOUND),
                         redirect_url,
                     );
                     true
-                } else if r_cfg
-                    .cgi_ext
-                    .as_ref()
-                    .is_some_and(|ext| request.url.ends_with(ext))
+                } else if let Some(plan) = ProxyPlan::for_route(r_cfg, &request.normalized_target())
+                {
+                    if start_proxy(conn, plan, &s_cfg, poll, tokens, client_token) {
+                        return Ok(true);
+                    }
+                    false
+                } else if request.method != Method::OPTIONS
+                    && r_cfg
+                        .cgi_ext
+                        .as_ref()
+                        .is_some_and(|ext| request.path.ends_with(ext))
                 {
                     let full_script_path =
-                        PathBuf::from(&s_cfg.root).join(request.url.trim_start_matches('/'));
+                        PathBuf::from(&s_cfg.root).join(request.path.trim_start_matches('/'));
 
                     let cmp = full_script_path.to_string_lossy().into_owned();
 
@@ -363,7 +500,6 @@ impl HttpRequest {
                         _ => {
                             // No interpreter path defined in config
                             let ext = r_cfg.cgi_ext.as_deref().unwrap_or("");
-                            dbg!(&ext);
                             match ext {
                                 ".py" => ("python3".to_string(), vec![full_script_path]),
                                 ".sh" => ("bash".to_string(), vec![full_script_path]),
@@ -378,17 +514,24 @@ impl HttpRequest {
 
                     // 1. Create the OUT pair (Script Output -> Server)
                     let Ok((server_out_std, script_out_std)) = UnixStream::pair() else {
-                        handle_error(&mut conn.response, 500, Some(&s_cfg));
+                        handle_error(
+                            &mut conn.response,
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Some(&s_cfg),
+                        );
                         return Ok(true);
                     };
 
-
                     server_out_std.set_nonblocking(true).ok();
                     let mut server_out_mio = mio::net::UnixStream::from_std(server_out_std);
 
                     // 2. Setup Input pair (Server -> Script Input)
                     let Ok((server_in_std, script_in_std)) = UnixStream::pair() else {
-                        handle_error(&mut conn.response, 500, Some(&s_cfg));
+                        handle_error(
+                            &mut conn.response,
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Some(&s_cfg),
+                        );
                         return Ok(true);
                     };
                     server_in_std.set_nonblocking(true).ok();
@@ -408,14 +551,12 @@ impl HttpRequest {
 
                     match cmd.spawn() {
                         Ok(child) => {
-                            let out_token = Token(*next_token);
-                            *next_token += 1;
+                            let out_token = tokens.insert(Slot::Pipe(client_token));
                             poll.registry()
                                 .register(&mut server_out_mio, out_token, Interest::READABLE)
                                 .ok();
 
-                            let in_token = Token(*next_token);
-                            *next_token += 1;
+                            let in_token = tokens.insert(Slot::Pipe(client_token));
                             poll.registry()
                                 .register(&mut server_in_mio, in_token, Interest::WRITABLE)
                                 .ok();
@@ -430,25 +571,30 @@ impl HttpRequest {
                                 parse_state: CgiParsingState::ReadHeaders,
                                 header_buf: Vec::new(),
                                 start_time: Instant::now(),
+                                head_only: conn.request.method == Method::HEAD,
+                                output_done: None,
                             };
 
-                            cgi_to_client.insert(out_token, client_token);
-                            cgi_to_client.insert(in_token, client_token);
-
                             false
                         }
                         Err(_) => {
-                            handle_error(&mut conn.response, 500, Some(&s_cfg));
+                            handle_error(
+                                &mut conn.response,
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                Some(&s_cfg),
+                            );
                             return Ok(true);
                         }
                     }
                 } else {
                     match request.method {
-                        Method::GET => {
-                            if let ActiveAction::FileDownload(file, file_size) =
+                        Method::GET | Method::HEAD => {
+                            // HEAD gets the exact GET headers, the file handle is just dropped
+                            if let ActiveAction::FileDownload(file, segments) =
                                 handle_get(request, &mut conn.response, r_cfg, &s_cfg)
+                                && request.method == Method::GET
                             {
-                                conn.action = ActiveAction::FileDownload(file, file_size);
+                                conn.action = ActiveAction::FileDownload(file, segments);
                             }
                             true
                         }
@@ -458,37 +604,100 @@ impl HttpRequest {
                                 conn.action = ActiveAction::Upload(path);
                                 false
                             } else {
-                                handle_error(&mut conn.response, HTTP_FORBIDDEN, Some(&s_cfg));
+                                handle_error(
+                                    &mut conn.response,
+                                    StatusCode::FORBIDDEN,
+                                    Some(&s_cfg),
+                                );
                                 return Ok(true);
                             }
                         }
+                        Method::PUT => {
+                            if r_cfg.upload_dir.is_empty() {
+                                handle_error(
+                                    &mut conn.response,
+                                    StatusCode::FORBIDDEN,
+                                    Some(&s_cfg),
+                                );
+                                return Ok(true);
+                            }
+                            let Some(mut upload) =
+                                handle_put(&mut conn.response, request, r_cfg, &s_cfg)
+                            else {
+                                return Ok(true);
+                            };
+                            // The body is the file itself, never multipart
+                            conn.boundary.clear();
+                            if !is_chunked && content_length == 0 {
+                                Upload::handel_upload_manager(
+                                    &mut conn.response,
+                                    &mut upload,
+                                    &s_cfg,
+                                );
+                                return Ok(true);
+                            }
+                            conn.action = ActiveAction::Upload(upload.path.clone());
+                            conn.upload_manager = Some(upload);
+                            false
+                        }
                         Method::DELETE => {
                             handle_delete(&mut conn.response, request, r_cfg, &s_cfg);
                             true
                         }
+                        Method::OPTIONS => {
+                            handle_options(&mut conn.response, r_cfg);
+                            true
+                        }
+                        Method::CONNECT => {
+                            handle_error(
+                                &mut conn.response,
+                                StatusCode::METHOD_NOT_ALLOWED,
+                                Some(&s_cfg),
+                            );
+                            true
+                        }
                     }
                 }
             }
             Err(RoutingError::MethodNotAllowed) => {
-                handle_error(&mut conn.response, HTTP_METHOD_NOT_ALLOWED, Some(&s_cfg));
+                handle_error(
+                    &mut conn.response,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    Some(&s_cfg),
+                );
                 true
             }
             Err(RoutingError::NotFound) => {
-                handle_error(&mut conn.response, HTTP_NOT_FOUND, Some(&s_cfg));
+                handle_error(&mut conn.response, StatusCode::NOT_FOUND, Some(&s_cfg));
                 true
             }
         };
 
         // 3. Update State based on body presence
         if !res {
+            // The route accepted the request, let a waiting client send its body
+            if conn.request.expects_continue() {
+                conn.write_buffer
+                    .extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
+            }
+            if is_chunked || content_length > 0 {
+                conn.body_started = Some(Instant::now());
+                conn.body_received = 0;
+            }
             if is_chunked {
                 conn.request.state = ParsingState::ChunkedBody;
             } else if content_length > 0 {
                 conn.request.state = ParsingState::Body;
-            } else if matches!(conn.action, ActiveAction::Cgi { .. }) {
+            } else if matches!(
+                conn.action,
+                ActiveAction::Cgi { .. }
+                    | ActiveAction::Proxy { .. }
+                    | ActiveAction::Resolve { .. }
+                    | ActiveAction::Tunnel { .. }
+            ) {
                 conn.request.state = ParsingState::Complete;
             } else {
-                conn.response.set_status_code(400);
+                conn.response.set_status_code(StatusCode::BAD_REQUEST);
                 conn.response
                     .set_body(b"Error: No file data provided.".to_vec(), "text/plain");
                 return Ok(true);
@@ -498,9 +707,13 @@ impl HttpRequest {
         Ok(res)
     }
 
-    fn parse_request_line(&mut self) -> core::result::Result<(), ParseError> {
+    fn parse_request_line(&mut self, max_len: usize) -> core::result::Result<(), ParseError> {
         if let Some(abs_index) = find_crlf(&self.buffer, self.cursor) {
             let line_bytes = &self.buffer[self.cursor..abs_index];
+            if line_bytes.len() > max_len {
+                return Err(ParseError::UriTooLong);
+            }
+            check_line(line_bytes)?;
             let request_line =
                 std::str::from_utf8(line_bytes).map_err(|_| ParseError::MalformedRequestLine)?;
 
@@ -508,12 +721,17 @@ impl HttpRequest {
             if parts.len() == 3 {
                 self.method = match parts[0] {
                     "GET" => Method::GET,
+                    "HEAD" => Method::HEAD,
                     "POST" => Method::POST,
+                    "PUT" => Method::PUT,
                     "DELETE" => Method::DELETE,
+                    "OPTIONS" => Method::OPTIONS,
+                    "CONNECT" => Method::CONNECT,
                     _ => return Err(ParseError::InvalidMethod),
                 };
-                self.url = parts[1].to_string();
+                let target = parts[1].to_string();
                 self.version = parts[2].to_string();
+                self.set_target(&target)?;
 
                 self.cursor = abs_index + CRLN_LEN;
                 self.state = ParsingState::Headers;
@@ -521,41 +739,137 @@ impl HttpRequest {
                 return Err(ParseError::MalformedRequestLine);
             }
         } else {
+            self.check_pending_line()?;
+            if self.buffer.len() - self.cursor > max_len {
+                return Err(ParseError::UriTooLong);
+            }
             return Err(ParseError::IncompleteRequestLine);
         }
         Ok(())
     }
 
+    /// With no CRLF in sight, any LF already received is a bare one.
+    fn check_pending_line(&self) -> core::result::Result<(), ParseError> {
+        if self.buffer[self.cursor..].contains(&b'\n') {
+            return Err(ParseError::MalformedRequestLine);
+        }
+        Ok(())
+    }
+
+    /// Splits the request target by form: absolute-form (`http://host/path`)
+    /// keeps its authority for forward proxying and routes on the path, while
+    /// authority-form (`host:443`) is only valid for CONNECT.
+    fn set_target(&mut self, target: &str) -> core::result::Result<(), ParseError> {
+        if let Some(rest) = target.strip_prefix("http://") {
+            let (authority, path) = match rest.find(['/', '?']) {
+                Some(idx) => (&rest[..idx], &rest[idx..]),
+                None => (rest, "/"),
+            };
+            if authority.is_empty() {
+                return Err(ParseError::MalformedRequestLine);
+            }
+            self.authority = Some(authority.to_string());
+            self.url = if path.starts_with('?') {
+                format!("/{}", path)
+            } else {
+                path.to_string()
+            };
+        } else if self.method == Method::CONNECT {
+            if target.starts_with('/') || !target.contains(':') {
+                return Err(ParseError::MalformedRequestLine);
+            }
+            self.authority = Some(target.to_string());
+            self.url = target.to_string();
+            self.path = target.to_string();
+            self.query.clear();
+            self.query_params = QueryParams::default();
+            return Ok(());
+        } else {
+            self.url = target.to_string();
+        }
+        self.split_target()
+    }
+
+    /// The normalized path, escaped again, plus `?query`. This is what the
+    /// route matched on, so it is what the proxy forwards.
+    pub fn normalized_target(&self) -> String {
+        let path = percent_encode_path(&self.path);
+        if self.query.is_empty() {
+            path
+        } else {
+            format!("{}?{}", path, self.query)
+        }
+    }
+
+    /// Splits `url` into the normalized `path` and the raw `query`.
+    fn split_target(&mut self) -> core::result::Result<(), ParseError> {
+        let (raw_path, query) = match self.url.split_once('?') {
+            Some((p, q)) => (p, q),
+            None => (self.url.as_str(), ""),
+        };
+        self.path = normalize_path(raw_path).ok_or(ParseError::MalformedRequestLine)?;
+        self.query = query.to_string();
+        self.query_params = QueryParams::parse(query);
+        Ok(())
+    }
+
     fn extract_and_parse_header(
         &mut self,
+        max_line: usize,
     ) -> core::result::Result<Option<(String, String)>, ParseError> {
         if let Some(abs_index) = find_crlf(&self.buffer, self.cursor) {
             let line_bytes = &self.buffer[self.cursor..abs_index];
+            if line_bytes.len() > max_line {
+                return Err(ParseError::HeaderTooLong);
+            }
             if line_bytes.is_empty() {
                 self.cursor = abs_index + CRLN_LEN;
                 return Ok(None);
             }
-            let line =
-                std::str::from_utf8(line_bytes).map_err(|_| ParseError::MalformedRequestLine)?;
+            let header = parse_header_line(line_bytes)?;
             self.cursor = abs_index + CRLN_LEN;
-            if let Some(sep) = line.find(':') {
-                let key = line[..sep].trim().to_string();
-                let val = line[sep + 1..].trim().to_string();
-                return Ok(Some((key.to_ascii_lowercase(), val)));
-            }
-            Err(ParseError::MalformedRequestLine)
+            Ok(Some(header))
         } else {
+            self.check_pending_line()?;
+            if self.buffer.len() - self.cursor > max_line {
+                return Err(ParseError::HeaderTooLong);
+            }
             Err(ParseError::IncompleteRequestLine)
         }
     }
 
+    /// Parses header lines as they arrive, enforcing the default server's
+    /// limits on every line so an endless header section is cut off early.
     fn parse_headers(conn: &mut HttpConnection) -> core::result::Result<(), ParseError> {
+        let limits = conn.default_config();
         loop {
-            let headers_option = conn.request.extract_and_parse_header()?;
+            // Everything buffered before the blank line belongs to the head
+            let headers_option = match conn
+                .request
+                .extract_and_parse_header(limits.max_header_size)
+            {
+                Err(ParseError::IncompleteRequestLine)
+                    if conn.request.buffer.len() > limits.max_header_bytes =>
+                {
+                    return Err(ParseError::HeaderTooLong);
+                }
+                res => res?,
+            };
+            if conn.request.cursor > limits.max_header_bytes {
+                return Err(ParseError::HeaderTooLong);
+            }
             match headers_option {
-                Some((k, v)) => conn.request.headers.insert(k, v),
+                Some((k, v)) => {
+                    conn.request.header_count += 1;
+                    if conn.request.header_count > limits.max_headers {
+                        return Err(ParseError::TooManyHeaders);
+                    }
+                    merge_header(&mut conn.request.headers, k, v)?
+                }
                 None => {
-                    conn.request.buffer.drain(..conn.request.cursor);
+                    validate_framing(&conn.request.version, &conn.request.headers)?;
+                    conn.head_started = None;
+                    conn.request.buffer.consume(conn.request.cursor);
                     conn.request.cursor = 0;
                     conn.request.state = ParsingState::HeadersDone;
 
@@ -569,15 +883,16 @@ impl HttpRequest {
         poll: &Poll,
         conn: &mut HttpConnection,
     ) -> core::result::Result<(), ParseError> {
-        let available = conn.request.buffer.len() - conn.request.cursor;
+        // The head was consumed with the headers, the body starts the buffer
+        let available = conn.request.buffer.len();
         let to_process = std::cmp::min(available, conn.body_remaining);
-        // let cursor = conn.request.cursor;
 
         if to_process > 0 {
             match &mut conn.action {
                 ActiveAction::Cgi { in_stream, .. } => {
-                    let data = conn.request.buffer.drain(..to_process).collect::<Vec<u8>>();
-                    conn.cgi_buffer.extend_from_slice(&data);
+                    conn.cgi_buffer
+                        .extend_from_slice(&conn.request.buffer[..to_process]);
+                    conn.request.buffer.consume(to_process);
                     conn.body_remaining -= to_process;
 
                     if let Some(in_token) = conn.cgi_in_token
@@ -588,19 +903,23 @@ impl HttpRequest {
                             .ok();
                     }
                 }
+                ActiveAction::Proxy { .. } | ActiveAction::Resolve { .. } => {
+                    let data = conn.request.buffer.take_front(to_process);
+                    conn.body_remaining -= to_process;
+                    queue_proxy_body(conn, poll, &data, false);
+                }
                 _ => {
-                    let start = conn.request.cursor;
                     execute_active_action(
                         &conn.request,
                         &mut conn.upload_manager,
                         &mut conn.action,
-                        start,
+                        0,
                         to_process,
                         &conn.boundary,
                     )?;
 
                     conn.body_remaining -= to_process;
-                    conn.request.buffer.drain(start..start + to_process);
+                    conn.request.buffer.consume(to_process);
                 }
             }
         }
@@ -614,8 +933,11 @@ impl HttpRequest {
         Ok(())
     }
 
-    pub fn parse_chunked_body(conn: &mut HttpConnection) -> core::result::Result<bool, ParseError> {
-        if let Some(s_cfg) = &conn.s_cfg {
+    pub fn parse_chunked_body(
+        conn: &mut HttpConnection,
+        poll: &Poll,
+    ) -> core::result::Result<bool, ParseError> {
+        if let Some(s_cfg) = conn.s_cfg.clone() {
             loop {
                 match conn.request.chunk_state {
                     ChunkState::ReadSize => {
@@ -624,14 +946,15 @@ impl HttpRequest {
                             return Ok(false);
                         }
 
-                        let search_limit = std::cmp::min(current_len, 18);
+                        let search_limit = std::cmp::min(current_len, MAX_CHUNK_LINE);
                         match find_subsequence(&conn.request.buffer[..search_limit], b"\r\n", 0) {
                             Some(line_end) => {
-                                let hex_str =
-                                    String::from_utf8_lossy(&conn.request.buffer[..line_end]);
-                                let chunk_size = usize::from_str_radix(hex_str.trim(), 16)
-                                    .map_err(|_| ParseError::ParseHexError)?;
-                                if conn.total_body_read + chunk_size > s_cfg.client_max_body_size {
+                                let chunk_size =
+                                    parse_chunk_size(&conn.request.buffer[..line_end])?;
+                                let allowed = s_cfg
+                                    .client_max_body_size
+                                    .saturating_sub(conn.total_body_read);
+                                if chunk_size > allowed {
                                     return Err(ParseError::PayloadTooLarge);
                                 }
 
@@ -640,17 +963,19 @@ impl HttpRequest {
                                     if conn.request.buffer.len() < line_end + 2 {
                                         return Ok(false);
                                     }
-                                    conn.request.buffer.drain(..line_end + 2); // Drain the "0\r\n"
+                                    conn.request.buffer.consume(line_end + 2); // Consume the "0\r\n"
                                     conn.request.chunk_state = ChunkState::ReadTrailers;
                                     continue;
                                 }
 
                                 conn.request.chunk_state = ChunkState::ReadData(chunk_size);
-                                conn.request.buffer.drain(..line_end + 2);
+                                conn.request.buffer.consume(line_end + 2);
                             }
                             None => {
-                                if current_len >= 18 {
-                                    return Err(ParseError::ParseHexError);
+                                if current_len >= MAX_CHUNK_LINE
+                                    || conn.request.buffer[..search_limit].contains(&b'\n')
+                                {
+                                    return Err(ParseError::InvalidChunkSize);
                                 }
                                 return Ok(false);
                             }
@@ -665,22 +990,37 @@ impl HttpRequest {
                         let available = conn.request.buffer.len();
                         let to_read = std::cmp::min(available, remaining_size);
 
-                        let data = conn.request.buffer.drain(..to_read).collect::<Vec<u8>>();
+                        let data = &conn.request.buffer[..to_read];
 
                         match &mut conn.action {
                             ActiveAction::Cgi { .. } => {
-                                conn.cgi_buffer.extend_from_slice(&data);
+                                conn.cgi_buffer.extend_from_slice(data);
+                            }
+                            ActiveAction::Proxy { .. } | ActiveAction::Resolve { .. } => {
+                                let data = data.to_vec();
+                                queue_proxy_body(conn, poll, &data, true);
                             }
                             _ => {
+                                if conn.upload_manager.is_none()
+                                    && let ActiveAction::Upload(path) = &conn.action
+                                {
+                                    conn.upload_manager =
+                                        Some(Upload::new(path.clone(), &conn.boundary));
+                                }
                                 if let Some(mgr) = &mut conn.upload_manager {
+                                    let data = &conn.request.buffer[..to_read];
                                     if !conn.boundary.is_empty() {
-                                        mgr.upload_body_with_boundry(&conn.request, &data);
+                                        mgr.upload_body_with_boundry(&conn.request, data);
                                     } else {
-                                        mgr.upload_simple_body(&conn.request, &data);
+                                        mgr.upload_simple_body(&conn.request, data);
+                                    }
+                                    if let UploadState::Error(code) = mgr.state {
+                                        return Err(ParseError::Error(code));
                                     }
                                 }
                             }
                         }
+                        conn.request.buffer.consume(to_read);
 
                         conn.total_body_read += to_read;
                         let new_remaining = remaining_size - to_read;
@@ -701,27 +1041,53 @@ impl HttpRequest {
                         if &conn.request.buffer[..2] != b"\r\n" {
                             return Err(ParseError::ParseHexError);
                         }
-                        conn.request.buffer.drain(..2);
+                        conn.request.buffer.consume(2);
                         conn.request.chunk_state = ChunkState::ReadSize;
                     }
 
                     ChunkState::ReadTrailers => {
-                        if conn.request.buffer.len() > 8192 {
-                            // 8KB
+                        // As with the head, a pipelined request behind the
+                        // blank line doesn't count against the limit
+                        let trailer =
+                            match conn.request.extract_and_parse_header(s_cfg.max_header_size) {
+                                Err(ParseError::IncompleteRequestLine)
+                                    if conn.request.buffer.len() > s_cfg.max_header_bytes =>
+                                {
+                                    return Err(ParseError::HeaderTooLong);
+                                }
+                                res => res,
+                            };
+                        if conn.request.cursor > s_cfg.max_header_bytes {
                             return Err(ParseError::HeaderTooLong);
                         }
-                        match conn.request.extract_and_parse_header() {
+                        match trailer {
                             Ok(Some((k, v))) => {
-                                if let Some(allowed_trailers) = conn.request.headers.get("trailer")
-                                    && allowed_trailers.to_lowercase().contains(&k)
+                                // Only fields announced in `Trailer` are kept, and never
+                                // ones that would change how the message was framed
+                                let announced =
+                                    conn.request.headers.get("trailer").is_some_and(|list| {
+                                        list.split(',').any(|n| n.trim().eq_ignore_ascii_case(&k))
+                                    });
+                                if announced
+                                    && !matches!(
+                                        k.as_str(),
+                                        "content-length" | "transfer-encoding" | "host" | "trailer"
+                                    )
                                 {
-                                    conn.request.trailers.insert(k, v);
+                                    merge_header(&mut conn.request.trailers, k, v)?;
                                 }
                                 continue;
                             }
                             Ok(None) => {
-                                conn.request.buffer.drain(..conn.request.cursor);
+                                conn.request.buffer.consume(conn.request.cursor);
                                 conn.request.cursor = 0;
+                                if matches!(
+                                    conn.action,
+                                    ActiveAction::Proxy { .. } | ActiveAction::Resolve { .. }
+                                ) {
+                                    conn.proxy_buffer.extend_from_slice(b"0\r\n\r\n");
+                                    wake_upstream(conn, poll);
+                                }
                                 return Ok(true);
                             }
                             Err(ParseError::IncompleteRequestLine) => return Ok(false),
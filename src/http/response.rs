@@ -3,74 +3,73 @@ use crate::prelude::*;
 #[derive(Debug)]
 pub struct HttpResponse {
     pub version: String,
-    pub status_code: u16,
+    pub status_code: StatusCode,
+    /// The registered phrase, unless relayed from a CGI script or upstream.
     pub status_text: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
     pub body: Vec<u8>,
 }
 
 impl HttpResponse {
-    pub fn new(status_code: u16, status_text: &str) -> Self {
+    pub fn new(status_code: StatusCode) -> Self {
         Self {
             version: "HTTP/1.1".to_string(),
             status_code,
-            status_text: status_text.to_string(),
-            headers: HashMap::from([("Content-Length".to_string(), "0".to_string())]),
+            status_text: status_code.reason().to_string(),
+            headers: {
+                let mut headers = HeaderMap::new();
+                headers.set("content-length", "0");
+                headers
+            },
             body: Vec::new(),
         }
     }
 
+    /// Sets `key`, replacing any value it already had.
     pub fn set_header(&mut self, key: &str, value: &str) -> &mut Self {
-        self.headers.insert(key.to_lowercase(), value.to_string());
+        self.headers.set(key, value);
+        self
+    }
+
+    /// Adds another `key` line next to the existing ones.
+    pub fn append_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.headers.append(key, value);
         self
     }
 
     pub fn set_body(&mut self, body: Vec<u8>, content_type: &str) -> &mut Self {
-        self.headers
-            .insert("Content-Length".to_string(), body.len().to_string());
-        self.headers
-            .insert("Content-Type".to_string(), content_type.to_string());
+        self.headers.set("content-length", body.len().to_string());
+        self.headers.set("content-type", content_type);
         self.body = body;
         self
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut res = format!(
-            "{} {} {}\r\n",
-            self.version, self.status_code, self.status_text
-        )
-        .into_bytes();
-
-        for (key, val) in &self.headers {
-            let formatted_key = Self::to_pascal_case(key);
-            res.extend_from_slice(format!("{}: {}\r\n", formatted_key, val).as_bytes());
-        }
-        res.extend_from_slice(b"\r\n");
+        let mut res = self.to_bytes_headers_only();
         res.extend_from_slice(&self.body);
         res
     }
 
-    pub fn status_text(code: u16) -> String {
-        match code {
-            HTTP_NOT_FOUND => "NOT FOUND".to_string(),
-            HTTP_CREATED => "Created".to_string(),
-            204 => "No Content".to_string(),
-            403 => "Forbidden".to_string(),
-            400 => "Bad Request".to_string(),
-            _ => "Ok".to_string(),
-        }
-    }
-
-    pub fn set_status_code(&mut self, code: u16) -> &mut Self {
+    pub fn set_status_code(&mut self, code: StatusCode) -> &mut Self {
         self.status_code = code;
-        self.status_text = HttpResponse::status_text(code);
+        self.status_text = code.reason().to_string();
         self
     }
 
+    /// Status line and header section. A `Date` is stamped here unless the
+    /// response already carries one (e.g. relayed from an upstream).
     pub fn to_bytes_headers_only(&self) -> Vec<u8> {
-        let mut res = format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status_text);
+        let mut res = format!(
+            "{} {} {}\r\n",
+            self.version,
+            self.status_code.as_u16(),
+            self.status_text
+        );
 
-        for (k, v) in &self.headers {
+        if !self.headers.contains_key("date") {
+            res.push_str(&format!("Date: {}\r\n", format_http_date(SystemTime::now())));
+        }
+        for (k, v) in self.headers.iter() {
             let formatted_key = Self::to_pascal_case(k);
             res.push_str(&format!("{}: {}\r\n", formatted_key, v));
         }
@@ -92,17 +91,8 @@ impl HttpResponse {
             .join("-")
     }
 
-    pub fn redirect(res: &mut HttpResponse, code: u16, target_url: &str) {
-        let status_text = match code {
-            301 => "Moved Permanently",
-            302 => "Found",
-            303 => "See Other",
-            307 => "Temporary Redirect",
-            308 => "Permanent Redirect",
-            _ => "Found",
-        };
-        res.status_code = code;
-        res.status_text = status_text.to_string();
+    pub fn redirect(res: &mut HttpResponse, code: StatusCode, target_url: &str) {
+        res.set_status_code(code);
         res.set_header("Location", target_url)
             .set_header("Content-Length", "0")
             .set_header("Connection", "close");
@@ -160,37 +150,30 @@ pub fn generate_autoindex(res: &mut HttpResponse, path: &Path, original_url: &st
     }
 
     html.push_str("</ul></body></html>");
-    res.set_status_code(200);
+    res.set_status_code(StatusCode::OK);
     res.set_body(html.into_bytes(), "text/html");
 }
 
-pub fn handle_error(res: &mut HttpResponse, code: u16, s_cfg: Option<&Arc<ServerConfig>>) {
-    let status_text = match code {
-        HTTP_BAD_REQUEST => "Bad Request",
-        HTTP_FORBIDDEN => "Forbidden",
-        HTTP_NOT_FOUND => "Not Found",
-        HTTP_METHOD_NOT_ALLOWED => "Method Not Allowed",
-        HTTP_PAYLOAD_TOO_LARGE => "Payload Too Large",
-        HTTP_URI_TOO_LONG => "URI Too Long",
-        HTTP_NOT_IMPLEMENTED => "Not Implemented",
-        GATEWAY_TIMEOUT => "GATEWAY TIMEOUT",
-        _ => "Internal Server Error",
-    };
+pub fn handle_error(res: &mut HttpResponse, code: StatusCode, s_cfg: Option<&Arc<ServerConfig>>) {
+    if let Some(server) = s_cfg.and_then(|cfg| cfg.server_header()) {
+        res.set_header("server", &server);
+    }
 
     if let Some(cfg) = s_cfg
-        && let Some(path_str) = cfg.error_pages.get(&code)
+        && let Some(path_str) = cfg.error_pages.get(&code.as_u16())
     {
         let s_root = std::path::Path::new(&cfg.root);
         let err_path = s_root.join(path_str.trim_start_matches('/'));
         if let Ok(content) = fs::read(err_path) {
             res.set_status_code(code).set_body(content, "text/html");
 
-            if code >= 400 && code != 404 && code != 405 {
-                res.headers
-                    .insert("connection".to_string(), "close".to_string());
+            if code.as_u16() >= 400
+                && code != StatusCode::NOT_FOUND
+                && code != StatusCode::METHOD_NOT_ALLOWED
+            {
+                res.set_header("connection", "close");
             } else {
-                res.headers
-                    .insert("connection".to_string(), "keep-alive".to_string());
+                res.set_header("connection", "keep-alive");
             }
 
             return;
@@ -199,13 +182,14 @@ pub fn handle_error(res: &mut HttpResponse, code: u16, s_cfg: Option<&Arc<Server
 
     res.set_status_code(code);
 
-    let body = format!("{} {}", code, status_text).into_bytes();
-    if code >= 400 && code != 404 && code != 405 {
-        res.headers
-            .insert("connection".to_string(), "close".to_string());
+    let body = code.to_string().into_bytes();
+    if code.as_u16() >= 400
+        && code != StatusCode::NOT_FOUND
+        && code != StatusCode::METHOD_NOT_ALLOWED
+    {
+        res.set_header("connection", "close");
     } else {
-        res.headers
-            .insert("connection".to_string(), "keep-alive".to_string());
+        res.set_header("connection", "keep-alive");
     }
     res.set_body(body, "text/plain");
 }
@@ -1,13 +1,22 @@
+pub mod cli;
 pub mod config;
 pub mod error;
 pub mod server;
+pub mod slab;
 pub mod router;
 pub mod http;
 pub mod cgi;
+pub mod cors;
+pub mod proxy;
+pub mod upstream;
 pub mod upload;
 pub mod prelude;
 pub mod handlers;
 pub mod utils;
 pub mod timeouts;
+pub mod shutdown;
+pub mod reload;
 use crate::cgi::*;
+use crate::cors::*;
+use crate::proxy::*;
 use crate::handlers::*;
\ No newline at end of file
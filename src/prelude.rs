@@ -1,4 +1,4 @@
-pub use crate::config::{AppConfig, RouteConfig, ServerConfig};
+pub use crate::config::{AppConfig, CorsConfig, RouteConfig, ServerConfig, UpstreamConfig};
 pub use crate::error::Result;
 pub use crate::http::*;
 pub use crate::utils::*;
@@ -10,11 +10,11 @@ pub use mio::{
 };
 pub use std::process::Child;
 pub use proxy_log::{info, trace};
-pub use std::collections::HashMap;
+pub use std::collections::{HashMap, VecDeque};
 pub use std::fs::{self, File, OpenOptions};
 pub use std::io::{ErrorKind, Read, Write};
 pub use std::net::SocketAddr;
-pub use std::os::unix::fs::MetadataExt;
+pub use std::os::unix::fs::{FileExt, MetadataExt};
 pub use std::path::{Path, PathBuf};
 pub use std::sync::Arc;
 pub use std::time::Duration;
@@ -39,33 +39,23 @@ pub use crate::{
     cgi::CgiParsingState,
     http::HttpResponse,
     router::RoutingError,
-    server::Server,
+    reload::Reloader,
+    server::{ListenerGroup, Server},
+    shutdown::Drain,
+    slab::{Slot, TokenSlab},
     upload::{Upload, UploadState},
+    upstream::{HealthProbe, PeerGuard, UpstreamPool},
 };
 
 
 pub use crate::http::{HttpRequest, PartInfo, find_subsequence, parse_part_headers};
 
 pub const READ_BUF_SIZE: usize = 4096;
-// 4xx Client Errors
-pub const HTTP_BAD_REQUEST: u16 = 400;
-pub const HTTP_FORBIDDEN: u16 = 403;
-pub const HTTP_NOT_FOUND: u16 = 404;
-pub const HTTP_METHOD_NOT_ALLOWED: u16 = 405;
-pub const HTTP_PAYLOAD_TOO_LARGE: u16 = 413;
-pub const HTTP_URI_TOO_LONG: u16 = 414;
-pub const HTTP_OK: u16 = 200;
 pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(120);
 
-// 5xx Server Errors
-pub const HTTP_INTERNAL_SERVER_ERROR: u16 = 500;
-pub const HTTP_NOT_IMPLEMENTED: u16 = 501;
-pub const GATEWAY_TIMEOUT: u16 = 504;
-
-pub const HTTP_FOUND: u16 = 302;
-pub const HTTP_CREATED: u16 = 201;
-
 pub const _1MB: usize = 1_024 * 1024;
 pub const MAX_READ_DATA: usize = u16::MAX as usize; // 64KB
 pub const TIMEOUT_CGI: u64 = 30;
+pub const TIMEOUT_PROXY: u64 = 60;
+pub const TIMEOUT_RESOLVE: u64 = 5;
 pub const CLEAN_UP: u64 = 60;
@@ -0,0 +1,957 @@
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::prelude::*;
+
+/// Hop-by-hop headers that must never be forwarded between client and upstream.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "upgrade",
+    "transfer-encoding",
+    "expect",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamTarget {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+/// `proxy_pass` scheme naming an `upstreams` block instead of a host.
+pub const UPSTREAM_SCHEME: &str = "upstream://";
+
+impl UpstreamTarget {
+    /// Parses a `proxy_pass` value such as `http://127.0.0.1:9000/api`, or
+    /// `upstream://backend/api` for a pool, where the name stands as host.
+    pub fn parse(url: &str) -> std::result::Result<Self, String> {
+        let pool = url.starts_with(UPSTREAM_SCHEME);
+        let rest = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix(UPSTREAM_SCHEME))
+            .ok_or_else(|| {
+                format!("proxy_pass '{}' must start with http:// or {}", url, UPSTREAM_SCHEME)
+            })?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, ""),
+        };
+
+        if authority.is_empty() {
+            return Err(format!("proxy_pass '{}' has no host", url));
+        }
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some(_) if pool => {
+                return Err(format!("proxy_pass '{}' names an upstream, it takes no port", url));
+            }
+            Some((h, p)) if !h.ends_with(']') || authority.starts_with('[') => {
+                let port = p
+                    .parse::<u16>()
+                    .map_err(|_| format!("proxy_pass '{}' has an invalid port", url))?;
+                (h, port)
+            }
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() || port == 0 {
+            return Err(format!("proxy_pass '{}' has an invalid authority", url));
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn resolve(&self) -> Option<SocketAddr> {
+        use std::net::ToSocketAddrs;
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        (host, self.port).to_socket_addrs().ok()?.next()
+    }
+
+    pub fn host_header(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Maps the client URL onto the upstream. Without a path in `proxy_pass`
+    /// the original target is forwarded untouched, otherwise the matched route
+    /// prefix is replaced by that path.
+    pub fn upstream_url(&self, url: &str, route_path: &str) -> String {
+        if self.path.is_empty() {
+            return url.to_string();
+        }
+        let rest = url.strip_prefix(route_path).unwrap_or(url);
+        if self.path.ends_with('/') {
+            format!("{}{}", self.path, rest.trim_start_matches('/'))
+        } else if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') {
+            format!("{}{}", self.path, rest)
+        } else {
+            format!("{}/{}", self.path, rest)
+        }
+    }
+}
+
+/// Where a proxied request goes: a reverse-proxy route or, on a forward
+/// proxy, the authority the client asked for.
+#[derive(Debug)]
+pub struct ProxyPlan {
+    pub target: UpstreamTarget,
+    /// Known up front for routes, whose hosts are resolved with the config.
+    pub addr: Option<SocketAddr>,
+    pub pool: Option<Arc<UpstreamPool>>,
+    pub url: String,
+    pub tunnel: bool,
+}
+
+impl ProxyPlan {
+    pub fn for_route(r_cfg: &RouteConfig, url: &str) -> Option<Self> {
+        let target = UpstreamTarget::parse(r_cfg.proxy_pass.as_deref()?).ok()?;
+        Some(Self {
+            url: target.upstream_url(url, &r_cfg.path),
+            target,
+            addr: r_cfg.proxy_addr,
+            pool: r_cfg.upstream.clone(),
+            tunnel: false,
+        })
+    }
+
+    /// Builds the plan for an absolute-form request or a CONNECT tunnel.
+    pub fn for_forward(request: &HttpRequest) -> Option<Self> {
+        let authority = request.authority.as_deref()?;
+        let tunnel = request.method == Method::CONNECT;
+        let target = UpstreamTarget::parse(&format!("http://{}", authority)).ok()?;
+        Some(Self {
+            url: request.url.clone(),
+            target,
+            addr: None,
+            pool: None,
+            tunnel,
+        })
+    }
+}
+
+/// Forward-proxy lookups running on resolver threads, across all workers.
+static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Lookups allowed at once before clients get 503; getaddrinfo can't be
+/// cancelled, so every slow name holds a thread until it answers.
+const MAX_LOOKUPS: usize = 64;
+
+/// Queues the request head and opens the non-blocking upstream socket, or
+/// starts looking the target up when its address isn't known yet.
+///
+/// Returns `true` when the response is already final (e.g. 502) and `false`
+/// when the proxy is in flight and the request body still has to be streamed.
+pub fn start_proxy(
+    conn: &mut HttpConnection,
+    plan: ProxyPlan,
+    s_cfg: &Arc<ServerConfig>,
+    poll: &Poll,
+    tokens: &mut TokenSlab,
+    client_token: Token,
+) -> bool {
+    // Pool routes pick a live peer, plain routes use the address resolved
+    // with the config and forward targets are looked up off the loop
+    let (addr, peer) = match &plan.pool {
+        Some(pool) => {
+            let client_ip = conn.stream.peer_addr().ok().map(|a| a.ip());
+            let Some(peer) = pool.select(client_ip) else {
+                handle_error(&mut conn.response, StatusCode::SERVICE_UNAVAILABLE, Some(s_cfg));
+                return true;
+            };
+            (Some(peer.addr), Some(PeerGuard::new(Arc::clone(pool), peer)))
+        }
+        None => (plan.addr, None),
+    };
+
+    // Body bytes read while the upstream is being reached queue behind the head
+    if plan.tunnel {
+        conn.proxy_buffer.clear();
+    } else {
+        conn.proxy_buffer = build_upstream_request(conn, &plan.target, &plan.url);
+    }
+    let head_only = conn.request.method == Method::HEAD;
+
+    let started = match addr {
+        Some(addr) => connect_upstream(
+            conn,
+            addr,
+            peer,
+            plan.tunnel,
+            head_only,
+            poll,
+            tokens,
+            client_token,
+        ),
+        None => start_lookup(conn, plan, head_only, poll, tokens, client_token),
+    };
+    if let Err(code) = started {
+        conn.proxy_buffer.clear();
+        handle_error(&mut conn.response, code, Some(s_cfg));
+        return true;
+    }
+    false
+}
+
+/// Connects to `addr` and puts the connection in its proxy or tunnel state.
+/// Fails with the status to answer when the upstream can't be reached.
+#[allow(clippy::too_many_arguments)]
+fn connect_upstream(
+    conn: &mut HttpConnection,
+    addr: SocketAddr,
+    peer: Option<PeerGuard>,
+    tunnel: bool,
+    head_only: bool,
+    poll: &Poll,
+    tokens: &mut TokenSlab,
+    client_token: Token,
+) -> std::result::Result<(), StatusCode> {
+    let mut upstream = match TcpStream::connect(addr) {
+        Ok(s) => s,
+        Err(_) => {
+            if let Some(guard) = &peer {
+                guard.record_failure();
+            }
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let token = tokens.insert(Slot::Pipe(client_token));
+    if poll
+        .registry()
+        .register(&mut upstream, token, Interest::READABLE | Interest::WRITABLE)
+        .is_err()
+    {
+        tokens.remove(token);
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    conn.proxy_token = Some(token);
+    if tunnel {
+        conn.action = ActiveAction::Tunnel {
+            upstream,
+            connected: false,
+            client_eof: false,
+        };
+    } else {
+        conn.action = ActiveAction::Proxy {
+            upstream,
+            parse_state: CgiParsingState::ReadHeaders,
+            header_buf: Vec::new(),
+            connected: false,
+            start_time: Instant::now(),
+            peer,
+            head_only,
+        };
+    }
+    Ok(())
+}
+
+/// Resolves the target on a thread of its own, so a slow or hostile name
+/// server never stalls the event loop. The thread writes the address to
+/// its end of a socket pair and closes it; the other end is registered
+/// like an upstream, and `finish_lookup` picks up from there.
+fn start_lookup(
+    conn: &mut HttpConnection,
+    plan: ProxyPlan,
+    head_only: bool,
+    poll: &Poll,
+    tokens: &mut TokenSlab,
+    client_token: Token,
+) -> std::result::Result<(), StatusCode> {
+    if LOOKUPS.fetch_add(1, Ordering::SeqCst) >= MAX_LOOKUPS {
+        LOOKUPS.fetch_sub(1, Ordering::SeqCst);
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let Ok((ours, theirs)) = UnixStream::pair() else {
+        LOOKUPS.fetch_sub(1, Ordering::SeqCst);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let target = plan.target;
+    let spawned = std::thread::Builder::new()
+        .name("resolver".to_string())
+        .spawn(move || {
+            if let Some(addr) = target.resolve() {
+                let answer = addr.to_string();
+                // SAFETY: the buffer outlives the call; MSG_NOSIGNAL turns a
+                // client that already gave up into EPIPE instead of SIGPIPE
+                unsafe {
+                    libc::send(
+                        theirs.as_raw_fd(),
+                        answer.as_ptr() as *const libc::c_void,
+                        answer.len(),
+                        libc::MSG_NOSIGNAL,
+                    )
+                };
+            }
+            LOOKUPS.fetch_sub(1, Ordering::SeqCst);
+        });
+    if spawned.is_err() {
+        LOOKUPS.fetch_sub(1, Ordering::SeqCst);
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    ours.set_nonblocking(true).ok();
+    let mut lookup = mio::net::UnixStream::from_std(ours);
+    let token = tokens.insert(Slot::Pipe(client_token));
+    if poll
+        .registry()
+        .register(&mut lookup, token, Interest::READABLE)
+        .is_err()
+    {
+        tokens.remove(token);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    conn.proxy_token = Some(token);
+    conn.action = ActiveAction::Resolve {
+        lookup,
+        answer: Vec::new(),
+        tunnel: plan.tunnel,
+        head_only,
+        start_time: Instant::now(),
+    };
+    Ok(())
+}
+
+/// Collects the resolver's answer and connects once it has hung up. A
+/// name that didn't resolve, or an upstream that refuses, is a 502.
+fn finish_lookup(
+    poll: &Poll,
+    client_token: Token,
+    conn: &mut HttpConnection,
+    tokens: &mut TokenSlab,
+) -> Result<()> {
+    let ActiveAction::Resolve {
+        ref mut lookup,
+        ref mut answer,
+        ..
+    } = conn.action
+    else {
+        return Ok(());
+    };
+    let mut buf = [0u8; 64];
+    loop {
+        match lookup.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => answer.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(_) => break,
+        }
+    }
+
+    let old_action = std::mem::replace(&mut conn.action, ActiveAction::None);
+    let ActiveAction::Resolve {
+        mut lookup,
+        answer,
+        tunnel,
+        head_only,
+        ..
+    } = old_action
+    else {
+        return Ok(());
+    };
+    let _ = poll.registry().deregister(&mut lookup);
+    if let Some(t) = conn.proxy_token.take() {
+        tokens.remove(t);
+    }
+
+    let addr = std::str::from_utf8(&answer)
+        .ok()
+        .and_then(|a| a.parse::<SocketAddr>().ok());
+    let started = match addr {
+        Some(addr) => connect_upstream(
+            conn,
+            addr,
+            None,
+            tunnel,
+            head_only,
+            poll,
+            tokens,
+            client_token,
+        ),
+        None => Err(StatusCode::BAD_GATEWAY),
+    };
+    if let Err(code) = started {
+        handle_error(&mut conn.response, code, conn.s_cfg.as_ref());
+        conn.write_buffer.clear();
+        conn.write_buffer
+            .extend_from_slice(&conn.response.to_bytes());
+        conn.proxy_buffer.clear();
+        conn.closed = true;
+    }
+
+    poll.registry().reregister(
+        &mut conn.stream,
+        client_token,
+        Interest::READABLE | Interest::WRITABLE,
+    )?;
+    Ok(())
+}
+
+pub fn build_upstream_request(conn: &HttpConnection, target: &UpstreamTarget, url: &str) -> Vec<u8> {
+    let req = &conn.request;
+    let is_chunked = req.is_chunked();
+    // A 1.0 client can't take the chunks a 1.1 upstream might send
+    let version = if req.accepts_chunked() { "HTTP/1.1" } else { "HTTP/1.0" };
+    let mut head = format!("{} {} {}\r\n", req.method, url, version);
+
+    for (k, v) in &req.headers {
+        if HOP_BY_HOP.contains(&k.as_str())
+            || k == "host"
+            || k == "content-length"
+            || k == "x-forwarded-for"
+        {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", k, v));
+    }
+
+    head.push_str(&format!("host: {}\r\n", target.host_header()));
+
+    let client_ip = conn
+        .stream
+        .peer_addr()
+        .map(|a| a.ip().to_string())
+        .unwrap_or_default();
+    let forwarded_for = match req.headers.get("x-forwarded-for") {
+        Some(prev) => format!("{}, {}", prev, client_ip),
+        None => client_ip,
+    };
+    head.push_str(&format!("x-forwarded-for: {}\r\n", forwarded_for));
+    if let Some(host) = req.headers.get("host") {
+        head.push_str(&format!("x-forwarded-host: {}\r\n", host));
+    }
+    head.push_str("x-forwarded-proto: http\r\n");
+
+    if is_chunked {
+        head.push_str("transfer-encoding: chunked\r\n");
+    } else if conn.body_remaining > 0 {
+        head.push_str(&format!("content-length: {}\r\n", conn.body_remaining));
+    }
+
+    // One upstream connection per request: the response ends at EOF.
+    head.push_str("connection: close\r\n\r\n");
+    head.into_bytes()
+}
+
+pub fn handle_proxy_event(
+    poll: &Poll,
+    event: &Event,
+    client_token: Token,
+    conn: &mut HttpConnection,
+    tokens: &mut TokenSlab,
+) -> Result<()> {
+    match conn.action {
+        ActiveAction::Resolve { .. } => return finish_lookup(poll, client_token, conn, tokens),
+        ActiveAction::Tunnel { .. } => {
+            return handle_tunnel_event(poll, event, client_token, conn, tokens);
+        }
+        _ => {}
+    }
+
+    // 1. Confirm the connect and push the request to the upstream
+    if (event.is_writable() || event.is_error()) && !write_to_upstream(conn, poll)? {
+        finish_proxy(conn, poll, tokens, true);
+    }
+
+    // 2. Stream the upstream response back to the client
+    if (event.is_readable() || event.is_read_closed()) && !conn.backend_paused {
+        read_upstream_response(conn, poll, tokens)?;
+    }
+
+    poll.registry().reregister(
+        &mut conn.stream,
+        client_token,
+        Interest::READABLE | Interest::WRITABLE,
+    )?;
+    Ok(())
+}
+
+/// Polls a non-blocking connect: `None` while it is still in progress.
+fn check_connect(upstream: &TcpStream) -> Option<bool> {
+    if !matches!(upstream.take_error(), Ok(None)) {
+        return Some(false);
+    }
+    match upstream.peer_addr() {
+        Ok(_) => Some(true),
+        Err(e) if e.kind() == ErrorKind::NotConnected => None,
+        Err(_) => Some(false),
+    }
+}
+
+/// Returns `false` when the upstream connection failed.
+fn write_to_upstream(conn: &mut HttpConnection, poll: &Poll) -> Result<bool> {
+    let (upstream, connected) = match conn.action {
+        ActiveAction::Proxy {
+            ref mut upstream,
+            ref mut connected,
+            ..
+        }
+        | ActiveAction::Tunnel {
+            ref mut upstream,
+            ref mut connected,
+            ..
+        } => (upstream, connected),
+        _ => return Ok(true),
+    };
+
+    if !*connected {
+        match check_connect(upstream) {
+            Some(true) => *connected = true,
+            Some(false) => return Ok(false),
+            None => return Ok(true),
+        }
+    }
+
+    while !conn.proxy_buffer.is_empty() {
+        match upstream.write(&conn.proxy_buffer) {
+            Ok(0) => return Ok(false),
+            Ok(n) => {
+                conn.proxy_buffer.drain(..n);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(_) => return Ok(false),
+        }
+    }
+
+    if conn.proxy_buffer.is_empty() {
+        conn.sync_backend_interest(poll);
+    }
+    Ok(true)
+}
+
+fn read_upstream_response(
+    conn: &mut HttpConnection,
+    poll: &Poll,
+    tokens: &mut TokenSlab,
+) -> Result<()> {
+    let (high, _) = conn.write_buffer_marks();
+    let mut outcome = None;
+    let mut pause = false;
+
+    if let ActiveAction::Proxy {
+        ref mut upstream,
+        ref mut parse_state,
+        ref mut header_buf,
+        connected,
+        head_only,
+        ref peer,
+        ..
+    } = conn.action
+    {
+        if !connected {
+            return Ok(());
+        }
+        let mut buf = [0u8; READ_BUF_SIZE];
+        loop {
+            match upstream.read(&mut buf) {
+                Ok(0) => {
+                    outcome = Some(false);
+                    break;
+                }
+                Ok(n) => {
+                    let awaiting_head = matches!(parse_state, CgiParsingState::ReadHeaders);
+                    if process_upstream_data(
+                        parse_state,
+                        header_buf,
+                        &mut conn.write_buffer,
+                        &buf[..n],
+                        ClientEnd {
+                            extra_headers: &conn.extra_headers,
+                            head_only,
+                            chunked_ok: conn.request.accepts_chunked(),
+                            keep_alive: &mut conn.keep_alive,
+                        },
+                    )
+                    .is_err()
+                    {
+                        outcome = Some(true);
+                        break;
+                    }
+                    // A parsed response head counts as a working peer
+                    if awaiting_head
+                        && !matches!(parse_state, CgiParsingState::ReadHeaders)
+                        && let Some(guard) = peer
+                    {
+                        guard.record_success();
+                    }
+                    if conn.write_buffer.len() >= high {
+                        pause = true;
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    outcome = Some(true);
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(failed) = outcome {
+        finish_proxy(conn, poll, tokens, failed);
+    } else if pause {
+        conn.pause_backend(poll);
+    }
+    Ok(())
+}
+
+/// Parses the upstream status line and headers, then forwards the body using
+/// the same framing rules as `process_cgi_stdout`. Replies to HEAD keep the
+/// upstream headers untouched and never carry a body.
+pub fn process_upstream_data(
+    parse_state: &mut CgiParsingState,
+    header_buf: &mut Vec<u8>,
+    write_buffer: &mut Vec<u8>,
+    new_data: &[u8],
+    mut client: ClientEnd,
+) -> std::result::Result<(), ParseError> {
+    match parse_state {
+        CgiParsingState::ReadHeaders => {
+            header_buf.extend_from_slice(new_data);
+
+            let Some(pos) = find_subsequence(header_buf, b"\r\n\r\n", 0) else {
+                if header_buf.len() > MAX_READ_DATA {
+                    return Err(ParseError::HeaderTooLong);
+                }
+                return Ok(());
+            };
+
+            let head = String::from_utf8_lossy(&header_buf[..pos]).into_owned();
+            let body_start = header_buf[pos + 4..].to_vec();
+            header_buf.clear();
+
+            let mut lines = head.split("\r\n");
+            let status_line = lines.next().unwrap_or("");
+            let mut parts = status_line.splitn(3, ' ');
+            let version = parts.next().unwrap_or("");
+            if !version.starts_with("HTTP/1.") {
+                return Err(ParseError::MalformedRequestLine);
+            }
+            let status = parts
+                .next()
+                .and_then(|s| s.parse::<u16>().ok())
+                .and_then(StatusCode::new)
+                .ok_or(ParseError::MalformedRequestLine)?;
+
+            // Interim heads (100 Continue, 103 Early Hints) are dropped and
+            // the final one is read from what follows them
+            if status.is_informational() && status != StatusCode::SWITCHING_PROTOCOLS {
+                header_buf.extend_from_slice(&body_start);
+                return process_upstream_data(parse_state, header_buf, write_buffer, &[], client);
+            }
+
+            // The upstream's own phrase is relayed as is
+            let mut res = HttpResponse::new(status);
+            res.status_text = parts.next().unwrap_or("").to_string();
+            res.headers.remove("Content-Length");
+
+            let mut is_upstream_chunked = false;
+            for line in lines {
+                if let Some((k, v)) = line.split_once(':') {
+                    let key = k.trim().to_lowercase();
+                    let val = v.trim();
+                    if key == "transfer-encoding" {
+                        is_upstream_chunked = val.to_lowercase().contains("chunked");
+                        continue;
+                    }
+                    if HOP_BY_HOP.contains(&key.as_str()) {
+                        continue;
+                    }
+                    res.append_header(&key, val);
+                }
+            }
+            for (k, v) in client.extra_headers {
+                res.set_header(k, v);
+            }
+
+            let head_only = client.head_only;
+            let bodyless = head_only
+                || status.is_informational()
+                || status == StatusCode::NO_CONTENT
+                || status == StatusCode::NOT_MODIFIED;
+            let unframed =
+                !bodyless && !is_upstream_chunked && !res.headers.contains_key("content-length");
+            if unframed && !client.chunked_ok {
+                client.close_delimit(&mut res);
+            }
+            let is_chunked = unframed && client.chunked_ok;
+
+            if head_only {
+                if is_upstream_chunked {
+                    res.set_header("transfer-encoding", "chunked");
+                }
+                *parse_state = CgiParsingState::DiscardBody;
+            } else if is_upstream_chunked {
+                res.set_header("transfer-encoding", "chunked");
+                *parse_state = CgiParsingState::StreamBody;
+            } else if is_chunked {
+                res.set_header("transfer-encoding", "chunked");
+                *parse_state = CgiParsingState::StreamBodyChuncked;
+            } else {
+                *parse_state = CgiParsingState::StreamBody;
+            }
+
+            write_buffer.extend_from_slice(&res.to_bytes_headers_only());
+
+            if !body_start.is_empty() && !head_only {
+                push_cgi_data(write_buffer, &body_start, is_chunked);
+            }
+        }
+        CgiParsingState::StreamBody => {
+            write_buffer.extend_from_slice(new_data);
+        }
+        CgiParsingState::StreamBodyChuncked => {
+            push_cgi_data(write_buffer, new_data, true);
+        }
+        CgiParsingState::DiscardBody => {}
+    }
+    Ok(())
+}
+
+/// Queues the request body bytes for the upstream, re-framing them as chunks
+/// when the client sent a chunked body.
+pub fn queue_proxy_body(conn: &mut HttpConnection, poll: &Poll, data: &[u8], chunked: bool) {
+    if chunked {
+        push_cgi_data(&mut conn.proxy_buffer, data, true);
+    } else {
+        conn.proxy_buffer.extend_from_slice(data);
+    }
+    wake_upstream(conn, poll);
+}
+
+pub fn wake_upstream(conn: &mut HttpConnection, poll: &Poll) {
+    conn.sync_backend_interest(poll);
+}
+
+/// Ends the proxied exchange. A clean upstream EOF closes the re-framed
+/// chunked body, while a failure before the headers turns into a 502 and a
+/// failure mid-body closes the client connection.
+fn finish_proxy(
+    conn: &mut HttpConnection,
+    poll: &Poll,
+    tokens: &mut TokenSlab,
+    failed: bool,
+) {
+    let old_action = std::mem::replace(&mut conn.action, ActiveAction::None);
+    if let ActiveAction::Proxy {
+        mut upstream,
+        parse_state,
+        peer,
+        ..
+    } = old_action
+    {
+        let _ = poll.registry().deregister(&mut upstream);
+        match parse_state {
+            CgiParsingState::ReadHeaders => {
+                if let Some(guard) = &peer {
+                    guard.record_failure();
+                }
+                handle_error(&mut conn.response, StatusCode::BAD_GATEWAY, conn.s_cfg.as_ref());
+                conn.write_buffer.clear();
+                conn.write_buffer
+                    .extend_from_slice(&conn.response.to_bytes());
+                conn.closed = true;
+            }
+            _ if failed => conn.closed = true,
+            CgiParsingState::StreamBodyChuncked => {
+                conn.write_buffer.extend_from_slice(b"0\r\n\r\n");
+            }
+            CgiParsingState::StreamBody | CgiParsingState::DiscardBody => {}
+        }
+    }
+    cleanup_proxy(tokens, conn);
+}
+
+pub fn cleanup_proxy(tokens: &mut TokenSlab, conn: &mut HttpConnection) {
+    if let Some(t) = conn.proxy_token.take() {
+        tokens.remove(t);
+    }
+    conn.proxy_buffer.clear();
+    conn.backend_paused = false;
+}
+
+pub fn force_proxy_timeout(
+    conn: &mut HttpConnection,
+    poll: &Poll,
+    tokens: &mut TokenSlab,
+) {
+    let old_action = std::mem::replace(&mut conn.action, ActiveAction::None);
+    if let ActiveAction::Resolve { mut lookup, .. } = old_action {
+        // The resolver thread runs on and finds nobody listening
+        let _ = poll.registry().deregister(&mut lookup);
+        handle_error(&mut conn.response, StatusCode::GATEWAY_TIMEOUT, conn.s_cfg.as_ref());
+        conn.response.set_header("Connection", "close");
+        conn.write_buffer.clear();
+        conn.write_buffer
+            .extend_from_slice(&conn.response.to_bytes());
+        conn.closed = true;
+    } else if let ActiveAction::Proxy {
+        mut upstream,
+        parse_state,
+        ..
+    } = old_action
+    {
+        let _ = poll.registry().deregister(&mut upstream);
+        match parse_state {
+            CgiParsingState::ReadHeaders => {
+                if let Some(s_cfg) = &conn.s_cfg {
+                    handle_error(&mut conn.response, StatusCode::GATEWAY_TIMEOUT, Some(s_cfg));
+                    conn.response.set_header("Connection", "close");
+                    conn.write_buffer.clear();
+                    conn.write_buffer
+                        .extend_from_slice(&conn.response.to_bytes());
+                }
+            }
+            CgiParsingState::StreamBodyChuncked => {
+                conn.write_buffer.extend_from_slice(b"0\r\n\r\n");
+            }
+            CgiParsingState::StreamBody | CgiParsingState::DiscardBody => {}
+        }
+        conn.closed = true;
+    }
+    cleanup_proxy(tokens, conn);
+}
+
+/// Splices bytes between the client and a CONNECT upstream. The client side
+/// is fed from `handle_read_phase` through `read_tunnel_client`.
+fn handle_tunnel_event(
+    poll: &Poll,
+    event: &Event,
+    client_token: Token,
+    conn: &mut HttpConnection,
+    tokens: &mut TokenSlab,
+) -> Result<()> {
+    let was_connected = matches!(conn.action, ActiveAction::Tunnel { connected: true, .. });
+
+    if (event.is_writable() || event.is_error()) && !write_to_upstream(conn, poll)? {
+        if was_connected {
+            conn.closed = true;
+        } else {
+            handle_error(&mut conn.response, StatusCode::BAD_GATEWAY, conn.s_cfg.as_ref());
+            conn.write_buffer
+                .extend_from_slice(&conn.response.to_bytes());
+            conn.closed = true;
+        }
+        close_tunnel(conn, poll, tokens);
+    } else if !was_connected
+        && matches!(conn.action, ActiveAction::Tunnel { connected: true, .. })
+    {
+        conn.write_buffer
+            .extend_from_slice(b"HTTP/1.1 200 Connection Established\r\n\r\n");
+    }
+    // Bytes the client pipelined after the CONNECT head belong to the tunnel,
+    // and whatever waited for room can move up now
+    forward_client_data(conn, poll);
+
+    let (high, _) = conn.write_buffer_marks();
+    if (event.is_readable() || event.is_read_closed())
+        && !conn.backend_paused
+        && let ActiveAction::Tunnel {
+            ref mut upstream,
+            connected: true,
+            ..
+        } = conn.action
+    {
+        let mut buf = [0u8; READ_BUF_SIZE];
+        loop {
+            match upstream.read(&mut buf) {
+                Ok(0) => {
+                    conn.closed = true;
+                    break;
+                }
+                Ok(n) => {
+                    conn.write_buffer.extend_from_slice(&buf[..n]);
+                    if conn.write_buffer.len() >= high {
+                        conn.pause_backend(poll);
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    conn.closed = true;
+                    break;
+                }
+            }
+        }
+        if conn.closed {
+            close_tunnel(conn, poll, tokens);
+        }
+    }
+
+    // Registering the client anew reports what it sent while the tunnel was
+    // full; after its EOF only the writes are left
+    let interest = if matches!(conn.action, ActiveAction::Tunnel { client_eof: true, .. }) {
+        Interest::WRITABLE
+    } else {
+        Interest::READABLE | Interest::WRITABLE
+    };
+    poll.registry()
+        .reregister(&mut conn.stream, client_token, interest)?;
+    Ok(())
+}
+
+/// Reads from a tunnel client only while less than `MAX_READ_DATA` bytes
+/// wait for the upstream, so a slow upstream pushes back on the client
+/// instead of growing the buffers.
+pub fn read_tunnel_client(conn: &mut HttpConnection, poll: &Poll) {
+    let waiting = conn.proxy_buffer.len() + conn.request.buffer.len();
+    if waiting < MAX_READ_DATA
+        && !matches!(conn.action, ActiveAction::Tunnel { client_eof: true, .. })
+    {
+        let eof = conn.read_data().unwrap_or(true);
+        if let ActiveAction::Tunnel {
+            ref mut client_eof, ..
+        } = conn.action
+        {
+            *client_eof = eof;
+        }
+    }
+    forward_client_data(conn, poll);
+}
+
+/// Moves what the client sent into the tunnel once it is established, up to
+/// `MAX_READ_DATA` queued bytes. When the client is done and all of it has
+/// been flushed, the upstream's write side is shut down.
+fn forward_client_data(conn: &mut HttpConnection, poll: &Poll) {
+    let ActiveAction::Tunnel {
+        ref upstream,
+        connected: true,
+        client_eof,
+    } = conn.action
+    else {
+        return;
+    };
+    let room = MAX_READ_DATA.saturating_sub(conn.proxy_buffer.len());
+    let n = room.min(conn.request.buffer.len());
+    if n > 0 {
+        let data = conn.request.buffer.take_front(n);
+        conn.request.cursor = 0;
+        conn.proxy_buffer.extend_from_slice(&data);
+        wake_upstream(conn, poll);
+    } else if client_eof && conn.proxy_buffer.is_empty() && conn.request.buffer.is_empty() {
+        let _ = upstream.shutdown(std::net::Shutdown::Write);
+    }
+}
+
+fn close_tunnel(conn: &mut HttpConnection, poll: &Poll, tokens: &mut TokenSlab) {
+    let old_action = std::mem::replace(&mut conn.action, ActiveAction::None);
+    if let ActiveAction::Tunnel { mut upstream, .. } = old_action {
+        let _ = poll.registry().deregister(&mut upstream);
+    }
+    cleanup_proxy(tokens, conn);
+}
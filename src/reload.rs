@@ -0,0 +1,206 @@
+use crate::prelude::*;
+use proxy_log::{errors, warn};
+use std::sync::Mutex;
+
+/// Reads and validates the configuration again, with errors as plain text.
+pub type ConfigLoader = dyn Fn() -> std::result::Result<AppConfig, String> + Send + Sync;
+
+/// What a reload hands every worker.
+#[derive(Debug)]
+pub struct Reloaded {
+    pub groups: Vec<ListenerGroup>,
+    pub pools: Vec<Arc<UpstreamPool>>,
+    pub shutdown_timeout: Duration,
+}
+
+/// Loads the configuration once per SIGHUP and shares the outcome, so all
+/// workers switch to the same snapshot however many of them ask.
+pub struct Reloader {
+    load: Box<ConfigLoader>,
+    workers: usize,
+    /// Reload generation and its outcome, `None` when it was rejected.
+    latest: Mutex<Option<(usize, Option<Arc<Reloaded>>)>>,
+}
+
+impl Reloader {
+    /// `workers` is the running thread count, which a reload can't change.
+    pub fn new<F>(workers: usize, load: F) -> Self
+    where
+        F: Fn() -> std::result::Result<AppConfig, String> + Send + Sync + 'static,
+    {
+        Self {
+            load: Box::new(load),
+            workers,
+            latest: Mutex::new(None),
+        }
+    }
+
+    /// The configuration for reload `generation`, loaded by whichever worker
+    /// asks first. `None` keeps the current one; the reason is logged once.
+    /// Workers share their pools, so `running` is the same for all of them.
+    pub fn get(&self, generation: usize, running: &[Arc<UpstreamPool>]) -> Option<Arc<Reloaded>> {
+        let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((loaded, outcome)) = &*latest
+            && *loaded >= generation
+        {
+            return outcome.clone();
+        }
+
+        let outcome = match self.load_groups(running) {
+            Ok(reloaded) => Some(Arc::new(reloaded)),
+            Err(e) => {
+                errors!("Reload rejected, keeping the running configuration: {}", e);
+                None
+            }
+        };
+        *latest = Some((generation, outcome.clone()));
+        outcome
+    }
+
+    fn load_groups(&self, running: &[Arc<UpstreamPool>]) -> std::result::Result<Reloaded, String> {
+        let mut config = (self.load)()?;
+        config.keep_pools(running);
+        if config.workers != self.workers {
+            warn!(
+                "workers changed from {} to {}; this takes a restart.",
+                self.workers, config.workers
+            );
+        }
+        let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+        Ok(Reloaded {
+            groups: Server::listener_groups(config.servers).map_err(|e| e.to_string())?,
+            pools: config.pools,
+            shutdown_timeout,
+        })
+    }
+}
+
+impl fmt::Debug for Reloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reloader")
+            .field("workers", &self.workers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Applies a SIGHUP that this worker hasn't seen yet. A draining worker has
+/// no listeners left to update.
+pub fn check(server: &mut Server, poll: &Poll) {
+    let received = reload_signals();
+    if received == server.reloads_seen || server.drain.is_some() {
+        return;
+    }
+    server.reloads_seen = received;
+
+    let Some(reloader) = server.reloader.clone() else {
+        warn!("Worker {}: SIGHUP ignored, no configuration source.", server.worker_id);
+        return;
+    };
+    let Some(reloaded) = reloader.get(received, &server.upstreams) else {
+        return;
+    };
+    match apply(server, poll, &reloaded) {
+        Ok((added, removed)) => {
+            info!(
+                "Worker {}: configuration reloaded, {} listener(s) added, {} closed.",
+                server.worker_id, added, removed
+            );
+        }
+        Err(e) => {
+            errors!(
+                "Worker {}: reload failed, keeping the running configuration: {}",
+                server.worker_id,
+                e
+            );
+        }
+    }
+}
+
+/// Swaps the virtual hosts of every listener. Removed addresses are closed
+/// before new ones are bound, since a port moving between `0.0.0.0` and a
+/// specific address would otherwise clash with itself; if a bind then fails
+/// they are bound again, so the worker stays as it was. Open connections
+/// keep the configuration they were accepted with.
+/// Returns the number of listeners added and closed.
+pub fn apply(server: &mut Server, poll: &Poll, reloaded: &Reloaded) -> Result<(usize, usize)> {
+    let mut current = HashMap::new();
+    for (token, slot) in server.tokens.iter() {
+        if let Slot::Listener(listener, _) = slot {
+            current.insert(listener.local_addr()?, token);
+        }
+    }
+
+    let wanted: HashMap<_, _> = reloaded.groups.iter().cloned().collect();
+    let mut closed = Vec::new();
+    for (addr, token) in &current {
+        if !wanted.contains_key(addr)
+            && let Some(Slot::Listener(mut listener, config_list)) = server.tokens.remove(*token)
+        {
+            let _ = poll.registry().deregister(&mut listener);
+            closed.push((*addr, config_list));
+        }
+    }
+
+    let mut fresh = Vec::new();
+    for (addr, config_list) in &reloaded.groups {
+        if current.contains_key(addr) {
+            continue;
+        }
+        match bind(server.reuse_port, *addr) {
+            Ok(listener) => fresh.push((listener, config_list.clone())),
+            Err(e) => {
+                drop(fresh);
+                for (addr, config_list) in closed {
+                    match bind(server.reuse_port, addr) {
+                        Ok(listener) => listen(server, poll, listener, config_list)?,
+                        Err(e) => errors!(
+                            "Worker {}: cannot listen on {} again: {}",
+                            server.worker_id,
+                            addr,
+                            e
+                        ),
+                    }
+                }
+                return Err(e.into());
+            }
+        }
+    }
+
+    for (addr, token) in current {
+        if let Some(config_list) = wanted.get(&addr)
+            && let Some(Slot::Listener(_, list)) = server.tokens.get_mut(token)
+        {
+            *list = config_list.clone();
+        }
+    }
+
+    let added = fresh.len();
+    for (listener, config_list) in fresh {
+        listen(server, poll, listener, config_list)?;
+    }
+
+    server.upstreams = reloaded.pools.clone();
+    server.shutdown_timeout = reloaded.shutdown_timeout;
+    Ok((added, closed.len()))
+}
+
+fn bind(reuse_port: bool, addr: SocketAddr) -> io::Result<TcpListener> {
+    if reuse_port {
+        bind_reuseport(addr)
+    } else {
+        TcpListener::bind(addr)
+    }
+}
+
+fn listen(
+    server: &mut Server,
+    poll: &Poll,
+    mut listener: TcpListener,
+    config_list: Vec<Arc<ServerConfig>>,
+) -> Result<()> {
+    let token = server.tokens.vacant_token();
+    poll.registry()
+        .register(&mut listener, token, Interest::READABLE)?;
+    server.tokens.insert(Slot::Listener(listener, config_list));
+    Ok(())
+}
@@ -1,34 +1,81 @@
 use crate::prelude::*;
 
+/// Listening address and the virtual hosts served on it.
+pub type ListenerGroup = (SocketAddr, Vec<Arc<ServerConfig>>);
+
 pub struct Server {
-    pub listeners: HashMap<Token, (TcpListener, Vec<Arc<ServerConfig>>)>,
-    pub connections: HashMap<Token, HttpConnection>,
-    pub cgi_to_client: HashMap<Token, Token>,
-    pub next_token: usize,
+    /// Listeners, clients, CGI pipes, upstream sockets and health probes,
+    /// indexed by their `Poll` token.
+    pub tokens: TokenSlab,
+    /// Next deadline of every client connection, keyed by its token.
+    pub timers: TimerHeap,
+    /// Next run of the health checks, zombie reaping and session sweep.
+    pub next_housekeeping: Instant,
     pub session_store: SessionStore,
     pub zombie_purgatory: Vec<Child>,
+    pub upstreams: Vec<Arc<UpstreamPool>>,
+    /// Index of this event loop; worker 0 also runs the upstream health checks.
+    pub worker_id: usize,
+    /// How long in-flight requests may run on after a shutdown signal.
+    pub shutdown_timeout: Duration,
+    /// Shutdown signals already acted upon.
+    pub shutdowns_seen: usize,
+    /// Set once a shutdown signal arrived; the loop exits when it is done.
+    pub drain: Option<Drain>,
+    /// Source of the configuration on SIGHUP, shared by all workers.
+    pub reloader: Option<Arc<Reloader>>,
+    /// SIGHUPs already acted upon.
+    pub reloads_seen: usize,
+    /// Whether listeners are bound with `SO_REUSEPORT`, reloads included.
+    pub reuse_port: bool,
 }
 
 impl Server {
     pub fn new(config: AppConfig, poll: &Poll) -> Result<Self> {
-        let mut server = Self {
-            listeners: HashMap::new(),
-            connections: HashMap::new(),
-            cgi_to_client: HashMap::new(),
-            next_token: 0,
-            session_store: SessionStore::new(10),
-            zombie_purgatory: Vec::new(),
-        };
-        server.setup_listeners(config, poll)?;
+        let groups = Self::listener_groups(config.servers)?;
+        let mut server = Self::worker(0, config.pools, SessionStore::new(10));
+        server.shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+        server.setup_listeners(&groups, false, poll)?;
         Ok(server)
     }
 
-    pub fn setup_listeners(&mut self, config: AppConfig, poll: &Poll) -> Result<()> {
-        info!("Initializing server listeners...");
+    fn worker(worker_id: usize, upstreams: Vec<Arc<UpstreamPool>>, session_store: SessionStore) -> Self {
+        Self {
+            tokens: TokenSlab::new(),
+            timers: TimerHeap::new(),
+            next_housekeeping: Instant::now(),
+            session_store,
+            zombie_purgatory: Vec::new(),
+            upstreams,
+            worker_id,
+            shutdown_timeout: Duration::ZERO,
+            shutdowns_seen: 0,
+            drain: None,
+            reloader: None,
+            reloads_seen: 0,
+            reuse_port: false,
+        }
+    }
 
+    /// Registers a self-pipe so SIGTERM and SIGINT drain this event loop
+    /// and make `run` return, instead of killing the process mid-request,
+    /// and SIGHUP reloads the configuration through `reloader`.
+    pub fn watch_signals(&mut self, poll: &Poll) -> Result<()> {
+        let mut pipe = SignalPipe::new()?;
+        let token = self.tokens.vacant_token();
+        poll.registry()
+            .register(&mut pipe, token, Interest::READABLE)?;
+        self.tokens.insert(Slot::Signal(pipe));
+        self.shutdowns_seen = shutdown_signals();
+        self.reloads_seen = reload_signals();
+        Ok(())
+    }
+
+    /// Groups the virtual hosts by the address they listen on.
+    pub fn listener_groups(servers: Vec<ServerConfig>) -> Result<Vec<ListenerGroup>> {
         let mut groups: HashMap<(String, u16), Vec<Arc<ServerConfig>>> = HashMap::new();
 
-        for s_cfg in config.servers {
+        for s_cfg in servers {
             let shared_s_cfg = Arc::new(s_cfg);
             for &port in &shared_s_cfg.ports {
                 let key = (shared_s_cfg.host_header(), port);
@@ -39,81 +86,203 @@ impl Server {
             }
         }
 
-        for ((host, port), config_list) in groups {
-            let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
-            let token = Token(self.next_token);
+        groups
+            .into_iter()
+            .map(|((host, port), config_list)| {
+                let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
+                Ok((addr, config_list))
+            })
+            .collect()
+    }
+
+    pub fn setup_listeners(&mut self, groups: &[ListenerGroup], reuse_port: bool, poll: &Poll) -> Result<()> {
+        info!("Initializing server listeners...");
+        self.reuse_port = reuse_port;
+
+        for (addr, config_list) in groups {
+            let token = self.tokens.vacant_token();
 
-            let mut listener = TcpListener::bind(addr)?;
+            let mut listener = if reuse_port {
+                bind_reuseport(*addr)?
+            } else {
+                TcpListener::bind(*addr)?
+            };
             poll.registry()
                 .register(&mut listener, token, Interest::READABLE)?;
-            self.listeners.insert(token, (listener, config_list));
+            self.tokens
+                .insert(Slot::Listener(listener, config_list.clone()));
+        }
+
+        Ok(())
+    }
 
-            self.next_token += 1;
+    /// Runs `config.workers` event loops, each on its own thread with its
+    /// own `Poll`, connections and `SO_REUSEPORT` listeners. Sessions and
+    /// upstream state are shared. Every socket is bound before any thread
+    /// starts, so a bad address fails here rather than in a worker.
+    pub fn run_workers(config: AppConfig) -> Result<()> {
+        Self::serve(config, None)
+    }
+
+    /// `run_workers`, reloading the configuration from `reloader` on SIGHUP.
+    pub fn serve(config: AppConfig, reloader: Option<Arc<Reloader>>) -> Result<()> {
+        let workers = config.workers.max(1);
+        if workers == 1 {
+            let poll = Poll::new()?;
+            let mut server = Server::new(config, &poll)?;
+            server.reloader = reloader;
+            server.watch_signals(&poll)?;
+            return server.run(poll);
         }
 
+        let groups = Self::listener_groups(config.servers)?;
+        let session_store = SessionStore::new(10);
+        let mut loops = Vec::with_capacity(workers);
+        for id in 0..workers {
+            let poll = Poll::new()?;
+            let mut server = Self::worker(id, config.pools.clone(), session_store.clone());
+            server.shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+            server.reloader = reloader.clone();
+            server.setup_listeners(&groups, true, &poll)?;
+            server.watch_signals(&poll)?;
+            loops.push((server, poll));
+        }
+
+        let handles = loops
+            .into_iter()
+            .map(|(mut server, poll)| {
+                std::thread::Builder::new()
+                    .name(format!("worker-{}", server.worker_id))
+                    // Errors are boxed without `Send`, so only the message crosses threads
+                    .spawn(move || server.run(poll).map_err(|e| e.to_string()))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        info!("Started {} worker threads.", workers);
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => return Err("worker thread panicked".into()),
+            }
+        }
         Ok(())
     }
 
+    /// Buffer usage of every client connection of this worker.
+    pub fn buffer_usage(&self) -> Vec<(Token, BufferUsage)> {
+        self.tokens
+            .iter()
+            .filter_map(|(token, slot)| match slot {
+                Slot::Client(conn) => Some((token, conn.buffer_usage())),
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn run(&mut self, mut poll: Poll) -> Result<()> {
         let mut events = Events::with_capacity(1024);
 
         info!(
             "Server running. Monitoring {} listeners...",
-            self.listeners.len()
+            self.tokens
+                .iter()
+                .filter(|(_, slot)| matches!(slot, Slot::Listener(..)))
+                .count()
         );
 
         loop {
-            poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+            let timeout = timeouts::poll_timeout(self);
+            match poll.poll(&mut events, Some(timeout)) {
+                // A signal landed on this thread; its pipe wakes the next poll
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => result?,
+            }
             timeouts::process(self, &poll);
 
             for event in events.iter() {
                 let token = event.token();
 
-                if let Some(&client_token) = self.cgi_to_client.get(&token) {
-                    if let Some(conn) = self.connections.get_mut(&client_token)
-                        && let Err(e) = handle_cgi_event(
-                            &mut self.session_store,
-                            &poll,
-                            event,
-                            token,
-                            client_token,
-                            conn,
-                            &mut self.cgi_to_client,
-                        )
-                    {
-                        eprintln!("Cgi Error: {}", e);
-                        conn.closed = true;
+                match self.tokens.get(token) {
+                    // 1. CGI pipes and upstream sockets act on their client
+                    Some(&Slot::Pipe(client_token)) => {
+                        self.handle_pipe(&poll, event, token, client_token)
                     }
-                    continue;
-                }
-
-                // 1. Handle New Connections
-                if self.listeners.contains_key(&token) {
-                    if let Err(e) = self.handle_accept(&mut poll, token) {
-                        eprintln!("Accept Error: {}", e);
+                    Some(Slot::Probe(_)) => upstream::handle_probe_event(self, &poll, event, token),
+                    Some(Slot::Signal(_)) => self.handle_signal(&poll, token),
+                    // 2. Handle New Connections
+                    Some(Slot::Listener(..)) => {
+                        if let Err(e) = self.handle_accept(&mut poll, token) {
+                            eprintln!("Accept Error: {}", e);
+                        }
                     }
+                    // 3. Handle Existing Connection Data
+                    Some(Slot::Client(_)) => {
+                        if let Err(e) = self.handle_connection(&poll, event, token) {
+                            eprintln!("Connection Error: {}", e);
+                        }
+                    }
+                    // Stale event for a token that was freed in this batch
+                    Some(Slot::Lent) | None => {}
                 }
-                // 2. Handle Existing Connection Data
-                else if let Err(e) = self.handle_connection(&poll, event, token) {
-                    eprintln!("Connection Error: {}", e);
-                    self.connections.remove(&token);
-                }
+            }
+
+            if shutdown::finished(self, &poll) {
+                shutdown::complete(self);
+                return Ok(());
             }
         }
     }
 
-    pub fn handle_accept(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
-        let (listener, config_list) = self.listeners.get_mut(&token).unwrap();
+    fn handle_signal(&mut self, poll: &Poll, token: Token) {
+        if let Some(Slot::Signal(pipe)) = self.tokens.get_mut(token) {
+            pipe.drain();
+        }
+        reload::check(self, poll);
+        shutdown::check(self, poll);
+    }
 
+    fn handle_pipe(&mut self, poll: &Poll, event: &Event, token: Token, client_token: Token) {
+        let Some(mut conn) = self.tokens.lend(client_token) else {
+            return;
+        };
+        if conn.proxy_token == Some(token) {
+            if let Err(e) = handle_proxy_event(poll, event, client_token, &mut conn, &mut self.tokens) {
+                eprintln!("Proxy Error: {}", e);
+                conn.closed = true;
+            }
+        } else if let Err(e) = handle_cgi_event(
+            &mut self.session_store,
+            poll,
+            event,
+            token,
+            client_token,
+            &mut conn,
+            &mut self.tokens,
+        ) {
+            eprintln!("Cgi Error: {}", e);
+            conn.closed = true;
+        }
+        self.timers
+            .schedule(client_token, timeouts::next_deadline(&conn, Instant::now()));
+        self.tokens.restore(client_token, conn);
+    }
+
+    pub fn handle_accept(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
         loop {
+            let Some(Slot::Listener(listener, config_list)) = self.tokens.get(token) else {
+                return Ok(());
+            };
             match listener.accept() {
                 Ok((mut stream, _)) => {
-                    let client_token = Token(self.next_token);
-                    self.next_token += 1;
+                    let conn_configs = config_list.clone();
+                    let client_token = self.tokens.vacant_token();
                     poll.registry()
                         .register(&mut stream, client_token, Interest::READABLE)?;
-                    let conn = HttpConnection::new(stream, config_list.clone());
-                    self.connections.insert(client_token, conn);
+                    let conn = HttpConnection::new(stream, conn_configs);
+                    self.timers
+                        .schedule(client_token, timeouts::next_deadline(&conn, Instant::now()));
+                    self.tokens.insert(Slot::Client(Box::new(conn)));
                 }
                 Err(e) if e.kind() == ErrorKind::WouldBlock => break,
                 Err(e) => return Err(e.into()),
@@ -122,21 +291,47 @@ impl Server {
         Ok(())
     }
 
+    /// Runs the read and write phases on a client taken out of the arena,
+    /// then puts it back or tears it down.
     pub fn handle_connection(&mut self, poll: &Poll, event: &Event, token: Token) -> Result<()> {
-        let conn = match self.connections.get_mut(&token) {
-            Some(c) => c,
-            None => return Ok(()),
+        let Some(mut conn) = self.tokens.lend(token) else {
+            return Ok(());
         };
         conn.touch();
 
+        let result = self.drive_connection(&mut conn, poll, event, token);
+
+        // Nothing left to finish while shutting down
+        if self.drain.is_some() && conn.is_idle() {
+            conn.closed = true;
+        }
+
+        // PHASE 3: Connection Lifecycle (Keep-Alive or Close)
+        if result.is_err() || conn.should_close() {
+            HttpConnection::terminate_connection(self, token, conn);
+        } else {
+            self.timers
+                .schedule(token, timeouts::next_deadline(&conn, Instant::now()));
+            self.tokens.restore(token, conn);
+        }
+
+        result
+    }
+
+    fn drive_connection(
+        &mut self,
+        conn: &mut HttpConnection,
+        poll: &Poll,
+        event: &Event,
+        token: Token,
+    ) -> Result<()> {
         // PHASE 1: Handle Incoming Data
         if !conn.closed && event.is_readable() {
             HttpConnection::handle_read_phase(
                 conn,
                 poll,
                 token,
-                &mut self.next_token,
-                &mut self.cgi_to_client,
+                &mut self.tokens,
                 &mut self.session_store,
             )?;
         }
@@ -147,17 +342,11 @@ impl Server {
                 conn,
                 poll,
                 token,
-                &mut self.next_token,
-                &mut self.cgi_to_client,
+                &mut self.tokens,
                 &mut self.session_store,
             )?;
         }
 
-        // PHASE 3: Connection Lifecycle (Keep-Alive or Close)
-        if conn.should_close() {
-            HttpConnection::terminate_connection(self, token);
-        }
-
         Ok(())
     }
 }
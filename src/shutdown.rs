@@ -0,0 +1,142 @@
+use crate::prelude::*;
+
+/// Progress of a graceful shutdown, kept for the closing summary.
+#[derive(Debug)]
+pub struct Drain {
+    pub started: Instant,
+    /// When whatever is still open gets cut off.
+    pub deadline: Instant,
+    /// Connections closed right away as they had nothing in flight.
+    pub idle: usize,
+    pub in_flight: usize,
+    /// In-flight connections still open at the deadline.
+    pub cut_off: usize,
+}
+
+/// Acts on a SIGTERM or SIGINT this worker hasn't seen yet. The first one
+/// starts draining, a second one ends the drain at once.
+pub fn check(server: &mut Server, poll: &Poll) {
+    let received = shutdown_signals();
+    if received == server.shutdowns_seen {
+        return;
+    }
+    server.shutdowns_seen = received;
+
+    match &mut server.drain {
+        Some(drain) => {
+            info!("Worker {}: second signal, closing remaining connections.", server.worker_id);
+            drain.deadline = Instant::now();
+        }
+        None => begin(server, poll),
+    }
+}
+
+/// Stops accepting and closes the connections that sit between requests.
+/// The rest are told not to keep alive and close once answered; a request
+/// head still arriving is read to the end and answered first.
+pub fn begin(server: &mut Server, poll: &Poll) {
+    let listeners: Vec<Token> = server
+        .tokens
+        .iter()
+        .filter(|(_, slot)| matches!(slot, Slot::Listener(..)))
+        .map(|(token, _)| token)
+        .collect();
+    for token in listeners {
+        if let Some(Slot::Listener(mut listener, _)) = server.tokens.remove(token) {
+            let _ = poll.registry().deregister(&mut listener);
+        }
+    }
+
+    let now = Instant::now();
+    let mut drain = Drain {
+        started: now,
+        deadline: now + server.shutdown_timeout,
+        idle: 0,
+        in_flight: 0,
+        cut_off: 0,
+    };
+    for token in server.tokens.client_tokens() {
+        let Some(mut conn) = server.tokens.lend(token) else {
+            continue;
+        };
+        if conn.is_idle() {
+            drain.idle += 1;
+            let _ = poll.registry().deregister(&mut conn.stream);
+            HttpConnection::terminate_connection(server, token, conn);
+        } else {
+            drain.in_flight += 1;
+            conn.draining = true;
+            let head_in_flight = match conn.request.state {
+                ParsingState::RequestLine => !conn.request.buffer.is_empty(),
+                ParsingState::Headers => true,
+                _ => false,
+            };
+            // That head negotiates keep-alive itself, a response not
+            // started yet still says so in its headers
+            if !head_in_flight {
+                conn.keep_alive = false;
+                conn.extra_headers
+                    .retain(|(k, _)| k != "connection" && k != "keep-alive");
+                conn.extra_headers
+                    .push(("connection".to_string(), "close".to_string()));
+            }
+            server.tokens.restore(token, conn);
+        }
+    }
+    info!(
+        "Worker {}: shutting down, draining {} connection(s) for up to {}s.",
+        server.worker_id,
+        drain.in_flight,
+        server.shutdown_timeout.as_secs()
+    );
+    server.drain = Some(drain);
+}
+
+/// Whether a draining worker may exit: every connection is gone, or the
+/// deadline passed and the stragglers have been closed.
+pub fn finished(server: &mut Server, poll: &Poll) -> bool {
+    let Some(deadline) = server.drain.as_ref().map(|d| d.deadline) else {
+        return false;
+    };
+    let clients = server.tokens.client_tokens();
+    if clients.is_empty() {
+        return true;
+    }
+    if Instant::now() < deadline {
+        return false;
+    }
+
+    for token in clients {
+        if let Some(mut conn) = server.tokens.lend(token) {
+            let _ = poll.registry().deregister(&mut conn.stream);
+            HttpConnection::terminate_connection(server, token, conn);
+            if let Some(drain) = &mut server.drain {
+                drain.cut_off += 1;
+            }
+        }
+    }
+    true
+}
+
+/// Kills and reaps the CGI processes left behind, then logs the summary.
+pub fn complete(server: &mut Server) {
+    let mut reaped = 0;
+    for mut child in server.zombie_purgatory.drain(..) {
+        let _ = child.kill();
+        if child.wait().is_ok() {
+            reaped += 1;
+        }
+    }
+
+    if let Some(drain) = &server.drain {
+        info!(
+            "Worker {} stopped after {:.1}s: {} idle closed, {} drained, {} cut off, {} CGI process(es) reaped.",
+            server.worker_id,
+            drain.started.elapsed().as_secs_f64(),
+            drain.idle,
+            drain.in_flight - drain.cut_off,
+            drain.cut_off,
+            reaped
+        );
+    }
+}
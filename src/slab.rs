@@ -0,0 +1,124 @@
+use crate::prelude::*;
+
+/// What a registered token stands for.
+pub enum Slot {
+    Listener(TcpListener, Vec<Arc<ServerConfig>>),
+    Client(Box<HttpConnection>),
+    /// A client taken out by `lend` while a handler works on it; the token
+    /// stays reserved until it is restored or removed.
+    Lent,
+    /// A CGI pipe or proxy upstream socket, owned by the client token.
+    Pipe(Token),
+    Probe(HealthProbe),
+    Signal(SignalPipe),
+}
+
+/// One arena for every token registered with `Poll`. Indices double as
+/// tokens, and freed indices are reused, so a long-running server keeps a
+/// token space as large as its peak load and every dispatch is an index.
+#[derive(Default)]
+pub struct TokenSlab {
+    entries: Vec<Option<Slot>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl TokenSlab {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The token the next `insert` will hand out, for registering a socket
+    /// before it moves into its slot.
+    pub fn vacant_token(&self) -> Token {
+        Token(self.free.last().copied().unwrap_or(self.entries.len()))
+    }
+
+    pub fn insert(&mut self, slot: Slot) -> Token {
+        self.len += 1;
+        match self.free.pop() {
+            Some(idx) => {
+                self.entries[idx] = Some(slot);
+                Token(idx)
+            }
+            None => {
+                self.entries.push(Some(slot));
+                Token(self.entries.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, token: Token) -> Option<Slot> {
+        let slot = self.entries.get_mut(token.0)?.take()?;
+        self.len -= 1;
+        self.free.push(token.0);
+        Some(slot)
+    }
+
+    pub fn get(&self, token: Token) -> Option<&Slot> {
+        self.entries.get(token.0)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, token: Token) -> Option<&mut Slot> {
+        self.entries.get_mut(token.0)?.as_mut()
+    }
+
+    pub fn client_mut(&mut self, token: Token) -> Option<&mut HttpConnection> {
+        match self.get_mut(token) {
+            Some(Slot::Client(conn)) => Some(conn),
+            _ => None,
+        }
+    }
+
+    /// Takes a client out of the arena so it can be handled alongside
+    /// `&mut self`; pair with `restore`, or `remove` to drop the token.
+    pub fn lend(&mut self, token: Token) -> Option<Box<HttpConnection>> {
+        let slot = self.entries.get_mut(token.0)?;
+        match slot.take() {
+            Some(Slot::Client(conn)) => {
+                *slot = Some(Slot::Lent);
+                Some(conn)
+            }
+            other => {
+                *slot = other;
+                None
+            }
+        }
+    }
+
+    pub fn restore(&mut self, token: Token, conn: Box<HttpConnection>) {
+        if let Some(slot @ Some(Slot::Lent)) = self.entries.get_mut(token.0) {
+            *slot = Some(Slot::Client(conn));
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Token, &Slot)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| slot.as_ref().map(|s| (Token(idx), s)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Token, &mut Slot)> {
+        self.entries
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, slot)| slot.as_mut().map(|s| (Token(idx), s)))
+    }
+
+    pub fn client_tokens(&self) -> Vec<Token> {
+        self.iter()
+            .filter(|(_, slot)| matches!(slot, Slot::Client(_)))
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    /// Number of occupied tokens.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
@@ -1,52 +1,294 @@
 use crate::prelude::*;
 
+/// Period of the housekeeping pass while probes or zombies need polling.
+const HOUSEKEEPING_TICK: Duration = Duration::from_secs(1);
+
+/// Deadlines never land closer than this, so a check that fires a hair
+/// early can't spin the loop with zero timeouts.
+const TIMER_SLACK: Duration = Duration::from_millis(10);
+
+/// How long `poll` may sleep: until the next connection deadline, the
+/// next housekeeping pass or the end of a shutdown drain, whichever is first.
+pub fn poll_timeout(server: &mut Server) -> Duration {
+    let mut next = match server.timers.next_deadline() {
+        Some(at) => at.min(server.next_housekeeping),
+        None => server.next_housekeeping,
+    };
+    if let Some(drain) = &server.drain {
+        next = next.min(drain.deadline);
+    }
+    next.saturating_duration_since(Instant::now())
+}
+
+/// Runs the checks of every connection whose deadline has passed, then
+/// the periodic housekeeping when it is due.
 pub fn process(server: &mut Server, poll: &Poll) {
     let now = Instant::now();
 
-    server.connections.retain(|token, conn| {
-        // 1️⃣ Client inactivity timeout
-        if now.duration_since(conn.last_activity) > CLIENT_TIMEOUT {
-            dbg!("gg");
+    while let Some(token) = server.timers.pop_expired(now) {
+        let Some(mut conn) = server.tokens.lend(token) else {
+            continue;
+        };
+        if check_connection(server, poll, token, &mut conn, now) {
+            server.timers.schedule(token, next_deadline(&conn, now));
+            server.tokens.restore(token, conn);
+        } else {
+            server.tokens.remove(token);
+        }
+    }
+
+    if now >= server.next_housekeeping {
+        housekeeping(server, poll, now);
+    }
+}
+
+fn housekeeping(server: &mut Server, poll: &Poll, now: Instant) {
+    // Pools are shared, so one worker probing is enough
+    let probing = server.worker_id == 0 && server.upstreams.iter().any(|p| !p.health_check.is_empty());
+    if probing {
+        upstream::run_health_checks(server, poll);
+    }
+
+    server.session_store.cleanup_every(Duration::from_secs(CLEAN_UP));
+
+    server
+        .zombie_purgatory
+        .retain_mut(|child| match child.try_wait() {
+            Ok(Some(_)) => false,
+            Ok(None) => true,
+            Err(_) => false,
+        });
+
+    server.next_housekeeping = if probing || !server.zombie_purgatory.is_empty() {
+        now + HOUSEKEEPING_TICK
+    } else {
+        server.session_store.next_cleanup(Duration::from_secs(CLEAN_UP)).max(now + HOUSEKEEPING_TICK)
+    };
+}
+
+/// Earliest moment one of the checks in `check_connection` can fire.
+pub fn next_deadline(conn: &HttpConnection, now: Instant) -> Instant {
+    let head_cfg = conn.default_config();
+    let s_cfg = conn.s_cfg.clone().unwrap_or_else(|| Arc::clone(&head_cfg));
+
+    let mut next = conn.last_activity + CLIENT_TIMEOUT;
+    if let Some(since) = conn.write_blocked_since {
+        next = next.min(since + Duration::from_secs(s_cfg.send_timeout));
+    }
+    if !conn.closed {
+        match conn.request.state {
+            ParsingState::RequestLine | ParsingState::Headers
+                if matches!(conn.action, ActiveAction::None) =>
+            {
+                if let Some(started) = conn.head_started {
+                    next = next.min(started + Duration::from_secs(head_cfg.header_timeout));
+                } else if conn.write_buffer.is_empty() {
+                    next = next.min(conn.last_activity + Duration::from_secs(s_cfg.keepalive_timeout));
+                }
+            }
+            ParsingState::Body | ParsingState::ChunkedBody if s_cfg.body_min_rate > 0 => {
+                // The rate falls behind once the window is over and the bytes
+                // received no longer cover the elapsed seconds
+                let started = conn.body_started.unwrap_or(now);
+                let covered = Duration::from_secs((conn.body_received / s_cfg.body_min_rate) as u64 + 1);
+                next = next.min(started + BODY_RATE_WINDOW.max(covered));
+            }
+            _ => {}
+        }
+    }
+    match &conn.action {
+        ActiveAction::Cgi {
+            start_time,
+            output_done,
+            ..
+        } => {
+            next = next.min(*start_time + Duration::from_secs(TIMEOUT_CGI));
+            // The output is complete but the script has yet to be reaped.
+            // Most exit right away, so the polls start close together and
+            // back off to the housekeeping tick for the ones that linger.
+            if let Some(done) = output_done {
+                let backoff = now.duration_since(*done).clamp(TIMER_SLACK, HOUSEKEEPING_TICK);
+                next = next.min(now + backoff);
+            }
+        }
+        ActiveAction::Proxy { start_time, .. } => {
+            next = next.min(*start_time + Duration::from_secs(TIMEOUT_PROXY));
+        }
+        ActiveAction::Resolve { start_time, .. } => {
+            next = next.min(*start_time + Duration::from_secs(TIMEOUT_RESOLVE));
+        }
+        _ => {}
+    }
+    next.max(now + TIMER_SLACK)
+}
+
+/// Applies the client and backend deadlines to one connection. Returns
+/// false when the connection has been torn down and should be dropped.
+fn check_connection(
+    server: &mut Server,
+    poll: &Poll,
+    token: Token,
+    conn: &mut HttpConnection,
+    now: Instant,
+) -> bool {
+    // 1️⃣ Client inactivity timeout
+    if now.duration_since(conn.last_activity) >= CLIENT_TIMEOUT {
+        cleanup_connection(conn, poll);
+        cleanup_proxy(&mut server.tokens, conn);
+        force_cgi_timeout(
+            conn,
+            &mut server.tokens,
+            &mut server.zombie_purgatory,
+        );
+        return false;
+    }
+
+    match expired_deadline(conn, now) {
+        Some(Expired::RequestTimeout) => {
+            handle_error(&mut conn.response, StatusCode::REQUEST_TIMEOUT, conn.s_cfg.as_ref());
+            conn.queue_response();
+            conn.closed = true;
+            poll.registry()
+                .reregister(&mut conn.stream, token, Interest::WRITABLE)
+                .ok();
+            return true;
+        }
+        Some(Expired::Close) => {
             cleanup_connection(conn, poll);
+            cleanup_proxy(&mut server.tokens, conn);
             force_cgi_timeout(
                 conn,
-                &mut server.cgi_to_client,
+                &mut server.tokens,
                 &mut server.zombie_purgatory,
             );
             return false;
         }
+        None => {}
+    }
 
-        // CGI execution timeout
-        if let ActiveAction::Cgi { start_time, .. } = &conn.action
-            && start_time.elapsed().as_secs() > TIMEOUT_CGI
-        {
-            force_cgi_timeout(
-                conn,
-                &mut server.cgi_to_client,
-                &mut server.zombie_purgatory,
-            );
+    // A script that closed its output gets no more pipe events, so its
+    // exit is polled for here
+    if matches!(conn.action, ActiveAction::Cgi { .. })
+        && conn.cgi_out_token.is_none()
+        && let Err(e) = monitor_cgi_process(
+            conn,
+            &mut server.session_store,
+            poll,
+            token,
+            &mut server.tokens,
+        )
+    {
+        eprintln!("Cgi Error: {}", e);
+        conn.closed = true;
+    }
 
-            poll.registry()
-                .reregister(&mut conn.stream, *token, Interest::WRITABLE)
-                .ok();
-        }
+    // CGI execution timeout
+    if let ActiveAction::Cgi { start_time, .. } = &conn.action
+        && start_time.elapsed() >= Duration::from_secs(TIMEOUT_CGI)
+    {
+        force_cgi_timeout(
+            conn,
+            &mut server.tokens,
+            &mut server.zombie_purgatory,
+        );
 
-        true
-    });
+        poll.registry()
+            .reregister(&mut conn.stream, token, Interest::WRITABLE)
+            .ok();
+    }
 
-    if server.session_store.last_cleanup.elapsed() > Duration::from_secs(CLEAN_UP) {
-        server.session_store.cleanup();
+    // Upstream lookup and response timeouts
+    let expired = match &conn.action {
+        ActiveAction::Resolve { start_time, .. } => {
+            start_time.elapsed() >= Duration::from_secs(TIMEOUT_RESOLVE)
+        }
+        ActiveAction::Proxy { start_time, .. } => {
+            start_time.elapsed() >= Duration::from_secs(TIMEOUT_PROXY)
+        }
+        _ => false,
+    };
+    if expired {
+        force_proxy_timeout(conn, poll, &mut server.tokens);
+
+        poll.registry()
+            .reregister(&mut conn.stream, token, Interest::WRITABLE)
+            .ok();
     }
 
-    server
-        .zombie_purgatory
-        .retain_mut(|child| match child.try_wait() {
-            Ok(Some(_)) => false,
-            Ok(None) => true,
-            Err(_) => false,
-        });
+    true
 }
+
 fn cleanup_connection(conn: &mut HttpConnection, poll: &Poll) {
     let _ = poll.registry().deregister(&mut conn.stream);
     let _ = conn.stream.shutdown(Shutdown::Both);
 }
+
+/// The body rate is only judged once it has had this long to ramp up.
+const BODY_RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// What to do with a client that missed one of its deadlines.
+enum Expired {
+    /// Mid-request: answer 408, then close once it is flushed.
+    RequestTimeout,
+    /// Idle or no longer reading: there is nobody to answer.
+    Close,
+}
+
+/// Checks the per-server client deadlines. The head and idle limits come
+/// from the default server, as the vhost isn't known before the headers.
+fn expired_deadline(conn: &mut HttpConnection, now: Instant) -> Option<Expired> {
+    let head_cfg = conn.default_config();
+    let s_cfg = conn.s_cfg.clone().unwrap_or_else(|| Arc::clone(&head_cfg));
+
+    if let Some(since) = conn.write_blocked_since
+        && now.duration_since(since) >= Duration::from_secs(s_cfg.send_timeout)
+    {
+        return Some(Expired::Close);
+    }
+    if conn.closed {
+        return None;
+    }
+
+    match conn.request.state {
+        ParsingState::RequestLine | ParsingState::Headers
+            if matches!(conn.action, ActiveAction::None) =>
+        {
+            if let Some(started) = conn.head_started {
+                if now.duration_since(started) >= Duration::from_secs(head_cfg.header_timeout) {
+                    // A fresh connection that never sent a byte gets no answer
+                    return Some(if conn.request.buffer.is_empty() {
+                        Expired::Close
+                    } else {
+                        Expired::RequestTimeout
+                    });
+                }
+            } else if conn.write_buffer.is_empty()
+                && now.duration_since(conn.last_activity)
+                    >= Duration::from_secs(s_cfg.keepalive_timeout)
+            {
+                return Some(Expired::Close);
+            }
+        }
+        ParsingState::Body | ParsingState::ChunkedBody if s_cfg.body_min_rate > 0 => {
+            // While a backend is still draining what we have, we are the slow
+            // side: restart the window instead of blaming the client
+            if !conn.cgi_buffer.is_empty()
+                || !conn.proxy_buffer.is_empty()
+                || conn.request.buffer.len() > MAX_READ_DATA
+            {
+                conn.body_started = Some(now);
+                conn.body_received = 0;
+                return None;
+            }
+            let started = *conn.body_started.get_or_insert(now);
+            let elapsed = now.duration_since(started);
+            if elapsed >= BODY_RATE_WINDOW
+                && (conn.body_received as u64) < s_cfg.body_min_rate as u64 * elapsed.as_secs()
+            {
+                return Some(Expired::RequestTimeout);
+            }
+        }
+        _ => {}
+    }
+    None
+}
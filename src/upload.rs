@@ -4,7 +4,7 @@ use crate::prelude::*;
 pub enum UploadState {
     InProgress,
     Done,
-    Error(u16),
+    Error(StatusCode),
 }
 
 impl Upload {
@@ -14,12 +14,51 @@ impl Upload {
             multi_part_state: MultiPartState::Start,
             path,
             boundary: boundary.to_string(),
-            buffer: Vec::new(),
+            buffer: ByteBuffer::new(),
             current_pos: 0,
             saved_filenames: Vec::new(),
             files_saved: 0,
             part_info: PartInfo::default(),
             current_file_path: None,
+            created: None,
+            put_target: None,
+        }
+    }
+
+    /// Upload of a raw body to one known file, as done by `PUT`. The body
+    /// goes to `temp` and only replaces `target` once `commit` runs.
+    pub fn for_target(temp: PathBuf, target: PathBuf, created: bool) -> Self {
+        let dir = target.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut upload = Self::new(dir, "");
+        upload.current_file_path = Some(temp);
+        upload.put_target = Some(target);
+        upload.created = Some(created);
+        upload
+    }
+
+    /// Moves a completed `PUT` body over its target, creating the missing
+    /// directories on the way. The temporary file is removed when that fails.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let (Some(target), Some(temp)) = (self.put_target.take(), &self.current_file_path) else {
+            return Ok(());
+        };
+        target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::rename(temp, &target))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(temp);
+            })
+    }
+}
+
+impl Drop for Upload {
+    /// A `PUT` that never completed leaves the old file untouched.
+    fn drop(&mut self) {
+        if self.put_target.is_some()
+            && let Some(temp) = &self.current_file_path
+        {
+            let _ = fs::remove_file(temp);
         }
     }
 }
@@ -30,12 +69,18 @@ pub struct Upload {
     pub multi_part_state: MultiPartState,
     pub path: PathBuf,
     pub boundary: String,
-    pub buffer: Vec<u8>,
+    /// Multipart input not written out yet; indices such as `current_pos`
+    /// are relative to its unread start.
+    pub buffer: ByteBuffer,
     pub current_pos: usize,
     pub saved_filenames: Vec<String>,
     pub files_saved: usize,
     pub part_info: PartInfo,
     pub current_file_path: Option<PathBuf>,
+    /// Set for `PUT`: whether the target was created (201) or replaced (204).
+    pub created: Option<bool>,
+    /// Set for `PUT` until `commit`: the file the body is meant for.
+    pub put_target: Option<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -68,14 +113,14 @@ impl Upload {
             Ok(mut file) => match file.write_all(chunk) {
                 Ok(_) => {}
                 Err(_) => {
-                    self.state = UploadState::Error(HTTP_INTERNAL_SERVER_ERROR);
+                    self.state = UploadState::Error(StatusCode::INTERNAL_SERVER_ERROR);
                 }
             },
             Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-                self.state = UploadState::Error(HTTP_FORBIDDEN);
+                self.state = UploadState::Error(StatusCode::FORBIDDEN);
             }
             Err(_) => {
-                self.state = UploadState::Error(HTTP_INTERNAL_SERVER_ERROR);
+                self.state = UploadState::Error(StatusCode::INTERNAL_SERVER_ERROR);
             }
         }
     }
@@ -147,7 +192,7 @@ impl Upload {
 
                         // 3. CLEANUP FOR NEXT PART
                         // Remove everything up to the boundary so the buffer is fresh
-                        self.buffer.drain(..next_boundary_idx);
+                        self.buffer.consume(next_boundary_idx);
                         self.current_pos = 0;
                         self.current_file_path = None; // Reset so next file gets a new name
                         self.multi_part_state = MultiPartState::Start;
@@ -187,9 +232,10 @@ impl Upload {
                 let _ = file.write_all(data_to_write);
             }
 
-            self.buffer.drain(data_start..write_end);
-            self.multi_part_state = MultiPartState::NextBoundary(data_start);
-            self.current_pos = data_start;
+            // The part headers before `data_start` are parsed already
+            self.buffer.consume(write_end);
+            self.multi_part_state = MultiPartState::NextBoundary(0);
+            self.current_pos = 0;
         }
     }
 
@@ -212,7 +258,7 @@ impl Upload {
         let b_len = self.boundary.len() + 4;
         if self.buffer.len() > b_len {
             let drain_to = self.buffer.len() - b_len;
-            self.buffer.drain(..drain_to);
+            self.buffer.consume(drain_to);
             self.current_pos = 0;
         }
     }
@@ -315,6 +361,17 @@ impl Upload {
         upload_manager: &mut Upload,
         s_cfg: &Arc<ServerConfig>,
     ) {
+        if let Some(created) = upload_manager.created {
+            if upload_manager.commit().is_err() {
+                handle_error(response, StatusCode::INTERNAL_SERVER_ERROR, Some(s_cfg));
+            } else if created {
+                response.set_status_code(StatusCode::CREATED);
+            } else {
+                response.set_status_code(StatusCode::NO_CONTENT);
+                response.headers.remove("Content-Length");
+            }
+            return;
+        }
         if upload_manager.boundary.is_empty()
             && let Some(target_path) = &upload_manager.current_file_path
         {
@@ -328,12 +385,12 @@ impl Upload {
             upload_manager.files_saved += 1;
         }
         if !upload_manager.saved_filenames.is_empty() {
-            // let mut res = HttpResponse::new(HTTP_CREATED, "Created");
-            response.set_status_code(HTTP_CREATED);
+            // let mut res = HttpResponse::new(StatusCode::CREATED);
+            response.set_status_code(StatusCode::CREATED);
             if upload_manager.saved_filenames.len() == 1 {
-                response.headers.insert(
-                    "location".to_string(),
-                    format!("/upload/{}", upload_manager.saved_filenames[0]),
+                response.set_header(
+                    "location",
+                    &format!("/upload/{}", upload_manager.saved_filenames[0]),
                 );
                 response.set_body(
                     format!("File saved as {}", upload_manager.saved_filenames[0]).into_bytes(),
@@ -345,7 +402,7 @@ impl Upload {
                 response.set_body(body_msg.into_bytes(), "text/plain");
             }
         } else {
-            handle_error(response, HTTP_INTERNAL_SERVER_ERROR, Some(s_cfg));
+            handle_error(response, StatusCode::INTERNAL_SERVER_ERROR, Some(s_cfg));
         };
     }
 }
@@ -0,0 +1,384 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    },
+};
+
+use crate::prelude::*;
+use proxy_log::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadBalance {
+    RoundRobin,
+    LeastConn,
+    IpHash,
+}
+
+impl FromStr for LoadBalance {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "" | "round_robin" => Ok(LoadBalance::RoundRobin),
+            "least_conn" => Ok(LoadBalance::LeastConn),
+            "ip_hash" => Ok(LoadBalance::IpHash),
+            _ => Err(format!("unknown balance '{}'", s)),
+        }
+    }
+}
+
+/// One backend of a pool. Counters are atomics so connections can hold the
+/// peer while the event loop updates its health.
+#[derive(Debug)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub healthy: AtomicBool,
+    pub active: AtomicUsize,
+    pub fails: AtomicU32,
+    pub successes: AtomicU32,
+    pub probing: AtomicBool,
+    /// When the peer was last marked down.
+    pub down_since: Mutex<Option<Instant>>,
+}
+
+impl Peer {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            healthy: AtomicBool::new(true),
+            active: AtomicUsize::new(0),
+            fails: AtomicU32::new(0),
+            successes: AtomicU32::new(0),
+            probing: AtomicBool::new(false),
+            down_since: Mutex::new(None),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct UpstreamPool {
+    pub name: String,
+    pub balance: LoadBalance,
+    pub peers: Vec<Arc<Peer>>,
+    pub health_check: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub max_fails: u32,
+    pub rise: u32,
+    pub fail_timeout: Duration,
+    pub rr_cursor: AtomicUsize,
+    pub last_check: Mutex<Option<Instant>>,
+    /// The block the pool was built from, to spot it unchanged on reload.
+    pub config: UpstreamConfig,
+}
+
+impl UpstreamPool {
+    pub fn from_config(cfg: &UpstreamConfig) -> std::result::Result<Self, String> {
+        let balance = cfg.balance.parse::<LoadBalance>()?;
+
+        let mut peers = Vec::new();
+        for server in &cfg.servers {
+            use std::net::ToSocketAddrs;
+            let addr = server
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut a| a.next())
+                .ok_or_else(|| format!("invalid upstream server '{}'", server))?;
+            peers.push(Arc::new(Peer::new(addr)));
+        }
+        if peers.is_empty() {
+            return Err("no servers configured".to_string());
+        }
+
+        if !cfg.health_check.is_empty() && !cfg.health_check.starts_with('/') {
+            return Err(format!(
+                "health_check '{}' must be an absolute path",
+                cfg.health_check
+            ));
+        }
+
+        Ok(Self {
+            name: cfg.name.clone(),
+            balance,
+            peers,
+            health_check: cfg.health_check.clone(),
+            interval: Duration::from_secs(cfg.health_interval.max(1)),
+            timeout: Duration::from_secs(cfg.health_timeout.max(1)),
+            max_fails: cfg.max_fails.max(1),
+            rise: cfg.rise.max(1),
+            fail_timeout: Duration::from_secs(cfg.fail_timeout.max(1)),
+            rr_cursor: AtomicUsize::new(0),
+            last_check: Mutex::new(None),
+            config: cfg.clone(),
+        })
+    }
+
+    /// Picks a healthy peer according to the pool's balancing strategy.
+    pub fn select(&self, client_ip: Option<IpAddr>) -> Option<Arc<Peer>> {
+        if self.health_check.is_empty() {
+            self.readmit_expired();
+        }
+        let healthy: Vec<&Arc<Peer>> = self.peers.iter().filter(|p| p.is_healthy()).collect();
+        if healthy.is_empty() {
+            return None;
+        }
+
+        let peer = match self.balance {
+            LoadBalance::RoundRobin => {
+                let idx = self.rr_cursor.fetch_add(1, Ordering::Relaxed);
+                healthy[idx % healthy.len()]
+            }
+            LoadBalance::LeastConn => {
+                // Rotate the starting point so ties are spread across peers
+                let start = self.rr_cursor.fetch_add(1, Ordering::Relaxed) % healthy.len();
+                healthy
+                    .iter()
+                    .cycle()
+                    .skip(start)
+                    .take(healthy.len())
+                    .min_by_key(|p| p.active())
+                    .copied()?
+            }
+            LoadBalance::IpHash => {
+                let mut hasher = DefaultHasher::new();
+                client_ip.hash(&mut hasher);
+                let start = hasher.finish() as usize % self.peers.len();
+                // Keep the mapping stable while the hashed peer is up
+                self.peers
+                    .iter()
+                    .cycle()
+                    .skip(start)
+                    .take(self.peers.len())
+                    .find(|p| p.is_healthy())?
+            }
+        };
+        Some(Arc::clone(peer))
+    }
+
+    pub fn record_success(&self, peer: &Peer) {
+        peer.fails.store(0, Ordering::Relaxed);
+        let ok = peer.successes.fetch_add(1, Ordering::Relaxed) + 1;
+        if !peer.is_healthy() && ok >= self.rise {
+            peer.healthy.store(true, Ordering::Relaxed);
+            info!("Upstream '{}': {} is back in rotation", self.name, peer.addr);
+        }
+    }
+
+    pub fn record_failure(&self, peer: &Peer) {
+        peer.successes.store(0, Ordering::Relaxed);
+        let failed = peer.fails.fetch_add(1, Ordering::Relaxed) + 1;
+        if peer.is_healthy() && failed >= self.max_fails {
+            peer.healthy.store(false, Ordering::Relaxed);
+            *peer.down_since.lock().unwrap() = Some(Instant::now());
+            warn!("Upstream '{}': {} marked down", self.name, peer.addr);
+        }
+    }
+
+    /// Without health probes nothing else revives a peer, so once it has
+    /// been down for `fail_timeout` it gets another chance: one more failure
+    /// takes it out again, a success clears its count.
+    fn readmit_expired(&self) {
+        for peer in &self.peers {
+            if peer.is_healthy() {
+                continue;
+            }
+            let mut down_since = peer.down_since.lock().unwrap();
+            if down_since.is_some_and(|t| t.elapsed() >= self.fail_timeout) {
+                *down_since = None;
+                peer.fails.store(self.max_fails - 1, Ordering::Relaxed);
+                peer.healthy.store(true, Ordering::Relaxed);
+                info!("Upstream '{}': retrying {} after fail_timeout", self.name, peer.addr);
+            }
+        }
+    }
+
+    fn check_due(&self, now: Instant) -> bool {
+        if self.health_check.is_empty() {
+            return false;
+        }
+        let mut last = self.last_check.lock().unwrap();
+        match *last {
+            Some(t) if now.duration_since(t) < self.interval => false,
+            _ => {
+                *last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// Keeps `Peer::active` accurate for least-connections: the count drops as
+/// soon as the proxied exchange ends, whatever path tears it down.
+#[derive(Debug)]
+pub struct PeerGuard {
+    pub pool: Arc<UpstreamPool>,
+    pub peer: Arc<Peer>,
+}
+
+impl PeerGuard {
+    pub fn new(pool: Arc<UpstreamPool>, peer: Arc<Peer>) -> Self {
+        peer.active.fetch_add(1, Ordering::Relaxed);
+        Self { pool, peer }
+    }
+
+    pub fn record_failure(&self) {
+        self.pool.record_failure(&self.peer);
+    }
+
+    pub fn record_success(&self) {
+        self.pool.record_success(&self.peer);
+    }
+}
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        self.peer.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct HealthProbe {
+    pub pool: Arc<UpstreamPool>,
+    pub peer: Arc<Peer>,
+    pub stream: TcpStream,
+    pub request: Vec<u8>,
+    pub response: Vec<u8>,
+    pub started: Instant,
+}
+
+impl HealthProbe {
+    /// Drives the probe. Returns `Some(healthy)` once a verdict is reached.
+    pub fn on_event(&mut self, event: &Event) -> Option<bool> {
+        if event.is_error() || !matches!(self.stream.take_error(), Ok(None)) {
+            return Some(false);
+        }
+
+        if event.is_writable() && !self.request.is_empty() {
+            match self.stream.write(&self.request) {
+                Ok(n) => {
+                    self.request.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == ErrorKind::NotConnected => {}
+                Err(_) => return Some(false),
+            }
+        }
+
+        if event.is_readable() || event.is_read_closed() {
+            let mut buf = [0u8; 512];
+            loop {
+                match self.stream.read(&mut buf) {
+                    Ok(0) => return Some(self.verdict().unwrap_or(false)),
+                    Ok(n) => {
+                        self.response.extend_from_slice(&buf[..n]);
+                        if let Some(ok) = self.verdict() {
+                            return Some(ok);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => return Some(false),
+                }
+            }
+        }
+        None
+    }
+
+    /// A 2xx or 3xx status line means the peer is healthy.
+    fn verdict(&self) -> Option<bool> {
+        let line_end = find_subsequence(&self.response, b"\r\n", 0)?;
+        let line = String::from_utf8_lossy(&self.response[..line_end]);
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok());
+        Some(matches!(status, Some(200..=399)))
+    }
+}
+
+/// Called from the `timeouts::process` tick: expires stale probes and starts
+/// new ones for every pool whose interval has elapsed.
+pub fn run_health_checks(server: &mut Server, poll: &Poll) {
+    let now = Instant::now();
+
+    let expired: Vec<Token> = server
+        .tokens
+        .iter()
+        .filter_map(|(token, slot)| match slot {
+            Slot::Probe(probe) if now.duration_since(probe.started) > probe.pool.timeout => {
+                Some(token)
+            }
+            _ => None,
+        })
+        .collect();
+    for token in expired {
+        if let Some(Slot::Probe(mut probe)) = server.tokens.remove(token) {
+            let _ = poll.registry().deregister(&mut probe.stream);
+            probe.peer.probing.store(false, Ordering::Relaxed);
+            probe.pool.record_failure(&probe.peer);
+        }
+    }
+
+    for pool in &server.upstreams {
+        if !pool.check_due(now) {
+            continue;
+        }
+        for peer in &pool.peers {
+            if peer.probing.swap(true, Ordering::Relaxed) {
+                continue;
+            }
+            let Ok(mut stream) = TcpStream::connect(peer.addr) else {
+                peer.probing.store(false, Ordering::Relaxed);
+                pool.record_failure(peer);
+                continue;
+            };
+            let token = server.tokens.vacant_token();
+            if poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)
+                .is_err()
+            {
+                peer.probing.store(false, Ordering::Relaxed);
+                continue;
+            }
+            let request = format!(
+                "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: 01-server-health\r\nConnection: close\r\n\r\n",
+                pool.health_check, peer.addr
+            );
+            server.tokens.insert(Slot::Probe(HealthProbe {
+                pool: Arc::clone(pool),
+                peer: Arc::clone(peer),
+                stream,
+                request: request.into_bytes(),
+                response: Vec::new(),
+                started: now,
+            }));
+        }
+    }
+}
+
+pub fn handle_probe_event(server: &mut Server, poll: &Poll, event: &Event, token: Token) {
+    let Some(Slot::Probe(probe)) = server.tokens.get_mut(token) else {
+        return;
+    };
+    if let Some(healthy) = probe.on_event(event)
+        && let Some(Slot::Probe(mut probe)) = server.tokens.remove(token)
+    {
+        let _ = poll.registry().deregister(&mut probe.stream);
+        probe.peer.probing.store(false, Ordering::Relaxed);
+        if healthy {
+            probe.pool.record_success(&probe.peer);
+        } else {
+            probe.pool.record_failure(&probe.peer);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::ops::Deref;
+
+/// Input buffer consumed from the front through a read cursor.
+///
+/// `consume` only moves the cursor, so parsers can eat a chunk header or a
+/// slice of body without shifting what follows. The unread bytes are moved
+/// back to the start of the allocation when new data is appended and the
+/// consumed prefix has grown to at least half of it, which keeps appends
+/// amortised O(1) and the unread bytes one contiguous slice. The allocation
+/// is reused for the life of the buffer.
+#[derive(Debug, Default, Clone)]
+pub struct ByteBuffer {
+    data: Vec<u8>,
+    head: usize,
+}
+
+impl ByteBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+            head: 0,
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        if self.head > 0 && self.head >= self.data.len() / 2 {
+            self.compact();
+        }
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Drops the first `n` unread bytes.
+    pub fn consume(&mut self, n: usize) {
+        assert!(n <= self.len(), "consumed past the end of the buffer");
+        self.head += n;
+        if self.head == self.data.len() {
+            self.clear();
+        }
+    }
+
+    /// Removes and returns the first `n` unread bytes.
+    pub fn take_front(&mut self, n: usize) -> Vec<u8> {
+        let bytes = self[..n].to_vec();
+        self.consume(n);
+        bytes
+    }
+
+    /// Removes every unread byte, keeping the allocation.
+    pub fn take_all(&mut self) -> Vec<u8> {
+        self.take_front(self.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.head = 0;
+    }
+
+    /// Bytes allocated, read or not.
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    fn compact(&mut self) {
+        self.data.copy_within(self.head.., 0);
+        self.data.truncate(self.data.len() - self.head);
+        self.head = 0;
+    }
+}
+
+impl Deref for ByteBuffer {
+    type Target = [u8];
+
+    /// The unread bytes.
+    fn deref(&self) -> &[u8] {
+        &self.data[self.head..]
+    }
+}
+
+impl From<&[u8]> for ByteBuffer {
+    fn from(bytes: &[u8]) -> Self {
+        Self {
+            data: bytes.to_vec(),
+            head: 0,
+        }
+    }
+}
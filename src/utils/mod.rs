@@ -1,7 +1,20 @@
+pub mod byte_buffer;
 pub mod cookie;
+pub mod http_date;
+pub mod listener;
+pub mod sendfile;
 pub mod session;
 pub mod set_cookie;
+pub mod signals;
+pub mod timer_heap;
 
+pub use byte_buffer::*;
 pub use cookie::*;
+pub use http_date::*;
+pub use listener::*;
+pub use sendfile::*;
 pub use session::*;
-pub use set_cookie::*;
\ No newline at end of file
+pub use set_cookie::*;
+pub use signals::*;
+pub use timer_heap::*;
+
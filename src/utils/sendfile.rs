@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+
+/// Copies up to `len` bytes of `file` starting at `offset` straight into
+/// `out` inside the kernel. Returns the bytes sent; `Ok(0)` means the file
+/// ended before `offset`.
+#[cfg(target_os = "linux")]
+pub fn send_file(out: &impl AsRawFd, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+    let mut off = libc::off_t::try_from(offset)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // SAFETY: both descriptors stay open for the call and `off` outlives it
+    let n = unsafe { libc::sendfile(out.as_raw_fd(), file.as_raw_fd(), &mut off, len) };
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_file(_out: &impl AsRawFd, _file: &File, _offset: u64, _len: usize) -> io::Result<usize> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Errors after which sendfile(2) won't work for this file or socket, and
+/// the buffered copy should take over.
+pub fn sendfile_unsupported(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::Unsupported {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP)) {
+        return true;
+    }
+    false
+}
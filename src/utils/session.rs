@@ -22,29 +22,56 @@ impl Session {
     }
 }
 
+use std::sync::{Mutex, MutexGuard};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct SessionStore {
+pub struct SessionTable {
     pub sessions: HashMap<String, Session>,
-    pub ttl: u64,
+    /// Ids handed out so far. Every new id ends with it, so no two workers
+    /// can mint the same one.
     pub counter: u64,
     pub last_cleanup: Instant,
 }
 
+/// Handle to the session table. Clones share the same table, so every
+/// worker thread sees a session whichever one created it.
+#[derive(Clone)]
+pub struct SessionStore {
+    table: Arc<Mutex<SessionTable>>,
+    pub ttl: u64,
+}
+
 impl SessionStore {
     pub fn new(ttl: u64) -> Self {
         SessionStore {
-            sessions: HashMap::new(),
+            table: Arc::new(Mutex::new(SessionTable {
+                sessions: HashMap::new(),
+                counter: 0,
+                last_cleanup: Instant::now(),
+            })),
             ttl,
-            counter: 0,
-            last_cleanup: Instant::now(),
         }
     }
 
-    pub fn cleanup(&mut self) {
-        let now = current_timestamp();
-        self.sessions.retain(|_, s| !s.is_expired(now));
-        self.last_cleanup = Instant::now();
+    /// Locks the table. A worker that panicked mid-update leaves at worst a
+    /// stale session behind, so a poisoned lock is still used.
+    pub fn lock(&self) -> MutexGuard<'_, SessionTable> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// When the next `cleanup_every(every)` will do any work.
+    pub fn next_cleanup(&self, every: Duration) -> Instant {
+        self.lock().last_cleanup + every
+    }
+
+    /// Drops expired sessions at most once per `every`, across all workers.
+    pub fn cleanup_every(&self, every: Duration) {
+        let mut table = self.lock();
+        if table.last_cleanup.elapsed() > every {
+            let now = current_timestamp();
+            table.sessions.retain(|_, s| !s.is_expired(now));
+            table.last_cleanup = Instant::now();
+        }
     }
 
     pub fn mange_session_store(&mut self, conn: &mut HttpConnection) {
@@ -55,31 +82,31 @@ impl SessionStore {
         };
 
         let mut valid_session_found = false;
+        let mut table = self.lock();
 
         if let Some(id) = cookies.get("session_id") {
-            if let Some(session) = self.sessions.get_mut(id)
+            if let Some(session) = table.sessions.get_mut(id)
                 && !session.is_expired(current_timestamp())
             {
                 conn.session_id = Some(id.to_string());
                 valid_session_found = true;
             } else {
-                self.sessions.remove(id);
+                table.sessions.remove(id);
             }
         }
 
         if !valid_session_found {
-            let new_id = generate_session_id();
-            self.sessions.insert(new_id.clone(), Session::new(self.ttl));
+            table.counter += 1;
+            let new_id = generate_session_id(table.counter);
+            table.sessions.insert(new_id.clone(), Session::new(self.ttl));
 
             let set_cookie = SetCookie::new("session_id", &new_id)
                 .max_age(self.ttl)
                 .to_header();
 
-            // conn.response = HttpResponse::new(200, &HttpResponse::status_text(200));
+            // conn.response = HttpResponse::new(StatusCode::OK);
 
-            conn.response
-                .headers
-                .insert("Set-Cookie".to_string(), set_cookie);
+            conn.response.append_header("set-cookie", &set_cookie);
 
             conn.response
                 .set_header("Cache-Control", "no-cache, no-store, must-revalidate");
@@ -99,21 +126,24 @@ fn current_timestamp() -> u64 {
         .as_secs()
 }
 
-fn generate_session_id() -> String {
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap();
-
-    // Get seconds and nanos
-    let sec = now.as_secs();
-    let nano = now.subsec_nanos();
-
-    // Mix them up using bitwise operations to create four "random" segments
-    // This isn't cryptographically secure, but it's unique for a school project.
-    let part1 = nano;
-    let part2 = (nano ^ 0x55555555) >> 8;
-    let part3 = (sec & 0xFFFF) as u32;
-    let part4 = (nano << 4) ^ (sec as u32);
+/// 128 random bits from the OS followed by the store's counter. Without
+/// `/dev/urandom` the clock stands in: the id can be guessed, but the
+/// counter still keeps it unique.
+fn generate_session_id(counter: u64) -> String {
+    use std::io::Read;
+
+    let mut random = [0u8; 16];
+    let filled = std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut random))
+        .is_ok();
+    if !filled {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        random = nanos.to_le_bytes();
+    }
 
-    format!("{:08x}-{:08x}-{:04x}-{:08x}", part1, part2, part3, part4)
+    let hex: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{:x}", hex, counter)
 }
@@ -0,0 +1,122 @@
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use mio::event::Source;
+use mio::unix::pipe::{self, Receiver, Sender};
+use mio::{Interest, Registry, Token};
+
+/// Event loops that can be woken at once, one pipe each.
+const MAX_PIPES: usize = 64;
+
+static WAKE_FDS: [AtomicI32; MAX_PIPES] = [const { AtomicI32::new(-1) }; MAX_PIPES];
+static SHUTDOWNS: AtomicUsize = AtomicUsize::new(0);
+static RELOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs on whatever thread the kernel picked, so it only touches atomics
+/// and write(2), both async-signal-safe.
+extern "C" fn on_signal(signal: libc::c_int) {
+    if signal == libc::SIGTERM || signal == libc::SIGINT {
+        SHUTDOWNS.fetch_add(1, Ordering::SeqCst);
+    } else if signal == libc::SIGHUP {
+        RELOADS.fetch_add(1, Ordering::SeqCst);
+    }
+    let byte = 1u8;
+    for slot in &WAKE_FDS {
+        let fd = slot.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // SAFETY: a full pipe fails with EAGAIN, which is fine: the
+            // loop has a wakeup pending already
+            unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+        }
+    }
+}
+
+fn install_handlers() -> io::Result<()> {
+    static INSTALLED: OnceLock<Result<(), i32>> = OnceLock::new();
+    let result = INSTALLED.get_or_init(|| {
+        // SAFETY: `on_signal` is async-signal-safe and the action struct is
+        // fully initialised before it is handed to the kernel
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+                if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+                }
+            }
+        }
+        Ok(())
+    });
+    result.map_err(io::Error::from_raw_os_error)
+}
+
+/// SIGTERM and SIGINT received since the process started.
+pub fn shutdown_signals() -> usize {
+    SHUTDOWNS.load(Ordering::SeqCst)
+}
+
+/// SIGHUP received since the process started.
+pub fn reload_signals() -> usize {
+    RELOADS.load(Ordering::SeqCst)
+}
+
+/// Self-pipe that turns SIGTERM, SIGINT and SIGHUP into a readable event
+/// for one event loop. The handlers are installed with the first pipe;
+/// every pipe is written on every signal, so all workers wake up.
+#[derive(Debug)]
+pub struct SignalPipe {
+    receiver: Receiver,
+    // Keeps the write end open while its fd sits in `WAKE_FDS`
+    _sender: Sender,
+    slot: usize,
+}
+
+impl SignalPipe {
+    pub fn new() -> io::Result<Self> {
+        install_handlers()?;
+        let (sender, receiver) = pipe::new()?;
+        let fd = sender.as_raw_fd();
+        let slot = WAKE_FDS
+            .iter()
+            .position(|slot| {
+                slot.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+            .ok_or_else(|| io::Error::other("too many signal pipes"))?;
+        Ok(Self {
+            receiver,
+            _sender: sender,
+            slot,
+        })
+    }
+
+    /// Empties the pipe so the next signal is a fresh edge.
+    pub fn drain(&mut self) {
+        let mut buf = [0u8; 64];
+        while matches!(self.receiver.read(&mut buf), Ok(n) if n > 0) {}
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        WAKE_FDS[self.slot].store(-1, Ordering::SeqCst);
+    }
+}
+
+impl Source for SignalPipe {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        self.receiver.register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        self.receiver.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.receiver.deregister(registry)
+    }
+}
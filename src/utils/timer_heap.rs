@@ -0,0 +1,95 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+use mio::Token;
+
+/// One pending deadline per token, ordered by a min-heap.
+///
+/// Cancelling or moving a deadline only rewrites the token's slot; the old
+/// heap entry is left behind and skipped when it surfaces. The heap is
+/// rebuilt once such stale entries outnumber the live ones.
+#[derive(Default)]
+pub struct TimerHeap {
+    heap: BinaryHeap<Reverse<(Instant, usize)>>,
+    scheduled: Vec<Option<Instant>>,
+    live: usize,
+}
+
+impl TimerHeap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the deadline of `token`, replacing any earlier one.
+    pub fn schedule(&mut self, token: Token, at: Instant) {
+        if token.0 >= self.scheduled.len() {
+            self.scheduled.resize(token.0 + 1, None);
+        }
+        let slot = &mut self.scheduled[token.0];
+        if *slot == Some(at) {
+            return;
+        }
+        if slot.replace(at).is_none() {
+            self.live += 1;
+        }
+        self.heap.push(Reverse((at, token.0)));
+        if self.heap.len() > 4 * self.live + 1024 {
+            self.compact();
+        }
+    }
+
+    pub fn cancel(&mut self, token: Token) {
+        if let Some(slot) = self.scheduled.get_mut(token.0)
+            && slot.take().is_some()
+        {
+            self.live -= 1;
+        }
+    }
+
+    /// Earliest live deadline.
+    pub fn next_deadline(&mut self) -> Option<Instant> {
+        self.skip_stale();
+        self.heap.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// Removes and returns a token whose deadline is at or before `now`.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<Token> {
+        self.skip_stale();
+        let Reverse((at, idx)) = *self.heap.peek()?;
+        if at > now {
+            return None;
+        }
+        self.heap.pop();
+        self.scheduled[idx] = None;
+        self.live -= 1;
+        Some(Token(idx))
+    }
+
+    /// Number of tokens with a pending deadline.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    fn skip_stale(&mut self) {
+        while let Some(&Reverse((at, idx))) = self.heap.peek() {
+            if self.scheduled[idx] == Some(at) {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+
+    fn compact(&mut self) {
+        self.heap = self
+            .scheduled
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, at)| at.map(|at| Reverse((at, idx))))
+            .collect();
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod backpressure {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::http::BufferUsage;
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::path::Path;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    const OUTPUT_LEN: usize = 32 * 1024 * 1024;
+    const LINE: &[u8] = b"0123456789abcde\n";
+
+    #[test]
+    fn test_buffer_usage_report() {
+        let usage = BufferUsage {
+            request: 10,
+            write: 300,
+            cgi: 0,
+            proxy: 2,
+            allocated: 4096,
+        };
+        assert_eq!(usage.queued(), 312);
+        assert_eq!(
+            usage.to_string(),
+            "request 10 B, write 300 B, cgi 0 B, proxy 2 B (4096 B allocated)"
+        );
+    }
+
+    /// A script that outruns its client stays blocked on the pipe instead of
+    /// having its whole output buffered, and the client still gets every byte.
+    #[test]
+    fn test_fast_cgi_slow_client() {
+        let root = "./tmp_backpressure_test_8141";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        let marker = fs::canonicalize(root).unwrap().join("finished");
+        fs::write(
+            format!("{}/flood.sh", root),
+            format!(
+                "printf 'Content-Type: text/plain\\r\\nContent-Length: {len}\\r\\n\\r\\n'\n\
+                 yes 0123456789abcde | head -c {len}\n\
+                 touch {marker}\n",
+                len = OUTPUT_LEN,
+                marker = marker.display()
+            ),
+        )
+        .unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8141],
+            root: root.to_string(),
+            write_buffer_high: 64 * 1024,
+            write_buffer_low: Some(16 * 1024),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                cgi_ext: Some(".sh".to_string()),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let mut stream = TcpStream::connect("127.0.0.1:8141").unwrap();
+        stream
+            .write_all(b"GET /flood.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        // Socket buffers hold a few MB at most, the rest must stay in the script
+        thread::sleep(Duration::from_millis(1500));
+        assert!(
+            !Path::new(&marker).exists(),
+            "the script finished while the client was not reading"
+        );
+
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        let pos = data.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&data[..pos]);
+        assert!(head.starts_with("HTTP/1.1 200"));
+        let body = &data[pos + 4..];
+        assert_eq!(body.len(), OUTPUT_LEN);
+        assert!(
+            body.chunks(LINE.len())
+                .all(|line| line == &LINE[..line.len()])
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,77 @@
+#[cfg(test)]
+mod byte_buffer {
+    use server_proxy::http::HttpRequest;
+    use server_proxy::upload::{Upload, UploadState};
+    use server_proxy::utils::ByteBuffer;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_consume_and_append() {
+        let mut buf = ByteBuffer::new();
+        buf.extend_from_slice(b"3\r\nabc\r\n0\r\n\r\n");
+        assert_eq!(&buf[..3], b"3\r\n");
+        buf.consume(3);
+        assert_eq!(buf.take_front(3), b"abc");
+        assert_eq!(&*buf, b"\r\n0\r\n\r\n");
+
+        // Appending after a large consumed prefix keeps the bytes in order
+        buf.consume(2);
+        buf.extend_from_slice(b"tail");
+        assert_eq!(&*buf, b"0\r\n\r\ntail");
+        assert_eq!(buf.len(), 9);
+
+        assert_eq!(buf.take_all(), b"0\r\n\r\ntail");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_allocation_is_reused() {
+        let mut buf = ByteBuffer::with_capacity(64);
+        buf.extend_from_slice(&[0u8; 10]);
+        for i in 0..1000u32 {
+            buf.extend_from_slice(&[i as u8; 40]);
+            buf.consume(40);
+        }
+        // A steady backlog of 50 bytes at most never needs more room
+        assert_eq!(buf.len(), 10);
+        assert!(buf.capacity() <= 128);
+        assert_eq!(&*buf, &[231u8; 10]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_consume_past_end() {
+        let mut buf = ByteBuffer::from(&b"abc"[..]);
+        buf.consume(4);
+    }
+
+    /// Feeds a multipart body in small pieces so boundaries and partial
+    /// flushes land at every position of the buffer.
+    #[test]
+    fn test_multipart_upload_in_pieces() {
+        let dir = PathBuf::from("./tmp_byte_buffer_upload");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let big: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"; filename=\"big.bin\"\r\n\r\n");
+        body.extend_from_slice(&big);
+        body.extend_from_slice(b"\r\n--XyZ\r\nContent-Disposition: form-data; name=\"b\"; filename=\"small.txt\"\r\n\r\n");
+        body.extend_from_slice(b"hello\r\n--XyZ--\r\n");
+
+        let request = HttpRequest::new();
+        let mut upload = Upload::new(dir.clone(), "XyZ");
+        for piece in body.chunks(997) {
+            upload.upload_body_with_boundry(&request, piece);
+        }
+
+        assert!(matches!(upload.state, UploadState::Done));
+        assert_eq!(upload.files_saved, 2);
+        assert_eq!(fs::read(dir.join("big.bin")).unwrap(), big);
+        assert_eq!(fs::read(dir.join("small.txt")).unwrap(), b"hello");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
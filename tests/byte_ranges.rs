@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod byte_ranges {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::http::{RangeRequest, parse_range};
+    use server_proxy::server::Server;
+    use server_proxy::utils::{format_http_date, parse_http_date};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::{fs, thread};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), RangeRequest::Partial(vec![(0, 4)]));
+        assert_eq!(parse_range("bytes=5-", 10), RangeRequest::Partial(vec![(5, 9)]));
+        assert_eq!(parse_range("bytes=-3", 10), RangeRequest::Partial(vec![(7, 9)]));
+        assert_eq!(parse_range("bytes=-30", 10), RangeRequest::Partial(vec![(0, 9)]));
+        assert_eq!(parse_range("bytes=8-100", 10), RangeRequest::Partial(vec![(8, 9)]));
+        assert_eq!(
+            parse_range("bytes=0-1, 4-5", 10),
+            RangeRequest::Partial(vec![(0, 1), (4, 5)])
+        );
+        // Overlapping and adjacent ranges collapse, in file order
+        assert_eq!(
+            parse_range("bytes=6-7, 0-2, 1-3, 4-4", 10),
+            RangeRequest::Partial(vec![(0, 4), (6, 7)])
+        );
+        assert_eq!(parse_range("bytes=0-5, -6", 10), RangeRequest::Partial(vec![(0, 9)]));
+        assert_eq!(parse_range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=4-2", 10), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-1", 10), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-b", 10), RangeRequest::Full);
+    }
+
+    #[test]
+    fn test_http_date_round_trip() {
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let time = parse_http_date(date).unwrap();
+        assert_eq!(time, UNIX_EPOCH + Duration::from_secs(784111777));
+        assert_eq!(format_http_date(time), date);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(time));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(time));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    fn send(request: &str) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:8130").unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+        let head = response.split("\r\n\r\n").next()?;
+        head.lines().find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            k.eq_ignore_ascii_case(name).then(|| v.trim())
+        })
+    }
+
+    #[test]
+    fn test_range_responses() {
+        let root = "./tmp_range_test_8130";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(format!("{}/digits.txt", root), "0123456789").unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8130],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let get = |extra: &str| {
+            send(&format!(
+                "GET /digits.txt HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+                extra
+            ))
+        };
+
+        let full = get("");
+        assert!(full.starts_with("HTTP/1.1 200"));
+        assert_eq!(header(&full, "accept-ranges"), Some("bytes"));
+        assert!(full.ends_with("0123456789"));
+        let etag = header(&full, "etag").unwrap().to_string();
+        let last_modified = header(&full, "last-modified").unwrap().to_string();
+
+        let single = get("Range: bytes=2-5\r\n");
+        assert!(single.starts_with("HTTP/1.1 206"));
+        assert_eq!(header(&single, "content-range"), Some("bytes 2-5/10"));
+        assert_eq!(header(&single, "content-length"), Some("4"));
+        assert!(single.ends_with("\r\n\r\n2345"));
+
+        let suffix = get("Range: bytes=-3\r\n");
+        assert_eq!(header(&suffix, "content-range"), Some("bytes 7-9/10"));
+        assert!(suffix.ends_with("\r\n\r\n789"));
+
+        let multi = get("Range: bytes=0-1,8-\r\n");
+        assert!(multi.starts_with("HTTP/1.1 206"));
+        let content_type = header(&multi, "content-type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let body = multi.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(
+            header(&multi, "content-length"),
+            Some(body.len().to_string().as_str())
+        );
+        assert!(body.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"));
+        assert!(body.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"));
+        assert!(body.ends_with(&format!("--{}--\r\n", boundary)));
+
+        let unsatisfiable = get("Range: bytes=20-30\r\n");
+        assert!(unsatisfiable.starts_with("HTTP/1.1 416"));
+        assert_eq!(header(&unsatisfiable, "content-range"), Some("bytes */10"));
+
+        let matching = get(&format!("Range: bytes=0-0\r\nIf-Range: {}\r\n", etag));
+        assert!(matching.starts_with("HTTP/1.1 206"));
+        let by_date = get(&format!("Range: bytes=0-0\r\nIf-Range: {}\r\n", last_modified));
+        assert!(by_date.starts_with("HTTP/1.1 206"));
+        let stale = get("Range: bytes=0-0\r\nIf-Range: \"stale\"\r\n");
+        assert!(stale.starts_with("HTTP/1.1 200"));
+        assert!(stale.ends_with("0123456789"));
+
+        let head = send("HEAD /digits.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-1\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.ends_with("\r\n\r\n"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
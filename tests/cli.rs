@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod cli {
+    use proxy_log::Level;
+    use server_proxy::cli::Cli;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(parse(&[]).unwrap(), Cli::default());
+        assert_eq!(parse(&[]).unwrap().config, PathBuf::from("config.yaml"));
+
+        let cli = parse(&["-c", "/etc/proxy.yaml", "-t", "--quiet", "--log-level", "WARN"]).unwrap();
+        assert_eq!(cli.config, PathBuf::from("/etc/proxy.yaml"));
+        assert!(cli.test && cli.quiet && !cli.dump_config);
+        assert_eq!(cli.log_level, Some(Level::Warn));
+
+        let cli = parse(&["--config=a.yaml", "--log-level=debug", "--dump-config"]).unwrap();
+        assert_eq!(cli.config, PathBuf::from("a.yaml"));
+        assert_eq!(cli.log_level, Some(Level::Debug));
+        assert!(cli.dump_config);
+
+        assert!(parse(&["-c"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--quiet=yes"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_level_order() {
+        assert!(Level::Error < Level::Warn && Level::Info < Level::Trace);
+        assert_eq!("trace".parse(), Ok(Level::Trace));
+        assert_eq!("warning".parse(), Ok(Level::Warn));
+    }
+
+    /// Runs the server binary and returns its exit code and stdout.
+    fn run(args: &[&str]) -> (i32, String) {
+        let output = Command::new(env!("CARGO_BIN_EXE_main"))
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+        )
+    }
+
+    #[test]
+    fn test_config_check_exit_codes() {
+        let dir = "./tmp_cli_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/www", dir)).unwrap();
+        fs::write(format!("{}/www/index.html", dir), "hi").unwrap();
+        let good = format!("{}/good.yaml", dir);
+        fs::write(
+            &good,
+            format!(
+                "servers:\n  - server_name: ok\n    ports: [9160]\n    root: \"{dir}/www\"\n    routes:\n      - path: \"/\"\n"
+            ),
+        )
+        .unwrap();
+        // The second block points at a missing root and would be skipped
+        let partly = format!("{}/partly.yaml", dir);
+        fs::write(
+            &partly,
+            format!(
+                "servers:\n  - server_name: ok\n    ports: [9160]\n    root: \"{dir}/www\"\n  - server_name: bad\n    ports: [9161]\n    root: \"{dir}/missing\"\n"
+            ),
+        )
+        .unwrap();
+
+        let (code, stdout) = run(&["-t", "-c", &good]);
+        assert_eq!(code, 0);
+        assert!(stdout.contains("configuration is valid"));
+        assert_eq!(run(&["--test", "--config", &partly]).0, 1);
+        assert_eq!(run(&["-t", "-c", &format!("{}/absent.yaml", dir)]).0, 1);
+        assert_eq!(run(&["--bogus"]).0, 2);
+
+        // The dump is clean YAML that checks out in turn
+        let (code, dump) = run(&["--dump-config", "-c", &partly]);
+        assert_eq!(code, 0);
+        assert!(dump.starts_with("workers: 1\n"));
+        assert!(!dump.contains("bad"));
+        let dumped = format!("{}/dumped.yaml", dir);
+        fs::write(&dumped, dump).unwrap();
+        assert_eq!(run(&["-t", "-c", &dumped]).0, 0);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,199 @@
+#[cfg(test)]
+mod conditional_get {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::http::{FileValidators, StatusCode};
+    use server_proxy::server::Server;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::{fs, thread};
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_precondition_order() {
+        let validators = FileValidators {
+            etag: "\"abc\"".to_string(),
+            // Sun, 06 Nov 1994 08:49:37 GMT
+            last_modified: UNIX_EPOCH + Duration::from_secs(784111777),
+        };
+        let before = "Sat, 05 Nov 1994 08:49:37 GMT";
+        let same = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+        assert_eq!(validators.evaluate_preconditions(&headers(&[])), None);
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-none-match", "\"x\", W/\"abc\"")])),
+            Some(StatusCode::NOT_MODIFIED)
+        );
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-none-match", "*")])),
+            Some(StatusCode::NOT_MODIFIED)
+        );
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-modified-since", same)])),
+            Some(StatusCode::NOT_MODIFIED)
+        );
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-modified-since", before)])),
+            None
+        );
+        // If-None-Match wins over If-Modified-Since
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[
+                ("if-none-match", "\"x\""),
+                ("if-modified-since", same)
+            ])),
+            None
+        );
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-match", "\"abc\"")])),
+            None
+        );
+        // If-Match compares strongly
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-match", "W/\"abc\"")])),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-unmodified-since", before)])),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[("if-unmodified-since", same)])),
+            None
+        );
+        // If-Match wins over If-Unmodified-Since
+        assert_eq!(
+            validators.evaluate_preconditions(&headers(&[
+                ("if-match", "*"),
+                ("if-unmodified-since", before)
+            ])),
+            None
+        );
+    }
+
+    fn send(request: &str) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:8131").unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+        let head = response.split("\r\n\r\n").next()?;
+        head.lines().find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            k.eq_ignore_ascii_case(name).then(|| v.trim())
+        })
+    }
+
+    #[test]
+    fn test_not_modified_and_precondition_failed() {
+        let root = "./tmp_conditional_test_8131";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(format!("{}/page.html", root), "<p>cached</p>").unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8131],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let get = |method: &str, extra: String| {
+            send(&format!(
+                "{} /page.html HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+                method, extra
+            ))
+        };
+
+        let full = get("GET", String::new());
+        assert!(full.starts_with("HTTP/1.1 200"));
+        let etag = header(&full, "etag").unwrap().to_string();
+        let last_modified = header(&full, "last-modified").unwrap().to_string();
+
+        let cached = get("GET", format!("If-None-Match: {}\r\n", etag));
+        assert!(cached.starts_with("HTTP/1.1 304"));
+        assert_eq!(header(&cached, "etag"), Some(etag.as_str()));
+        assert!(cached.ends_with("\r\n\r\n"));
+
+        let cached = get("HEAD", format!("If-Modified-Since: {}\r\n", last_modified));
+        assert!(cached.starts_with("HTTP/1.1 304"));
+
+        let changed = get("GET", "If-None-Match: \"other\"\r\n".to_string());
+        assert!(changed.starts_with("HTTP/1.1 200"));
+        assert!(changed.ends_with("<p>cached</p>"));
+
+        let failed = get("GET", "If-Match: \"other\"\r\n".to_string());
+        assert!(failed.starts_with("HTTP/1.1 412"));
+
+        let failed = get(
+            "GET",
+            "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n".to_string(),
+        );
+        assert!(failed.starts_with("HTTP/1.1 412"));
+
+        let passed = get("GET", format!("If-Match: {}\r\n", etag));
+        assert!(passed.starts_with("HTTP/1.1 200"));
+
+        // Two requests on one connection: the 304 must not leave a body behind
+        let mut stream = TcpStream::connect("127.0.0.1:8131").unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET /page.html HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\n\r\n\
+                     GET /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    etag
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut both = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            both.extend_from_slice(&buf[..n]);
+        }
+        let both = String::from_utf8_lossy(&both);
+        assert!(both.starts_with("HTTP/1.1 304"));
+        assert!(both.contains("\r\n\r\nHTTP/1.1 200"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -1,5 +1,6 @@
-use parser::FromYaml;
+use parser::{FromYaml, ToYaml};
 use server_proxy::config::{AppConfig, ServerConfig};
+use server_proxy::proxy::UpstreamTarget;
 
 #[cfg(test)]
 mod tests {
@@ -208,4 +209,245 @@ servers:
         let result = ServerConfig::from_str(yaml_str);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_route_proxy_pass() {
+        let yaml_str = r#"
+        routes:
+          - path: /api
+            proxy_pass: "http://127.0.0.1:9000/v1"
+    "#;
+        let config = ServerConfig::from_str(yaml_str).unwrap();
+        let url = config.routes[0].proxy_pass.as_deref().unwrap();
+        assert_eq!(url, "http://127.0.0.1:9000/v1");
+
+        let target = UpstreamTarget::parse(url).unwrap();
+        assert_eq!(target.host, "127.0.0.1");
+        assert_eq!(target.port, 9000);
+        assert_eq!(target.upstream_url("/api/users?id=1", "/api"), "/v1/users?id=1");
+        assert!(UpstreamTarget::parse("https://127.0.0.1").is_err());
+        assert!(UpstreamTarget::parse("http://127.0.0.1:abc").is_err());
+
+        let pool = UpstreamTarget::parse("upstream://backend/v2").unwrap();
+        assert_eq!(pool.host, "backend");
+        assert_eq!(pool.path, "/v2");
+        assert!(UpstreamTarget::parse("upstream://backend:8080").is_err());
+    }
+
+    #[test]
+    fn test_proxy_pass_upstream_names() {
+        let yaml = r#"
+servers:
+  - server_name: "pools"
+    ports: [9156]
+    root: "./www"
+    routes:
+      - path: "/api"
+        proxy_pass: "PROXY_PASS"
+upstreams:
+  - name: "backend"
+    servers: ["127.0.0.1:9157"]
+"#;
+        let mut config = AppConfig::from_str(&yaml.replace("PROXY_PASS", "upstream://backend")).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.servers[0].routes[0].upstream.as_ref().unwrap().name, "backend");
+
+        // A misspelt pool is an error, not a DNS lookup
+        for url in ["upstream://backnd", "http://backnd", "http://backend"] {
+            let mut config = AppConfig::from_str(&yaml.replace("PROXY_PASS", url)).unwrap();
+            assert!(config.validate().is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_proxy_pass_resolved_once() {
+        let yaml = r#"
+servers:
+  - server_name: "proxy"
+    ports: [9154]
+    root: "./www"
+    routes:
+      - path: "/api"
+        proxy_pass: "http://127.0.0.1:9155/v1"
+"#;
+        let mut config = AppConfig::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        let route = &config.servers[0].routes[0];
+        assert_eq!(route.proxy_addr, Some("127.0.0.1:9155".parse().unwrap()));
+        assert!(route.upstream.is_none());
+
+        let mut config =
+            AppConfig::from_str(&yaml.replace("127.0.0.1:9155", "unresolvable.invalid")).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_route_cors_block() {
+        let yaml_str = r#"
+        routes:
+          - path: /api
+            methods: ["GET", "POST"]
+            cors:
+              origins: ["https://app.example.com"]
+              headers: ["Content-Type", "X-Token"]
+              credentials: true
+              max_age: 600
+    "#;
+        let config = ServerConfig::from_str(yaml_str).unwrap();
+        let route = &config.routes[0];
+        let cors = route.cors.as_ref().unwrap();
+        assert_eq!(cors.origins, vec!["https://app.example.com"]);
+        assert_eq!(cors.headers, vec!["Content-Type", "X-Token"]);
+        assert!(cors.credentials);
+        assert_eq!(cors.max_age, Some(600));
+        assert!(cors.methods.is_empty());
+        assert_eq!(cors.allow_methods(route), "GET, POST, HEAD, OPTIONS");
+        assert_eq!(
+            cors.allow_origin("https://app.example.com").as_deref(),
+            Some("https://app.example.com")
+        );
+        assert!(cors.allow_origin("https://evil.example.com").is_none());
+    }
+
+    #[test]
+    fn test_cors_wildcard_with_credentials() {
+        let yaml = r#"
+servers:
+  - server_name: "cors"
+    ports: [9153]
+    root: "./www"
+    routes:
+      - path: "/"
+        cors:
+          credentials: true
+"#;
+        let config = AppConfig::from_str(yaml).unwrap();
+        let cors = config.servers[0].routes[0].cors.as_ref().unwrap();
+        assert_eq!(cors.origins, vec!["*"]);
+        // Never reflected back, so no site gets a credentialed grant
+        assert_eq!(cors.allow_origin("https://evil.example.com").as_deref(), Some("*"));
+
+        let mut config = config;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let config = ServerConfig::from_str("max_headers: 20\nmax_request_line: 1024").unwrap();
+        assert_eq!(config.max_headers, 20);
+        assert_eq!(config.max_request_line, 1024);
+        // Untouched limits keep their defaults
+        assert_eq!(config.max_header_size, 8192);
+        assert_eq!(config.max_header_bytes, 32768);
+    }
+
+    #[test]
+    fn test_server_tokens() {
+        let config = ServerConfig::from_str("server_software: edge\nserver_tokens: false").unwrap();
+        assert_eq!(config.server_header().as_deref(), Some("edge"));
+
+        let config = ServerConfig::default();
+        assert!(config.server_header().unwrap().starts_with("server_proxy/"));
+
+        let config = ServerConfig::from_str("server_software: \"\"").unwrap();
+        assert_eq!(config.server_header(), None);
+    }
+
+    #[test]
+    fn test_workers() {
+        let config = AppConfig::from_str("workers: 4\nservers:\n  - server_name: w\n").unwrap();
+        assert_eq!(config.workers, 4);
+        assert_eq!(AppConfig::from_str("servers: []").unwrap().workers, 1);
+
+        let mut config = AppConfig::from_str("workers: 0\nservers: []").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        assert_eq!(AppConfig::from_str("servers: []").unwrap().shutdown_timeout, 30);
+        let config = AppConfig::from_str("shutdown_timeout: 5\nservers: []").unwrap();
+        assert_eq!(config.shutdown_timeout, 5);
+    }
+
+    #[test]
+    fn test_write_buffer_marks() {
+        let config = ServerConfig::from_str("write_buffer_high: 1048576\nwrite_buffer_low: 131072").unwrap();
+        assert_eq!(config.write_buffer_high, 1048576);
+        assert_eq!(config.write_buffer_low, Some(131072));
+
+        let yaml = "servers:\n  - server_name: b\n    ports: [9141]\n    write_buffer_high: 8192\n";
+        let mut config = AppConfig::from_str(&format!("{yaml}    write_buffer_low: 4096\n")).unwrap();
+        assert!(config.validate().is_ok());
+        let mut config = AppConfig::from_str(&format!("{yaml}    write_buffer_low: 8192\n")).unwrap();
+        assert!(config.validate().is_err());
+
+        // Unset, the low mark follows the high one
+        let mut config = AppConfig::from_str(yaml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.servers[0].write_buffer_low, Some(2048));
+    }
+
+    #[test]
+    fn test_to_yaml_round_trip() {
+        let yaml = r#"
+workers: 2
+servers:
+  - host: "127.0.0.1"
+    ports: [9150, 9151]
+    server_name: "dump"
+    default_server: true
+    root: "./www"
+    routes:
+      - path: "/"
+        default_file: ""
+      - path: "/api"
+        methods: ["GET", "POST"]
+        proxy_pass: "upstream://backend"
+        cors:
+          origins: ["https://a.test"]
+          max_age: 600
+upstreams:
+  - name: "backend"
+    servers: ["127.0.0.1:9152"]
+"#;
+        let mut config = AppConfig::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        let dump = config.to_yaml().unwrap();
+        assert!(dump.contains("        root: \"./www\"\n"), "routes inherit the server root");
+        assert!(dump.contains("    keepalive_timeout: 75\n"), "defaults are spelled out");
+
+        let mut reparsed = AppConfig::from_str(&dump).unwrap();
+        reparsed.validate().unwrap();
+        assert_eq!(reparsed.to_yaml().unwrap(), dump);
+        assert_eq!(reparsed.servers[0].routes[1].cors.as_ref().unwrap().max_age, Some(600));
+        assert!(reparsed.servers[0].routes[1].upstream.is_some());
+    }
+
+    #[test]
+    fn test_to_yaml_quote_kinds() {
+        let yaml = r#"
+servers:
+  - host: "127.0.0.1"
+    ports: [9153]
+    server_name: 'say "hi"'
+    server_software: "it's"
+    error_pages: {404: "/404.html"}
+    routes:
+      - path: "/"
+        cgi_path: /cgi/it's_"x"
+"#;
+        let mut config = AppConfig::from_str(yaml).unwrap();
+        let route = &config.servers[0].routes[0];
+        assert_eq!(route.cgi_path.as_deref(), Some(r#"/cgi/it's_"x""#));
+
+        let dump = config.to_yaml().unwrap();
+        let reparsed = AppConfig::from_str(&dump).unwrap();
+        assert_eq!(format!("{:?}", reparsed), format!("{:?}", config));
+
+        // No quote or bare word can hold this one, so the dump refuses it
+        config.servers[0].server_name = r#"it's "x""#.to_string();
+        let err = config.to_yaml().unwrap_err();
+        assert!(err.to_string().contains("both quote kinds"));
+    }
 }
@@ -0,0 +1,107 @@
+#[cfg(test)]
+mod header_limits {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    fn send(request: &[u8]) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:8134").unwrap();
+        stream.write_all(request).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_request_head_limits() {
+        let root = "./tmp_limits_test_8134";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(format!("{}/uploads", root)).unwrap();
+        fs::write(format!("{}/index.html", root), "home").unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8134],
+            root: root.to_string(),
+            max_request_line: 64,
+            max_header_size: 64,
+            max_headers: 5,
+            max_header_bytes: 256,
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                upload_dir: "uploads".to_string(),
+                methods: vec!["GET".to_string(), "PUT".to_string()],
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let ok = send(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(ok.starts_with("HTTP/1.1 200"));
+
+        let long_uri = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", "a".repeat(80));
+        assert!(send(long_uri.as_bytes()).starts_with("HTTP/1.1 414"));
+
+        // Rejected as soon as the limit is crossed, without waiting for the CRLF
+        let unfinished = format!("GET /{}", "a".repeat(80));
+        assert!(send(unfinished.as_bytes()).starts_with("HTTP/1.1 414"));
+
+        let long_header = format!("GET / HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\n\r\n", "b".repeat(80));
+        assert!(send(long_header.as_bytes()).starts_with("HTTP/1.1 431"));
+
+        let unfinished = format!("GET / HTTP/1.1\r\nX-Big: {}", "b".repeat(80));
+        assert!(send(unfinished.as_bytes()).starts_with("HTTP/1.1 431"));
+
+        let mut many = String::from("GET / HTTP/1.1\r\nHost: localhost\r\n");
+        for i in 0..5 {
+            many.push_str(&format!("X-{}: 1\r\n", i));
+        }
+        many.push_str("\r\n");
+        assert!(send(many.as_bytes()).starts_with("HTTP/1.1 431"));
+
+        // Each line is fine on its own, together they exceed the section limit
+        let mut big = String::from("GET / HTTP/1.1\r\nHost: localhost\r\n");
+        for i in 0..4 {
+            big.push_str(&format!("X-{}: {}\r\n", i, "c".repeat(50)));
+        }
+        big.push_str("\r\n");
+        assert!(send(big.as_bytes()).starts_with("HTTP/1.1 431"));
+
+        // Only the trailer section counts, not the pipelined requests behind it
+        let next = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Pad: {}\r\n\r\n",
+            "d".repeat(40)
+        );
+        let pipelined = format!(
+            "PUT /t.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nt\r\n0\r\n\r\n{}",
+            next.repeat(4)
+        );
+        let response = send(pipelined.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+        assert_eq!(response.matches("HTTP/1.1 200").count(), 4, "{}", response);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,388 @@
+#[cfg(test)]
+mod http_methods {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, CorsConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    fn start_server(port: u16, routes: Vec<RouteConfig>) {
+        let test_root = format!("./tmp_methods_test_{}", port);
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![port],
+            root: test_root,
+            routes,
+            default_server: true,
+            ..Default::default()
+        });
+
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    fn fresh_root(port: u16) -> String {
+        let test_root = format!("./tmp_methods_test_{}", port);
+        let _ = fs::remove_dir_all(&test_root);
+        fs::create_dir_all(&test_root).unwrap();
+        test_root
+    }
+
+    /// Reads until the server has been quiet for a moment.
+    fn read_all(stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_head_matches_get_without_body() {
+        let root = fresh_root(8110);
+        fs::write(format!("{}/hello.txt", root), "hello world").unwrap();
+        fs::create_dir_all(format!("{}/files", root)).unwrap();
+        fs::write(format!("{}/files/a.txt", root), "a").unwrap();
+
+        start_server(
+            8110,
+            vec![
+                RouteConfig {
+                    path: "/files".to_string(),
+                    root: format!("{}/files", root),
+                    methods: vec!["GET".to_string()],
+                    autoindex: true,
+                    default_file: String::new(),
+                    ..Default::default()
+                },
+                RouteConfig {
+                    path: "/".to_string(),
+                    root: root.clone(),
+                    methods: vec!["GET".to_string()],
+                    ..Default::default()
+                },
+            ],
+        );
+
+        // The pipelined GET only parses correctly if HEAD left no body behind
+        let mut stream = TcpStream::connect("127.0.0.1:8110").unwrap();
+        stream
+            .write_all(
+                b"HEAD /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\nGET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .unwrap();
+        let response = read_all(&mut stream);
+        let (head, get) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains("Content-Length: 11\r\n"));
+        assert!(get.starts_with("HTTP/1.1 200"));
+        assert!(get.ends_with("\r\n\r\nhello world"));
+
+        let mut stream = TcpStream::connect("127.0.0.1:8110").unwrap();
+        stream
+            .write_all(b"HEAD /files/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_all(&mut stream);
+        assert!(response.contains("Content-Type: text/html\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+        assert!(!response.contains("Index of"));
+
+        let mut stream = TcpStream::connect("127.0.0.1:8110").unwrap();
+        stream
+            .write_all(b"HEAD /missing.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_all(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_head_on_cgi_drops_body() {
+        let root = fresh_root(8111);
+        fs::write(
+            format!("{}/hello.sh", root),
+            "printf 'Content-Type: text/plain\\r\\nX-Method: %s\\r\\n\\r\\n' \"$REQUEST_METHOD\"\nprintf 'cgi body'\n",
+        )
+        .unwrap();
+
+        start_server(
+            8111,
+            vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.clone(),
+                methods: vec!["GET".to_string()],
+                cgi_ext: Some(".sh".to_string()),
+                ..Default::default()
+            }],
+        );
+
+        let mut stream = TcpStream::connect("127.0.0.1:8111").unwrap();
+        stream
+            .write_all(b"HEAD /hello.sh HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_all(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("X-Method: HEAD\r\n"));
+        // Same framing headers a GET would get
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+        assert!(!response.contains("cgi body"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    fn send(port: u16, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(request).unwrap();
+        read_all(&mut stream)
+    }
+
+    #[test]
+    fn test_put_creates_and_replaces_exact_path() {
+        let root = fresh_root(8112);
+        fs::create_dir_all(format!("{}/uploads", root)).unwrap();
+
+        start_server(
+            8112,
+            vec![RouteConfig {
+                path: "/upload".to_string(),
+                root: root.clone(),
+                upload_dir: "uploads".to_string(),
+                methods: vec!["GET".to_string(), "PUT".to_string()],
+                ..Default::default()
+            }],
+        );
+        let target = format!("{}/uploads/reports/q3.csv", root);
+
+        let response = send(
+            8112,
+            b"PUT /upload/reports/q3.csv HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\na,b\n1,2\n3,4",
+        );
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "a,b\n1,2\n3,4");
+
+        // Replacing truncates the old content, chunked bodies stream the same way
+        let response = send(
+            8112,
+            b"PUT /upload/reports/q3.csv HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Type: multipart/form-data; boundary=x\r\n\r\n3\r\nnew\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(!response.contains("Content-Length"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+
+        // An aborted or oversized body leaves the old file and no temp file
+        let mut stream = TcpStream::connect("127.0.0.1:8112").unwrap();
+        stream
+            .write_all(b"PUT /upload/reports/q3.csv HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        drop(stream);
+        let response = send(
+            8112,
+            b"PUT /upload/reports/q3.csv HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n200000\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_dir(format!("{}/uploads/reports", root)).unwrap().count(), 1);
+
+        // Nor does a rejected body create the directories it would have needed
+        let response = send(
+            8112,
+            b"PUT /upload/new/dir/x.bin HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n200000\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        thread::sleep(Duration::from_millis(200));
+        assert!(!std::path::Path::new(&format!("{}/uploads/new", root)).exists());
+        assert_eq!(fs::read_dir(format!("{}/uploads", root)).unwrap().count(), 1);
+
+        let response = send(
+            8112,
+            b"PUT /upload/empty.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert_eq!(fs::read(format!("{}/uploads/empty.txt", root)).unwrap().len(), 0);
+
+        // Dot segments are resolved before routing, so this leaves /upload
+        let response = send(
+            8112,
+            b"PUT /upload/../escape.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nnope",
+        );
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(!std::path::Path::new(&format!("{}/escape.txt", root)).exists());
+
+        let response = send(
+            8112,
+            b"PUT /upload/reports HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nnope",
+        );
+        assert!(response.starts_with("HTTP/1.1 403"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_options_and_cors() {
+        let root = fresh_root(8113);
+        fs::write(format!("{}/index.html", root), "home").unwrap();
+        // CGI scripts resolve against the server root
+        fs::create_dir_all(format!("{}/api", root)).unwrap();
+        fs::write(
+            format!("{}/api/hello.sh", root),
+            "printf 'Content-Type: text/plain\\r\\n\\r\\n'\nprintf 'cgi'\n",
+        )
+        .unwrap();
+
+        start_server(
+            8113,
+            vec![
+                RouteConfig {
+                    path: "/api".to_string(),
+                    root: root.clone(),
+                    methods: vec!["GET".to_string(), "POST".to_string()],
+                    cgi_ext: Some(".sh".to_string()),
+                    cors: Some(CorsConfig {
+                        origins: vec!["https://app.example.com".to_string()],
+                        headers: vec!["Content-Type".to_string()],
+                        max_age: Some(600),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                RouteConfig {
+                    path: "/".to_string(),
+                    root: root.clone(),
+                    methods: vec!["GET".to_string(), "DELETE".to_string()],
+                    ..Default::default()
+                },
+            ],
+        );
+
+        let response = send(8113, b"OPTIONS /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Allow: GET, DELETE, HEAD, OPTIONS\r\n"));
+        assert!(!response.contains("Access-Control"));
+
+        let response = send(
+            8113,
+            b"OPTIONS /api/hello.sh HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: content-type\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+        assert!(response.contains("Access-Control-Allow-Methods: GET, POST, HEAD, OPTIONS\r\n"));
+        assert!(response.contains("Access-Control-Allow-Headers: Content-Type\r\n"));
+        assert!(response.contains("Access-Control-Max-Age: 600\r\n"));
+
+        // Refused preflights carry no grant
+        let response = send(
+            8113,
+            b"OPTIONS /api/hello.sh HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: DELETE\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(!response.contains("Access-Control"));
+
+        // Actual requests are decorated, including CGI output
+        let response = send(
+            8113,
+            b"GET /api/hello.sh HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+        assert!(response.contains("Vary: Origin\r\n"));
+        assert!(response.contains("cgi"));
+
+        let response = send(
+            8113,
+            b"GET /api/hello.sh HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.example.com\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(!response.contains("Access-Control"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let root = fresh_root(8114);
+        fs::create_dir_all(format!("{}/uploads", root)).unwrap();
+
+        start_server(
+            8114,
+            vec![
+                RouteConfig {
+                    path: "/upload".to_string(),
+                    root: root.clone(),
+                    upload_dir: "uploads".to_string(),
+                    methods: vec!["PUT".to_string()],
+                    ..Default::default()
+                },
+                RouteConfig {
+                    path: "/".to_string(),
+                    root: root.clone(),
+                    methods: vec!["GET".to_string(), "PUT".to_string()],
+                    ..Default::default()
+                },
+            ],
+        );
+
+        // Accepted: the interim response comes before any body byte is sent
+        let mut stream = TcpStream::connect("127.0.0.1:8114").unwrap();
+        stream
+            .write_all(b"PUT /upload/big.bin HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_all(&mut stream), "HTTP/1.1 100 Continue\r\n\r\n");
+        stream.write_all(b"hello").unwrap();
+        assert!(read_all(&mut stream).starts_with("HTTP/1.1 201"));
+        assert_eq!(
+            fs::read_to_string(format!("{}/uploads/big.bin", root)).unwrap(),
+            "hello"
+        );
+
+        // Rejected early: a final status, then the server hangs up instead of
+        // waiting for a body the client will never send
+        let rejections: [(&[u8], &str); 3] = [
+            (
+                b"PUT /upload/huge.bin HTTP/1.1\r\nHost: localhost\r\nContent-Length: 999999999999\r\nExpect: 100-continue\r\n\r\n",
+                "HTTP/1.1 413",
+            ),
+            (
+                b"POST /upload/x HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n",
+                "HTTP/1.1 405",
+            ),
+            (
+                b"PUT /other.bin HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-Continue\r\n\r\n",
+                "HTTP/1.1 403",
+            ),
+        ];
+        for (request, status) in rejections {
+            let mut stream = TcpStream::connect("127.0.0.1:8114").unwrap();
+            stream.write_all(request).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let mut response = Vec::new();
+            // read_to_end only returns once the server closes the socket
+            stream.read_to_end(&mut response).unwrap();
+            let response = String::from_utf8_lossy(&response);
+            assert!(response.starts_with(status), "{}", response);
+            assert!(!response.contains("100 Continue"));
+        }
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
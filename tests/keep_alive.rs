@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod keep_alive {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    const ROOT: &str = "./tmp_keepalive_test_8136";
+    const TESTS: usize = 5;
+    static START: Once = Once::new();
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    fn start_server() {
+        START.call_once(|| {
+            let _ = fs::remove_dir_all(ROOT);
+            fs::create_dir_all(ROOT).unwrap();
+            fs::write(format!("{}/index.html", ROOT), "home").unwrap();
+            fs::write(
+                format!("{}/stream.sh", ROOT),
+                "printf 'Content-Type: text/plain\\r\\n\\r\\nstreamed'\n",
+            )
+            .unwrap();
+            fs::write(
+                format!("{}/sink.sh", ROOT),
+                "head -c 10 >/dev/null\nprintf 'Content-Type: text/plain\\r\\n\\r\\nread'\n",
+            )
+            .unwrap();
+
+            let mut config = AppConfig::default();
+            config.servers.push(ServerConfig {
+                server_name: "localhost".to_string(),
+                ports: vec![8136],
+                root: ROOT.to_string(),
+                keepalive_requests: 3,
+                keepalive_timeout: 5,
+                client_max_body_size: 16,
+                routes: vec![RouteConfig {
+                    path: "/".to_string(),
+                    methods: vec!["GET".to_string(), "POST".to_string()],
+                    root: ROOT.to_string(),
+                    cgi_ext: Some(".sh".to_string()),
+                    ..Default::default()
+                }],
+                default_server: true,
+                ..Default::default()
+            });
+            thread::spawn(move || {
+                let poll = Poll::new().unwrap();
+                let mut server = Server::new(config, &poll).unwrap();
+                server.run(poll).unwrap();
+            });
+            thread::sleep(Duration::from_millis(300));
+        });
+    }
+
+    /// The last test to finish removes the shared root.
+    fn finish() {
+        if FINISHED.fetch_add(1, Ordering::SeqCst) + 1 == TESTS {
+            let _ = fs::remove_dir_all(ROOT);
+        }
+    }
+
+    /// Everything the server sent, and whether it closed the connection
+    /// within two seconds.
+    fn exchange(stream: &mut TcpStream, request: &str) -> (String, bool) {
+        stream.write_all(request.as_bytes()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        let closed = loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break true,
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+                Err(_) => break false,
+            }
+        };
+        (String::from_utf8_lossy(&response).into_owned(), closed)
+    }
+
+    #[test]
+    fn test_http10_closes_unless_asked() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let (response, closed) = exchange(&mut stream, "GET / HTTP/1.0\r\n\r\n");
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Keep-Alive:"));
+        assert!(response.ends_with("home"));
+        assert!(closed);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let (response, closed) =
+            exchange(&mut stream, "GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n");
+        assert!(response.contains("Connection: keep-alive\r\n"));
+        assert!(response.contains("Keep-Alive: timeout=5, max=2\r\n"));
+        assert!(!closed);
+        let (response, _) = exchange(&mut stream, "GET / HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        finish();
+    }
+
+    #[test]
+    fn test_http10_gets_close_delimited_stream() {
+        start_server();
+        // Without a length the body can only end with the connection
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let (response, closed) =
+            exchange(&mut stream, "GET /stream.sh HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n");
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Keep-Alive:"));
+        assert!(response.ends_with("\r\n\r\nstreamed"));
+        assert!(closed);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let (response, _) = exchange(
+            &mut stream,
+            "GET /stream.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("8\r\nstreamed\r\n0\r\n\r\n"));
+        finish();
+    }
+
+    #[test]
+    fn test_http11_honours_connection_close() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let (response, closed) = exchange(&mut stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.contains("Connection: keep-alive\r\n"));
+        assert!(!closed);
+
+        let (response, closed) = exchange(
+            &mut stream,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("home"));
+        assert!(closed);
+        finish();
+    }
+
+    #[test]
+    fn test_request_budget_per_connection() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let (response, closed) = exchange(&mut stream, &request.repeat(4));
+        assert!(closed);
+        assert_eq!(response.matches("HTTP/1.1 200").count(), 3);
+        assert!(response.contains("Keep-Alive: timeout=5, max=2\r\n"));
+        assert!(response.contains("Keep-Alive: timeout=5, max=1\r\n"));
+        let last = response.rsplit("HTTP/1.1 200").next().unwrap();
+        assert!(last.contains("Connection: close\r\n"));
+        finish();
+    }
+
+    #[test]
+    fn test_chunked_requests_on_one_connection() {
+        start_server();
+        // Each body fits the 16-byte limit on its own but not together
+        let body = "5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n";
+        let mut stream = TcpStream::connect("127.0.0.1:8136").unwrap();
+        let (response, closed) = exchange(
+            &mut stream,
+            &format!(
+                "POST /sink.sh HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+                body
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(!closed);
+
+        let (response, closed) = exchange(
+            &mut stream,
+            &format!(
+                "POST /sink.sh HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{}",
+                body
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("4\r\nread\r\n0\r\n\r\n"));
+        assert!(closed);
+        finish();
+    }
+}
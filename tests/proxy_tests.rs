@@ -0,0 +1,561 @@
+#[cfg(test)]
+mod proxy_tests {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig, UpstreamConfig};
+    use server_proxy::server::Server;
+    use server_proxy::upstream::{PeerGuard, UpstreamPool};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, mpsc};
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    /// Minimal upstream: reads one request, hands it to the test and answers
+    /// without a Content-Length so the proxy has to re-frame the body.
+    fn spawn_upstream(port: u16) -> mpsc::Receiver<String> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(300)))
+                    .unwrap();
+                let mut raw = Vec::new();
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    raw.extend_from_slice(&buf[..n]);
+                }
+                let _ = tx.send(String::from_utf8_lossy(&raw).into_owned());
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nX-Upstream: yes\r\nConnection: close\r\n\r\nfrom upstream")
+                    .unwrap();
+            }
+        });
+        rx
+    }
+
+    fn start_proxy_server(port: u16, upstream: &str) {
+        let test_root = format!("./tmp_proxy_test_{}", port);
+        let _ = fs::remove_dir_all(&test_root);
+        fs::create_dir_all(&test_root).unwrap();
+
+        let route = RouteConfig {
+            path: "/api".to_string(),
+            root: test_root.clone(),
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            proxy_pass: Some(upstream.to_string()),
+            ..Default::default()
+        };
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![port],
+            root: test_root,
+            routes: vec![route],
+            default_server: true,
+            ..Default::default()
+        });
+
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    fn read_response(stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+            if response.ends_with(b"0\r\n\r\n") {
+                break;
+            }
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_proxy_forwards_chunked_body() {
+        let upstream_rx = spawn_upstream(9101);
+        start_proxy_server(8101, "http://127.0.0.1:9101/v1");
+
+        let mut stream = TcpStream::connect("127.0.0.1:8101").unwrap();
+        stream
+            .write_all(
+                b"POST /api/items HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n",
+            )
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(b"6\r\n World\r\n0\r\n\r\n").unwrap();
+
+        let response = read_response(&mut stream);
+        println!("Proxy Response:\n{}", response);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("X-Upstream: yes"));
+        assert!(response.contains("Transfer-Encoding: chunked"));
+        assert!(response.contains("D\r\nfrom upstream\r\n0\r\n\r\n"));
+
+        let forwarded = upstream_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        println!("Upstream Request:\n{}", forwarded);
+        assert!(forwarded.starts_with("POST /v1/items HTTP/1.1\r\n"));
+        assert!(forwarded.contains("host: 127.0.0.1:9101\r\n"));
+        assert!(forwarded.contains("x-forwarded-for: 127.0.0.1\r\n"));
+        assert!(forwarded.contains("transfer-encoding: chunked\r\n"));
+        assert!(forwarded.ends_with("5\r\nHello\r\n6\r\n World\r\n0\r\n\r\n"));
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8101");
+    }
+
+    #[test]
+    fn test_proxy_forwards_normalized_path() {
+        let upstream_rx = spawn_upstream(9107);
+        start_proxy_server(8107, "http://127.0.0.1:9107/v1");
+
+        // Routed as /api/x, so it must not reach /v1/../api/x upstream
+        let mut stream = TcpStream::connect("127.0.0.1:8107").unwrap();
+        stream
+            .write_all(b"GET /api/../api/./x%20y?q=%2e%2e HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let forwarded = upstream_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(forwarded.starts_with("GET /v1/x%20y?q=%2e%2e HTTP/1.1\r\n"), "{}", forwarded);
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8107");
+    }
+
+    #[test]
+    fn test_proxy_skips_interim_responses() {
+        let listener = TcpListener::bind("127.0.0.1:9108").unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf);
+            stream
+                .write_all(b"HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload\r\n\r\n")
+                .unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfinal")
+                .unwrap();
+        });
+        start_proxy_server(8117, "http://127.0.0.1:9108");
+
+        let mut stream = TcpStream::connect("127.0.0.1:8117").unwrap();
+        stream
+            .write_all(b"GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(!response.contains("103"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\nfinal"));
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8117");
+    }
+
+    #[test]
+    fn test_proxy_rejects_out_of_range_status() {
+        let listener = TcpListener::bind("127.0.0.1:9109").unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf);
+            stream
+                .write_all(b"HTTP/1.1 700 Weird\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+        });
+        start_proxy_server(8118, "http://127.0.0.1:9109");
+
+        let mut stream = TcpStream::connect("127.0.0.1:8118").unwrap();
+        stream
+            .write_all(b"GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"), "{}", response);
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8118");
+    }
+
+    #[test]
+    fn test_proxy_bad_gateway() {
+        // Nothing listens on 9102
+        start_proxy_server(8102, "http://127.0.0.1:9102");
+
+        let mut stream = TcpStream::connect("127.0.0.1:8102").unwrap();
+        stream
+            .write_all(b"GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway"));
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8102");
+    }
+
+    fn pool_config(balance: &str) -> UpstreamConfig {
+        UpstreamConfig {
+            name: "backend".to_string(),
+            servers: vec!["127.0.0.1:9201".to_string(), "127.0.0.1:9202".to_string()],
+            balance: balance.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_upstream_pool_balancing() {
+        let rr = UpstreamPool::from_config(&pool_config("round_robin")).unwrap();
+        let first = rr.select(None).unwrap().addr;
+        let second = rr.select(None).unwrap().addr;
+        assert_ne!(first, second);
+        assert_eq!(rr.select(None).unwrap().addr, first);
+
+        let lc = Arc::new(UpstreamPool::from_config(&pool_config("least_conn")).unwrap());
+        let busy = lc.select(None).unwrap();
+        let _guard = PeerGuard::new(Arc::clone(&lc), Arc::clone(&busy));
+        for _ in 0..4 {
+            assert_ne!(lc.select(None).unwrap().addr, busy.addr);
+        }
+
+        let ih = UpstreamPool::from_config(&pool_config("ip_hash")).unwrap();
+        let ip = Some("10.0.0.7".parse().unwrap());
+        let pinned = ih.select(ip).unwrap().addr;
+        for _ in 0..4 {
+            assert_eq!(ih.select(ip).unwrap().addr, pinned);
+        }
+
+        // A peer that went down is skipped until it recovers
+        let down = ih.peers.iter().find(|p| p.addr == pinned).unwrap();
+        ih.record_failure(down);
+        ih.record_failure(down);
+        assert!(!down.is_healthy());
+        assert_ne!(ih.select(ip).unwrap().addr, pinned);
+        ih.record_success(down);
+        assert_eq!(ih.select(ip).unwrap().addr, pinned);
+
+        assert!(UpstreamPool::from_config(&pool_config("random")).is_err());
+    }
+
+    #[test]
+    fn test_passive_failures_expire_without_health_check() {
+        let test_root = "./tmp_proxy_test_8108";
+        let _ = fs::remove_dir_all(test_root);
+        fs::create_dir_all(test_root).unwrap();
+
+        let mut config = AppConfig::default();
+        config.upstreams.push(UpstreamConfig {
+            name: "backend".to_string(),
+            servers: vec!["127.0.0.1:9205".to_string()],
+            max_fails: 1,
+            fail_timeout: 1,
+            ..Default::default()
+        });
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8108],
+            root: test_root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/api".to_string(),
+                proxy_pass: Some("upstream://backend".to_string()),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        config.validate().unwrap();
+        let pool = Arc::clone(&config.pools[0]);
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let get = || {
+            let mut stream = TcpStream::connect("127.0.0.1:8108").unwrap();
+            stream
+                .write_all(b"GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            read_response(&mut stream)
+        };
+
+        // Nothing listens yet: the failed exchange takes the only peer out
+        assert!(get().starts_with("HTTP/1.1 502"));
+        assert!(!pool.peers[0].is_healthy());
+        assert!(get().starts_with("HTTP/1.1 503"));
+
+        // Once fail_timeout passes it is tried again, and a response clears it
+        let _upstream_rx = spawn_upstream(9205);
+        thread::sleep(Duration::from_millis(1100));
+        assert!(get().contains("from upstream"));
+        assert!(pool.peers[0].is_healthy());
+        assert_eq!(pool.peers[0].fails.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+        let _ = fs::remove_dir_all(test_root);
+    }
+
+    #[test]
+    fn test_health_checks_take_dead_peer_out() {
+        let _upstream_rx = spawn_upstream(9203);
+        let test_root = "./tmp_proxy_test_8103";
+        let _ = fs::remove_dir_all(test_root);
+        fs::create_dir_all(test_root).unwrap();
+
+        let mut config = AppConfig::default();
+        config.upstreams.push(UpstreamConfig {
+            name: "backend".to_string(),
+            // Nothing listens on 9204
+            servers: vec!["127.0.0.1:9203".to_string(), "127.0.0.1:9204".to_string()],
+            health_check: "/health".to_string(),
+            health_interval: 1,
+            max_fails: 1,
+            ..Default::default()
+        });
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8103],
+            root: test_root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/api".to_string(),
+                proxy_pass: Some("upstream://backend".to_string()),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        config.validate().unwrap();
+        let pool = Arc::clone(&config.pools[0]);
+
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(2500));
+
+        assert!(pool.peers[0].is_healthy());
+        assert!(!pool.peers[1].is_healthy());
+
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect("127.0.0.1:8103").unwrap();
+            stream
+                .write_all(b"GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let response = read_response(&mut stream);
+            assert!(response.contains("from upstream"));
+        }
+
+        let _ = fs::remove_dir_all(test_root);
+    }
+
+    fn start_forward_proxy(port: u16, forward_proxy: bool) {
+        let test_root = format!("./tmp_proxy_test_{}", port);
+        let _ = fs::remove_dir_all(&test_root);
+        fs::create_dir_all(&test_root).unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "proxy.local".to_string(),
+            ports: vec![port],
+            root: test_root,
+            default_server: true,
+            forward_proxy,
+            // The test origins stand in for :80 and :443
+            proxy_allow_hosts: vec!["127.0.0.1".to_string(), "nowhere.invalid".to_string()],
+            proxy_allow_ports: vec![9301],
+            connect_allow_ports: vec![9302, 9304],
+            ..Default::default()
+        });
+
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_forward_proxy_absolute_form() {
+        let upstream_rx = spawn_upstream(9301);
+        start_forward_proxy(8104, true);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8104").unwrap();
+        stream
+            .write_all(
+                b"GET http://127.0.0.1:9301/status?full=1 HTTP/1.1\r\nHost: 127.0.0.1:9301\r\nProxy-Connection: keep-alive\r\n\r\n",
+            )
+            .unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("from upstream"));
+
+        let forwarded = upstream_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(forwarded.starts_with("GET /status?full=1 HTTP/1.1\r\n"));
+        assert!(forwarded.contains("host: 127.0.0.1:9301\r\n"));
+        assert!(!forwarded.contains("proxy-connection"));
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8104");
+    }
+
+    #[test]
+    fn test_connect_tunnel() {
+        // Echo server standing in for a TLS origin
+        let listener = TcpListener::bind("127.0.0.1:9302").unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = stream.read(&mut buf) {
+                if n == 0 || stream.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        });
+        start_forward_proxy(8105, true);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8105").unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        // The first tunnel bytes travel with the CONNECT head
+        stream
+            .write_all(b"CONNECT 127.0.0.1:9302 HTTP/1.1\r\nHost: 127.0.0.1:9302\r\n\r\nhello")
+            .unwrap();
+
+        let mut received = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !received.ends_with(b"hello") {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "tunnel closed early");
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(
+            String::from_utf8_lossy(&received),
+            "HTTP/1.1 200 Connection Established\r\n\r\nhello"
+        );
+
+        stream.write_all(b"\x16\x03\x01binary").unwrap();
+        let n = stream.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"\x16\x03\x01binary");
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8105");
+    }
+
+    #[test]
+    fn test_tunnel_flushes_before_half_close() {
+        // Slow origin: counts everything up to EOF, then answers with the total
+        let listener = TcpListener::bind("127.0.0.1:9304").unwrap();
+        thread::spawn(move || {
+            let (mut origin, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(300));
+            let mut received = Vec::new();
+            origin.read_to_end(&mut received).unwrap();
+            origin.write_all(received.len().to_string().as_bytes()).unwrap();
+        });
+        start_forward_proxy(8115, true);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8115").unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"CONNECT 127.0.0.1:9304 HTTP/1.1\r\nHost: 127.0.0.1:9304\r\n\r\n")
+            .unwrap();
+        // Far more than the tunnel buffers, sent before the origin reads a byte
+        stream.write_all(&vec![b'x'; 4 * 1024 * 1024]).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&response),
+            "HTTP/1.1 200 Connection Established\r\n\r\n4194304"
+        );
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8115");
+    }
+
+    #[test]
+    fn test_forward_proxy_rejects_unlisted_targets() {
+        start_forward_proxy(8109, true);
+
+        for head in [
+            // CONNECT only reaches the tunnel ports
+            "CONNECT 127.0.0.1:9301 HTTP/1.1\r\nHost: 127.0.0.1:9301\r\n\r\n",
+            // and plain requests only the proxy ports
+            "GET http://127.0.0.1:9302/ HTTP/1.1\r\nHost: 127.0.0.1:9302\r\n\r\n",
+            "GET http://localhost:9301/ HTTP/1.1\r\nHost: localhost:9301\r\n\r\n",
+        ] {
+            let mut stream = TcpStream::connect("127.0.0.1:8109").unwrap();
+            stream.write_all(head.as_bytes()).unwrap();
+            let response = read_response(&mut stream);
+            assert!(response.starts_with("HTTP/1.1 403"), "{}: {}", head, response);
+        }
+
+        let config = ServerConfig {
+            proxy_allow_hosts: vec![".example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(config.forward_allowed("example.com", 80, false));
+        assert!(config.forward_allowed("API.example.com", 80, false));
+        assert!(!config.forward_allowed("badexample.com", 80, false));
+        assert!(!config.forward_allowed("api.example.com", 443, false));
+        assert!(config.forward_allowed("api.example.com", 443, true));
+        assert!(!config.forward_allowed("api.example.com", 22, true));
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8109");
+    }
+
+    #[test]
+    fn test_forward_proxy_unresolvable_host() {
+        start_forward_proxy(8116, true);
+
+        // The name is looked up off the loop; failing, it answers 502
+        let mut stream = TcpStream::connect("127.0.0.1:8116").unwrap();
+        stream
+            .write_all(b"GET http://nowhere.invalid:9301/ HTTP/1.1\r\nHost: nowhere.invalid:9301\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 502"), "{}", response);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8116").unwrap();
+        stream
+            .write_all(b"CONNECT nowhere.invalid:9302 HTTP/1.1\r\nHost: nowhere.invalid:9302\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 502"), "{}", response);
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8116");
+    }
+
+    #[test]
+    fn test_connect_rejected_without_forward_proxy() {
+        start_forward_proxy(8106, false);
+
+        let mut stream = TcpStream::connect("127.0.0.1:8106").unwrap();
+        stream
+            .write_all(b"CONNECT 127.0.0.1:9303 HTTP/1.1\r\nHost: 127.0.0.1:9303\r\n\r\n")
+            .unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"));
+
+        let _ = fs::remove_dir_all("./tmp_proxy_test_8106");
+    }
+}
@@ -0,0 +1,201 @@
+#[cfg(test)]
+mod reload {
+    use parser::FromYaml;
+    use server_proxy::config::AppConfig;
+    use mio::Poll;
+    use server_proxy::reload::{Reloaded, Reloader, apply};
+    use server_proxy::server::Server;
+    use server_proxy::slab::Slot;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, mpsc};
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    const DIR: &str = "./tmp_reload_test_8144";
+
+    fn write_config(workers: usize, ports: &str, root: &str) {
+        fs::write(
+            format!("{}/config.yaml", DIR),
+            format!(
+                "workers: {}\nservers:\n  - host: \"127.0.0.1\"\n    ports: [{}]\n    server_name: \"localhost\"\n    default_server: true\n    root: \"{}\"\n    routes:\n      - path: \"/\"\n        methods: [\"GET\"]\n        root: \"{}\"\n",
+                workers, ports, root, root
+            ),
+        )
+        .unwrap();
+    }
+
+    fn load() -> Result<AppConfig, String> {
+        let content = fs::read_to_string(format!("{}/config.yaml", DIR)).map_err(|e| e.to_string())?;
+        let mut config = AppConfig::from_str(&content).map_err(|e| format!("{:?}", e))?;
+        config.validate().map_err(|e| format!("{:?}", e))?;
+        Ok(config)
+    }
+
+    fn signal(signal: libc::c_int) {
+        // SAFETY: signals our own process, whose handler only wakes the loops
+        unsafe { libc::kill(libc::getpid(), signal) };
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    /// Sends a GET on `stream` and returns the body of the response.
+    fn get(stream: &mut TcpStream, path: &str) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut data = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed early");
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length: usize = text[..end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().unwrap())
+                    })
+                    .unwrap();
+                if data.len() >= end + 4 + length {
+                    return text[end + 4..end + 4 + length].to_string();
+                }
+            }
+        }
+    }
+
+    fn fetch(port: u16) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        get(&mut stream, "/index.html")
+    }
+
+    /// The scenarios share the process-wide SIGHUP counter, so they run one
+    /// after the other in a single test.
+    #[test]
+    fn test_sighup_swaps_config_and_keeps_connections() {
+        let _ = fs::remove_dir_all(DIR);
+        let old_root = format!("{}/old", DIR);
+        let new_root = format!("{}/new", DIR);
+        fs::create_dir_all(&old_root).unwrap();
+        fs::create_dir_all(&new_root).unwrap();
+        fs::write(format!("{}/index.html", old_root), "old").unwrap();
+        fs::write(format!("{}/index.html", new_root), "new").unwrap();
+
+        write_config(2, "8144", &old_root);
+        let config = load().unwrap();
+        let (tx, stopped) = mpsc::channel();
+        thread::spawn(move || {
+            let reloader = Reloader::new(config.workers, load);
+            let result = Server::serve(config, Some(Arc::new(reloader)));
+            let _ = tx.send(result.is_ok());
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let mut kept = TcpStream::connect("127.0.0.1:8144").unwrap();
+        assert_eq!(get(&mut kept, "/index.html"), "old");
+
+        // 1. New root and a new port; the open connection keeps its snapshot
+        write_config(2, "8144, 8145", &new_root);
+        signal(libc::SIGHUP);
+        assert_eq!(fetch(8144), "new");
+        assert_eq!(fetch(8145), "new");
+        assert_eq!(get(&mut kept, "/index.html"), "old");
+
+        // 2. An invalid file is rejected and the running config stays
+        write_config(0, "8145", &old_root);
+        signal(libc::SIGHUP);
+        assert_eq!(fetch(8144), "new");
+        assert_eq!(fetch(8145), "new");
+
+        // 3. A port dropped from the file stops listening in every worker
+        write_config(2, "8145", &new_root);
+        signal(libc::SIGHUP);
+        for _ in 0..4 {
+            assert!(TcpStream::connect("127.0.0.1:8144").is_err());
+        }
+        assert_eq!(fetch(8145), "new");
+        drop(kept);
+
+        signal(libc::SIGTERM);
+        assert_eq!(stopped.recv_timeout(Duration::from_secs(5)), Ok(true));
+        let _ = fs::remove_dir_all(DIR);
+    }
+
+    #[test]
+    fn test_unchanged_upstreams_keep_their_pool() {
+        let parse = |api_port: u16| {
+            let mut config = AppConfig::from_str(&format!(
+                "servers:\n  - ports: [9162]\n    root: \".\"\n    routes:\n      - path: \"/api\"\n        proxy_pass: \"upstream://api\"\n      - path: \"/static\"\n        proxy_pass: \"upstream://assets\"\nupstreams:\n  - name: api\n    servers: [\"127.0.0.1:{}\"]\n  - name: assets\n    servers: [\"127.0.0.1:9164\"]\n",
+                api_port
+            ))
+            .unwrap();
+            config.validate().unwrap();
+            config
+        };
+        let pool = |config: &AppConfig, name: &str| {
+            Arc::clone(config.pools.iter().find(|p| p.name == name).unwrap())
+        };
+        let running = parse(9163);
+        let assets = pool(&running, "assets");
+        assets.record_failure(&assets.peers[0]);
+
+        let mut reloaded = parse(9165);
+        reloaded.keep_pools(&running.pools);
+        let route = |config: &AppConfig, path: &str| {
+            let route = config.servers[0].routes.iter().find(|r| r.path == path).unwrap();
+            Arc::clone(route.upstream.as_ref().unwrap())
+        };
+
+        // The changed block starts over, the other one keeps its peers
+        assert!(!Arc::ptr_eq(&pool(&reloaded, "api"), &pool(&running, "api")));
+        assert!(Arc::ptr_eq(&pool(&reloaded, "assets"), &assets));
+        assert_eq!(pool(&reloaded, "assets").peers[0].fails.load(Ordering::Relaxed), 1);
+        assert!(Arc::ptr_eq(&route(&reloaded, "/static"), &assets));
+        assert!(Arc::ptr_eq(&route(&reloaded, "/api"), &pool(&reloaded, "api")));
+    }
+
+    #[test]
+    fn test_apply_moves_a_port_between_addresses() {
+        let config = |host: &str, port: u16| {
+            let mut config = AppConfig::from_str(&format!(
+                "servers:\n  - host: \"{}\"\n    ports: [{}]\n    root: \".\"\n",
+                host, port
+            ))
+            .unwrap();
+            config.validate().unwrap();
+            config
+        };
+        let reloaded = |host: &str, port: u16| Reloaded {
+            groups: Server::listener_groups(config(host, port).servers).unwrap(),
+            pools: Vec::new(),
+            shutdown_timeout: Duration::ZERO,
+        };
+        let listening = |server: &Server| -> Vec<String> {
+            server
+                .tokens
+                .iter()
+                .filter_map(|(_, slot)| match slot {
+                    Slot::Listener(listener, _) => Some(listener.local_addr().unwrap().to_string()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let poll = Poll::new().unwrap();
+        let mut server = Server::new(config("127.0.0.1", 8146), &poll).unwrap();
+
+        // The wildcard covers the old address, so it can only be bound once
+        // the old listener is gone
+        assert_eq!(apply(&mut server, &poll, &reloaded("0.0.0.0", 8146)).unwrap(), (1, 1));
+        assert_eq!(listening(&server), vec!["0.0.0.0:8146"]);
+
+        // A bind that fails puts the closed listener back
+        let _taken = std::net::TcpListener::bind("127.0.0.1:8147").unwrap();
+        assert!(apply(&mut server, &poll, &reloaded("127.0.0.1", 8147)).is_err());
+        assert_eq!(listening(&server), vec!["0.0.0.0:8146"]);
+        assert!(TcpStream::connect("127.0.0.1:8146").is_ok());
+    }
+}
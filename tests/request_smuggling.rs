@@ -0,0 +1,184 @@
+#[cfg(test)]
+mod request_smuggling {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::http::{
+        ParseError, StatusCode, merge_header, parse_chunk_size, parse_header_line, validate_framing,
+    };
+    use server_proxy::server::Server;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    #[test]
+    fn test_header_line_rules() {
+        assert_eq!(
+            parse_header_line(b"Content-Type:  text/plain\t").unwrap(),
+            ("content-type".to_string(), "text/plain".to_string())
+        );
+        assert!(parse_header_line(b" folded value").is_err());
+        assert!(parse_header_line(b"\tfolded value").is_err());
+        assert!(parse_header_line(b"Content-Length : 5").is_err());
+        assert!(parse_header_line(b"Bad Name: x").is_err());
+        assert!(parse_header_line(b"Bad(Name): x").is_err());
+        assert!(parse_header_line(b": empty").is_err());
+        assert!(parse_header_line(b"X-Test: a\nInjected: b").is_err());
+        assert!(parse_header_line(b"X-Test: a\rb").is_err());
+        assert!(parse_header_line(b"X-Test: a\0b").is_err());
+        assert!(parse_header_line(b"no-colon").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_headers_and_framing() {
+        let mut headers = HashMap::new();
+        merge_header(&mut headers, "accept".into(), "text/html".into()).unwrap();
+        merge_header(&mut headers, "accept".into(), "text/plain".into()).unwrap();
+        assert_eq!(headers["accept"], "text/html, text/plain");
+        merge_header(&mut headers, "cookie".into(), "a=1".into()).unwrap();
+        merge_header(&mut headers, "cookie".into(), "b=2".into()).unwrap();
+        assert_eq!(headers["cookie"], "a=1; b=2");
+        merge_header(&mut headers, "content-length".into(), "5".into()).unwrap();
+        assert!(merge_header(&mut headers, "content-length".into(), "5".into()).is_err());
+        merge_header(&mut headers, "host".into(), "a".into()).unwrap();
+        assert!(merge_header(&mut headers, "host".into(), "b".into()).is_err());
+
+        let framing = |pairs: &[(&str, &str)]| {
+            let headers: HashMap<String, String> = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            validate_framing("HTTP/1.1", &headers)
+        };
+        assert!(framing(&[("content-length", "42")]).is_ok());
+        assert!(framing(&[("transfer-encoding", "Chunked")]).is_ok());
+        assert!(framing(&[("content-length", "+42")]).is_err());
+        assert!(framing(&[("content-length", "4, 4")]).is_err());
+        assert!(framing(&[("content-length", "")]).is_err());
+        assert!(framing(&[("content-length", "5"), ("transfer-encoding", "chunked")]).is_err());
+        assert!(framing(&[("transfer-encoding", "chunked, identity")]).is_err());
+        assert!(framing(&[("transfer-encoding", "xchunked")]).is_err());
+        assert!(framing(&[("transfer-encoding", "chunked, chunked")]).is_err());
+        assert_eq!(
+            framing(&[("transfer-encoding", "gzip, chunked")]),
+            Err(ParseError::Error(StatusCode::NOT_IMPLEMENTED))
+        );
+        let te = HashMap::from([("transfer-encoding".to_string(), "chunked".to_string())]);
+        assert!(validate_framing("HTTP/1.0", &te).is_err());
+    }
+
+    #[test]
+    fn test_chunk_size_rules() {
+        assert_eq!(parse_chunk_size(b"1a").unwrap(), 26);
+        assert_eq!(parse_chunk_size(b"A;name=value").unwrap(), 10);
+        assert_eq!(parse_chunk_size(b"0").unwrap(), 0);
+        assert!(parse_chunk_size(b"").is_err());
+        assert!(parse_chunk_size(b" 5").is_err());
+        assert!(parse_chunk_size(b"5 ").is_err());
+        assert!(parse_chunk_size(b"+5").is_err());
+        assert!(parse_chunk_size(b"0x5").is_err());
+        assert!(parse_chunk_size(b"5\n").is_err());
+        assert!(parse_chunk_size(b"10000000000000000").is_err());
+    }
+
+    fn send(request: &[u8]) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:8133").unwrap();
+        stream.write_all(request).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_ambiguous_requests_are_rejected() {
+        let root = "./tmp_smuggling_test_8133";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(format!("{}/uploads", root)).unwrap();
+        fs::write(format!("{}/index.html", root), "home").unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8133],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                upload_dir: "uploads".to_string(),
+                methods: vec!["GET".to_string(), "PUT".to_string()],
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let bad: [&[u8]; 10] = [
+            b"PUT /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            b"PUT /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd",
+            b"PUT /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc",
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Long: a\r\n b\r\n\r\n",
+            b"PUT /a.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length : 3\r\n\r\nabc",
+            b"GET / HTTP/1.1\nHost: localhost\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: localhost\nX-Injected: 1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nX[Bad]: 1\r\n\r\n",
+            b"PUT /b.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n 3\r\nabc\r\n0\r\n\r\n",
+            b"PUT /b.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\nabc\r\n0\r\n\r\n",
+        ];
+        for request in bad {
+            let response = send(request);
+            assert!(
+                response.starts_with("HTTP/1.1 400"),
+                "{}\n=> {}",
+                String::from_utf8_lossy(request),
+                response
+            );
+        }
+
+        let response = send(
+            b"PUT /a.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 501"));
+        // Rejected while reading the headers, before any handler ran
+        assert!(!std::path::Path::new(&format!("{}/uploads/a.txt", root)).exists());
+
+        // Extensions and announced trailers are still fine
+        let response = send(
+            b"PUT /ok.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\r\n3;sig=abc\r\nabc\r\n0\r\nX-Checksum: 1\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+        assert_eq!(
+            fs::read_to_string(format!("{}/uploads/ok.txt", root)).unwrap(),
+            "abc"
+        );
+
+        // A folded line inside the trailers is rejected like in the headers
+        let response = send(
+            b"PUT /ok.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\nX-Checksum: 1\r\n folded\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+
+        // A huge size after a first chunk must not wrap past client_max_body_size
+        let response = send(
+            b"PUT /big.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\nffffffffffffffff\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,120 @@
+#[cfg(test)]
+mod request_target {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::http::{QueryParams, normalize_path, percent_decode, percent_encode_path};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/a%20b.txt").as_deref(), Some("/a b.txt"));
+        assert_eq!(normalize_path("/a/./b//c").as_deref(), Some("/a/b/c"));
+        assert_eq!(normalize_path("/a/b/../c/").as_deref(), Some("/a/c/"));
+        assert_eq!(normalize_path("/a/..").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/%2e%2e/etc/passwd"), None);
+        assert_eq!(normalize_path("/../etc/passwd"), None);
+        assert_eq!(normalize_path("/a%00b"), None);
+        assert_eq!(normalize_path("/a%zz"), None);
+        assert_eq!(normalize_path("/a%c3"), None);
+        assert_eq!(percent_decode("a+b%21", true).unwrap(), b"a b!");
+        assert_eq!(percent_decode("a+b", false).unwrap(), b"a+b");
+        assert_eq!(percent_encode_path("/a b/100%/ü?#"), "/a%20b/100%25/%C3%BC%3F%23");
+        assert_eq!(percent_encode_path("/v1/items;x=1"), "/v1/items;x=1");
+    }
+
+    #[test]
+    fn test_query_params_multimap() {
+        let params = QueryParams::parse("tag=a&name=J%C3%BCrgen+M&tag=b&flag&=x");
+        assert_eq!(params.get("tag"), Some("a"));
+        assert_eq!(params.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(params.get("name"), Some("Jürgen M"));
+        assert!(params.contains_key("flag"));
+        assert_eq!(params.get("flag"), Some(""));
+        assert_eq!(params.get("missing"), None);
+        assert_eq!(params.len(), 5);
+        assert!(QueryParams::parse("").is_empty());
+    }
+
+    fn read_all(stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn send(request: &[u8]) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:8120").unwrap();
+        stream.write_all(request).unwrap();
+        read_all(&mut stream)
+    }
+
+    #[test]
+    fn test_decoded_path_and_cgi_query_string() {
+        let root = "./tmp_target_test_8120";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(format!("{}/docs", root)).unwrap();
+        fs::write(format!("{}/docs/a b.txt", root), "spaced").unwrap();
+        fs::write(
+            format!("{}/env.sh", root),
+            "printf 'Content-Type: text/plain\\r\\n\\r\\n'\nprintf '%s|%s' \"$QUERY_STRING\" \"$PATH_INFO\"\n",
+        )
+        .unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8120],
+            root: root.to_string(),
+            routes: vec![
+                RouteConfig {
+                    path: "/docs".to_string(),
+                    root: format!("{}/docs", root),
+                    ..Default::default()
+                },
+                RouteConfig {
+                    path: "/".to_string(),
+                    root: root.to_string(),
+                    cgi_ext: Some(".sh".to_string()),
+                    ..Default::default()
+                },
+            ],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let response = send(b"GET /docs/a%20b.txt?download=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("spaced"));
+
+        let response = send(b"GET /docs/./x/../a%20b.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("spaced"));
+
+        let response = send(b"GET /env.sh?q=a%20b&tag=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("q=a%20b&tag=1|/env.sh"));
+
+        let response = send(b"GET /../env.sh HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,206 @@
+#[cfg(test)]
+mod response_headers {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::http::{HeaderMap, HttpResponse, StatusCode};
+    use server_proxy::utils::parse_http_date;
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    #[test]
+    fn test_header_map_set_and_append() {
+        let mut headers = HeaderMap::new();
+        headers.set("Content-Type", "text/plain");
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        headers.set("X-Extra", "x");
+
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
+        assert_eq!(headers.len(), 4);
+
+        // set collapses repeats in place of the first one
+        headers.set("set-cookie", "c=3");
+        let names: Vec<&str> = headers.iter().map(|(k, _)| k).collect();
+        assert_eq!(names, vec!["content-type", "set-cookie", "x-extra"]);
+        assert_eq!(headers.get("set-cookie"), Some("c=3"));
+
+        assert_eq!(headers.remove("Content-Type").as_deref(), Some("text/plain"));
+        assert!(!headers.contains_key("content-type"));
+        assert_eq!(headers.remove("missing"), None);
+    }
+
+    #[test]
+    fn test_response_serializes_in_order() {
+        let mut res = HttpResponse::new(StatusCode::OK);
+        res.set_header("Content-Type", "text/plain");
+        res.append_header("set-cookie", "a=1");
+        res.append_header("set-cookie", "b=2");
+        res.set_header("CONTENT-LENGTH", "0");
+
+        let head = String::from_utf8(res.to_bytes_headers_only()).unwrap();
+        assert_eq!(head.matches("Content-Length:").count(), 1);
+        assert_eq!(head.matches("Content-Type:").count(), 1);
+        let first = head.find("Set-Cookie: a=1\r\n").unwrap();
+        let second = head.find("Set-Cookie: b=2\r\n").unwrap();
+        assert!(first < second);
+    }
+
+    fn read_all(stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_cgi_multiple_set_cookie() {
+        let root = "./tmp_headers_test_8137";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(
+            format!("{}/cookies.sh", root),
+            "printf 'Content-Type: text/plain\\r\\nSet-Cookie: a=1\\r\\nSet-Cookie: b=2\\r\\nContent-Length: 2\\r\\n\\r\\nok'\n",
+        )
+        .unwrap();
+        fs::write(
+            format!("{}/bogus.sh", root),
+            "printf 'Status: 700 Weird\\r\\nContent-Length: 2\\r\\n\\r\\nok'\n",
+        )
+        .unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8137],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                cgi_ext: Some(".sh".to_string()),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let mut stream = TcpStream::connect("127.0.0.1:8137").unwrap();
+        stream
+            .write_all(b"GET /cookies.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let response = read_all(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Set-Cookie: a=1\r\n"));
+        assert!(response.contains("Set-Cookie: b=2\r\n"));
+        assert_eq!(response.matches("Content-Length:").count(), 1);
+        assert!(response.ends_with("ok"));
+
+        // A status outside 100-599 is the script's fault
+        let mut stream = TcpStream::connect("127.0.0.1:8137").unwrap();
+        stream
+            .write_all(b"GET /bogus.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let response = read_all(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"), "{}", response);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_status_registry() {
+        let reason = |code| StatusCode::new(code).unwrap().reason();
+        assert_eq!(reason(504), "Gateway Timeout");
+        assert_eq!(reason(505), "HTTP Version Not Supported");
+        assert_eq!(reason(413), "Content Too Large");
+        assert_eq!(reason(418), "Client Error");
+        assert_eq!(reason(599), "Server Error");
+        assert!(StatusCode::new(42).is_none());
+        assert!(StatusCode::new(600).is_none());
+        assert!(StatusCode::new(700).is_none());
+
+        let code = StatusCode::new(308).unwrap();
+        assert!(code.is_redirection() && code.is_registered());
+        assert_eq!(code, StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(code.to_string(), "308 Permanent Redirect");
+        assert!(!StatusCode::new(599).unwrap().is_registered());
+        assert!(StatusCode::new(599).unwrap().is_server_error());
+
+        assert_eq!(StatusCode::NOT_FOUND.reason(), "Not Found");
+    }
+
+    #[test]
+    fn test_date_and_server_headers() {
+        let root = "./tmp_headers_test_8138";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(format!("{}/index.html", root), "hi").unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8138],
+            root: root.to_string(),
+            server_software: "edge".to_string(),
+            server_tokens: false,
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let send = |request: &[u8]| {
+            let mut stream = TcpStream::connect("127.0.0.1:8138").unwrap();
+            stream.write_all(request).unwrap();
+            read_all(&mut stream)
+        };
+
+        for request in [
+            &b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"[..],
+            &b"GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"[..],
+            // rejected while parsing, before any virtual host is chosen
+            &b"GET / HTTP/1.1\r\nHost: localhost\r\nBad Header: x\r\n\r\n"[..],
+        ] {
+            let response = send(request);
+            let head = response.split("\r\n\r\n").next().unwrap();
+            assert!(head.lines().any(|l| l == "Server: edge"), "{}", head);
+            let date = head
+                .lines()
+                .find_map(|l| l.strip_prefix("Date: "))
+                .expect("missing Date header");
+            assert!(parse_http_date(date).is_some());
+            assert_eq!(head.matches("Date:").count(), 1);
+        }
+
+        let response = send(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
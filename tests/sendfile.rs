@@ -0,0 +1,120 @@
+#[cfg(test)]
+mod sendfile {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use server_proxy::utils::send_file;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_send_file_offsets() {
+        let path = "./tmp_sendfile_unit.bin";
+        fs::write(path, b"0123456789").unwrap();
+        let file = fs::File::open(path).unwrap();
+        let (tx, mut rx) = UnixStream::pair().unwrap();
+
+        match send_file(&tx, &file, 3, 4) {
+            Ok(n) => {
+                assert_eq!(n, 4);
+                let mut buf = [0u8; 4];
+                rx.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, b"3456");
+                // Past the end of the file nothing is sent
+                assert_eq!(send_file(&tx, &file, 10, 4).unwrap(), 0);
+            }
+            // Platforms without sendfile(2) use the buffered path
+            Err(e) => assert!(server_proxy::utils::sendfile_unsupported(&e)),
+        }
+        let _ = fs::remove_file(path);
+    }
+
+    /// Reads one response and returns its head and exactly Content-Length body bytes.
+    fn read_response(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed early");
+            data.extend_from_slice(&buf[..n]);
+            if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&data[..pos]).into_owned();
+                let len: usize = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let mut body = data[pos + 4..].to_vec();
+                while body.len() < len {
+                    let n = stream.read(&mut buf).unwrap();
+                    assert!(n > 0, "body cut short");
+                    body.extend_from_slice(&buf[..n]);
+                    // Let the server hit a full socket now and then
+                    if body.len() % (1024 * 1024) < n {
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                }
+                return (head, body);
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_file_download() {
+        let root = "./tmp_sendfile_test_8139";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        let content = pattern(8 * 1024 * 1024 + 17);
+        fs::write(format!("{}/media.bin", root), &content).unwrap();
+
+        let mut config = AppConfig::default();
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8139],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || {
+            let poll = Poll::new().unwrap();
+            let mut server = Server::new(config, &poll).unwrap();
+            server.run(poll).unwrap();
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let mut stream = TcpStream::connect("127.0.0.1:8139").unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // Full body, then a range on the same connection
+        stream
+            .write_all(b"GET /media.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (head, body) = read_response(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(body == content, "full body differs");
+
+        stream
+            .write_all(b"GET /media.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes=3000000-5999999\r\n\r\n")
+            .unwrap();
+        let (head, body) = read_response(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 206"));
+        assert!(body == content[3_000_000..6_000_000], "range body differs");
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
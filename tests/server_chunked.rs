@@ -1,3 +1,5 @@
+#![allow(clippy::field_reassign_with_default)]
+
 #[cfg(test)]
 mod integration_tests {
     use mio::Poll;
@@ -7,7 +9,6 @@ mod integration_tests {
     use std::error::Error;
     use std::io::{Read, Write};
     use std::net::TcpStream;
-    use std::path::Path;
     use std::thread::sleep;
     use std::time::Duration;
     use std::{fs, thread};
@@ -262,7 +263,7 @@ mod integration_tests {
         let res_text = String::from_utf8_lossy(&response[..n]);
 
         // 4. Verify we got the 413 and not a "Connection Reset"
-        assert!(res_text.contains("413 Payload Too Large"));
+        assert!(res_text.contains("413 Content Too Large"));
         println!("Verified: Server sent 413 before closing.");
     }
 
@@ -0,0 +1,135 @@
+#[cfg(test)]
+mod shutdown {
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+    use std::{fs, thread};
+
+    fn config(root: &str, port: u16, shutdown_timeout: u64) -> AppConfig {
+        let mut config = AppConfig {
+            shutdown_timeout,
+            ..Default::default()
+        };
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![port],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                cgi_ext: Some(".sh".to_string()),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        config
+    }
+
+    /// Starts the server and reports when `run_workers` returns.
+    fn spawn(config: AppConfig) -> mpsc::Receiver<bool> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = Server::run_workers(config);
+            let _ = tx.send(result.is_ok());
+        });
+        thread::sleep(Duration::from_millis(300));
+        rx
+    }
+
+    fn terminate() {
+        // SAFETY: signals our own process, whose handler only wakes the loops
+        unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+    }
+
+    fn read_to_close(stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut data = Vec::new();
+        let _ = stream.read_to_end(&mut data);
+        String::from_utf8_lossy(&data).into_owned()
+    }
+
+    /// Both scenarios share the process-wide signal handler, so they run
+    /// one after the other in a single test.
+    #[test]
+    fn test_sigterm_drains_then_cuts_off() {
+        let root = "./tmp_shutdown_test_8142";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(format!("{}/index.html", root), "hello").unwrap();
+        fs::write(
+            format!("{}/slow.sh", root),
+            "sleep 1\nprintf 'Content-Type: text/plain\\r\\nContent-Length: 4\\r\\n\\r\\ndone'\n",
+        )
+        .unwrap();
+        let pid_file = fs::canonicalize(root).unwrap().join("stuck.pid");
+        fs::write(
+            format!("{}/stuck.sh", root),
+            format!("echo $$ > {}\nexec sleep 30\n", pid_file.display()),
+        )
+        .unwrap();
+
+        // 1. In-flight requests finish, idle connections and the listener close
+        let stopped = spawn(config(root, 8142, 10));
+
+        let mut idle = TcpStream::connect("127.0.0.1:8142").unwrap();
+        idle.write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut first = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !first.ends_with(b"hello") {
+            let n = idle.read(&mut buf).unwrap();
+            assert!(n > 0);
+            first.extend_from_slice(&buf[..n]);
+        }
+
+        let mut busy = TcpStream::connect("127.0.0.1:8142").unwrap();
+        busy.write_all(b"GET /slow.sh HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        // A request line cut in half is in flight too
+        let mut partial = TcpStream::connect("127.0.0.1:8142").unwrap();
+        partial.write_all(b"GET /index.html HT").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        terminate();
+        thread::sleep(Duration::from_millis(100));
+        partial
+            .write_all(b"TP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        assert_eq!(read_to_close(&mut idle), "");
+        let response = read_to_close(&mut busy);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("done"));
+        let response = read_to_close(&mut partial);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("hello"));
+        assert_eq!(stopped.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(TcpStream::connect("127.0.0.1:8142").is_err());
+
+        // 2. Past the drain timeout the request is cut off and the script reaped
+        let stopped = spawn(config(root, 8143, 1));
+        let mut stuck = TcpStream::connect("127.0.0.1:8143").unwrap();
+        stuck
+            .write_all(b"GET /stuck.sh HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+        let started = Instant::now();
+        terminate();
+
+        assert_eq!(stopped.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert!(read_to_close(&mut stuck).is_empty());
+        let pid: i32 = fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+        // SAFETY: signal 0 only checks whether the process still exists
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,152 @@
+#[cfg(test)]
+mod slow_clients {
+    use mio::Poll;
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+    use std::{fs, thread};
+
+    const ROOT: &str = "./tmp_slow_test_8135";
+    const BIG_FILE: u64 = 128 * 1024 * 1024;
+    const TESTS: usize = 4;
+    static START: Once = Once::new();
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    fn start_server() {
+        START.call_once(|| {
+            let _ = fs::remove_dir_all(ROOT);
+            fs::create_dir_all(format!("{}/uploads", ROOT)).unwrap();
+            fs::write(format!("{}/index.html", ROOT), "home").unwrap();
+            // Sparse, and well beyond what the socket buffers can absorb
+            fs::File::create(format!("{}/big.bin", ROOT))
+                .unwrap()
+                .set_len(BIG_FILE)
+                .unwrap();
+
+            let mut config = AppConfig::default();
+            config.servers.push(ServerConfig {
+                server_name: "localhost".to_string(),
+                ports: vec![8135],
+                root: ROOT.to_string(),
+                header_timeout: 1,
+                keepalive_timeout: 1,
+                send_timeout: 1,
+                body_min_rate: 1000,
+                routes: vec![RouteConfig {
+                    path: "/".to_string(),
+                    root: ROOT.to_string(),
+                    upload_dir: "uploads".to_string(),
+                    methods: vec!["GET".to_string(), "PUT".to_string()],
+                    ..Default::default()
+                }],
+                default_server: true,
+                ..Default::default()
+            });
+            thread::spawn(move || {
+                let poll = Poll::new().unwrap();
+                let mut server = Server::new(config, &poll).unwrap();
+                server.run(poll).unwrap();
+            });
+            thread::sleep(Duration::from_millis(300));
+        });
+    }
+
+    /// The last test to finish removes the shared root.
+    fn finish() {
+        if FINISHED.fetch_add(1, Ordering::SeqCst) + 1 == TESTS {
+            let _ = fs::remove_dir_all(ROOT);
+        }
+    }
+
+    /// Reads until the server closes the connection.
+    fn read_to_close(stream: &mut TcpStream) -> (String, Duration) {
+        let start = Instant::now();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        (String::from_utf8_lossy(&response).into_owned(), start.elapsed())
+    }
+
+    #[test]
+    fn test_incomplete_head_gets_408() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8135").unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: local").unwrap();
+        let (response, elapsed) = read_to_close(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(elapsed < Duration::from_secs(5));
+        finish();
+    }
+
+    #[test]
+    fn test_silent_and_idle_connections_are_dropped() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8135").unwrap();
+        let (response, elapsed) = read_to_close(&mut stream);
+        assert!(response.is_empty());
+        assert!(elapsed < Duration::from_secs(5));
+
+        let mut stream = TcpStream::connect("127.0.0.1:8135").unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (response, elapsed) = read_to_close(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("home"));
+        assert!(elapsed < Duration::from_secs(5));
+        finish();
+    }
+
+    #[test]
+    fn test_trickled_body_gets_408() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8135").unwrap();
+        stream
+            .write_all(b"PUT /slow.bin HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000\r\n\r\n")
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        thread::spawn(move || {
+            for _ in 0..20 {
+                if writer.write_all(b"0123456789").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(500));
+            }
+        });
+        let (response, elapsed) = read_to_close(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(elapsed < Duration::from_secs(9));
+        finish();
+    }
+
+    #[test]
+    fn test_client_that_stops_reading_is_dropped() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8135").unwrap();
+        stream
+            .write_all(b"GET /big.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_secs(4));
+
+        let mut received = 0;
+        let mut buf = vec![0u8; 64 * 1024];
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            received += n;
+        }
+        assert!(received > 0);
+        assert!((received as u64) < BIG_FILE);
+        finish();
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod timer_heap {
+    use mio::Token;
+    use server_proxy::utils::TimerHeap;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_deadlines_pop_in_order() {
+        let base = Instant::now();
+        let mut timers = TimerHeap::new();
+        timers.schedule(Token(3), base + Duration::from_secs(3));
+        timers.schedule(Token(1), base + Duration::from_secs(1));
+        timers.schedule(Token(2), base + Duration::from_secs(2));
+        assert_eq!(timers.len(), 3);
+        assert_eq!(timers.next_deadline(), Some(base + Duration::from_secs(1)));
+
+        assert_eq!(timers.pop_expired(base), None);
+        let later = base + Duration::from_secs(2);
+        assert_eq!(timers.pop_expired(later), Some(Token(1)));
+        assert_eq!(timers.pop_expired(later), Some(Token(2)));
+        assert_eq!(timers.pop_expired(later), None);
+        assert_eq!(timers.len(), 1);
+    }
+
+    #[test]
+    fn test_reschedule_and_cancel() {
+        let base = Instant::now();
+        let mut timers = TimerHeap::new();
+        timers.schedule(Token(0), base + Duration::from_secs(1));
+        timers.schedule(Token(1), base + Duration::from_secs(2));
+
+        // Moving a deadline leaves the old entry behind, never to fire
+        timers.schedule(Token(0), base + Duration::from_secs(5));
+        timers.cancel(Token(1));
+        timers.cancel(Token(7));
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.next_deadline(), Some(base + Duration::from_secs(5)));
+        assert_eq!(timers.pop_expired(base + Duration::from_secs(4)), None);
+        assert_eq!(timers.pop_expired(base + Duration::from_secs(5)), Some(Token(0)));
+        assert!(timers.is_empty());
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_many_reschedules_stay_bounded() {
+        let base = Instant::now();
+        let mut timers = TimerHeap::new();
+        for i in 0..100_000u64 {
+            timers.schedule(Token((i % 10) as usize), base + Duration::from_millis(i));
+        }
+        assert_eq!(timers.len(), 10);
+        let mut popped = Vec::new();
+        while let Some(token) = timers.pop_expired(base + Duration::from_secs(1000)) {
+            popped.push(token.0);
+        }
+        popped.sort();
+        assert_eq!(popped, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod token_slab {
+    use mio::Token;
+    use server_proxy::cgi::{CgiParsingState, force_cgi_timeout};
+    use server_proxy::http::{ActiveAction, HttpConnection};
+    use server_proxy::slab::{Slot, TokenSlab};
+    use std::os::unix::net::UnixStream;
+    use std::process::Command;
+    use std::time::Instant;
+
+    #[test]
+    fn test_tokens_are_recycled() {
+        let mut tokens = TokenSlab::new();
+        assert_eq!(tokens.vacant_token(), Token(0));
+
+        let a = tokens.insert(Slot::Pipe(Token(100)));
+        let b = tokens.insert(Slot::Pipe(Token(100)));
+        let c = tokens.insert(Slot::Pipe(Token(200)));
+        assert_eq!((a, b, c), (Token(0), Token(1), Token(2)));
+        assert_eq!(tokens.len(), 3);
+
+        assert!(matches!(tokens.remove(b), Some(Slot::Pipe(Token(100)))));
+        assert!(tokens.remove(b).is_none());
+        assert!(tokens.get(b).is_none());
+
+        // The freed index is handed out before the arena grows
+        assert_eq!(tokens.vacant_token(), b);
+        assert_eq!(tokens.insert(Slot::Pipe(Token(300))), b);
+        assert!(matches!(tokens.get(b), Some(Slot::Pipe(Token(300)))));
+        assert_eq!(tokens.insert(Slot::Pipe(Token(300))), Token(3));
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn test_lend_only_takes_clients() {
+        let mut tokens = TokenSlab::new();
+        let pipe = tokens.insert(Slot::Pipe(Token(7)));
+        assert!(tokens.lend(pipe).is_none());
+        assert!(matches!(tokens.get(pipe), Some(Slot::Pipe(Token(7)))));
+        assert!(tokens.lend(Token(42)).is_none());
+        assert!(tokens.client_tokens().is_empty());
+    }
+
+    #[test]
+    fn test_cgi_timeout_frees_pipe_tokens() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut conn = HttpConnection::new(mio::net::TcpStream::from_std(stream), Vec::new());
+
+        let mut tokens = TokenSlab::new();
+        let client = tokens.vacant_token();
+        let (out_stream, _script_out) = UnixStream::pair().unwrap();
+        let (in_stream, _script_in) = UnixStream::pair().unwrap();
+        conn.cgi_out_token = Some(tokens.insert(Slot::Pipe(client)));
+        conn.cgi_in_token = Some(tokens.insert(Slot::Pipe(client)));
+        conn.action = ActiveAction::Cgi {
+            out_stream: mio::net::UnixStream::from_std(out_stream),
+            in_stream: Some(mio::net::UnixStream::from_std(in_stream)),
+            child: Command::new("sleep").arg("5").spawn().unwrap(),
+            parse_state: CgiParsingState::ReadHeaders,
+            header_buf: Vec::new(),
+            start_time: Instant::now(),
+            head_only: false,
+            output_done: None,
+        };
+
+        let mut purgatory = Vec::new();
+        force_cgi_timeout(&mut conn, &mut tokens, &mut purgatory);
+        assert!(tokens.is_empty());
+        assert!(conn.cgi_in_token.is_none() && conn.cgi_out_token.is_none());
+        for mut child in purgatory {
+            let _ = child.wait();
+        }
+    }
+}
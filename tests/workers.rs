@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod workers {
+    use server_proxy::config::{AppConfig, RouteConfig, ServerConfig};
+    use server_proxy::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    fn get(request: &str) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:8140").unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(700)))
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_workers_share_listeners_and_sessions() {
+        let root = "./tmp_workers_test_8140";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(format!("{}/index.html", root), "hello").unwrap();
+
+        let mut config = AppConfig {
+            workers: 4,
+            ..Default::default()
+        };
+        config.servers.push(ServerConfig {
+            server_name: "localhost".to_string(),
+            ports: vec![8140],
+            root: root.to_string(),
+            routes: vec![RouteConfig {
+                path: "/".to_string(),
+                root: root.to_string(),
+                ..Default::default()
+            }],
+            default_server: true,
+            ..Default::default()
+        });
+        thread::spawn(move || Server::run_workers(config).unwrap());
+        thread::sleep(Duration::from_millis(300));
+
+        let first = get("GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(first.starts_with("HTTP/1.1 200"));
+        let cookie = first
+            .lines()
+            .find_map(|l| l.strip_prefix("Set-Cookie: "))
+            .and_then(|c| c.split(';').next())
+            .unwrap()
+            .to_string();
+
+        // New connections land on any worker; all of them know the session
+        let handles: Vec<_> = (0..32)
+            .map(|_| {
+                let cookie = cookie.clone();
+                thread::spawn(move || {
+                    get(&format!(
+                        "GET /index.html HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nConnection: close\r\n\r\n",
+                        cookie
+                    ))
+                })
+            })
+            .collect();
+        for handle in handles {
+            let response = handle.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.ends_with("hello"));
+            assert!(!response.contains("Set-Cookie"), "session lost: {}", response);
+        }
+
+        // Sessions created at the same moment on different workers stay apart
+        let handles: Vec<_> = (0..32)
+            .map(|_| {
+                thread::spawn(|| {
+                    get("GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                })
+            })
+            .collect();
+        let mut ids: Vec<String> = handles
+            .into_iter()
+            .map(|handle| {
+                let response = handle.join().unwrap();
+                let cookie = response
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Set-Cookie: session_id="))
+                    .unwrap();
+                cookie.split(';').next().unwrap().to_string()
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 32);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}